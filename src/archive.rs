@@ -0,0 +1,205 @@
+//! Reads the members of `.zip` and `.tar` archives without extracting them to disk.
+//! Members are indexed under virtual paths like `notes.zip!/docs/readme.md`.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Separates the archive from the member in virtual paths
+const MEMBER_SEPARATOR: &str = "!/";
+
+/// Members larger than this are skipped rather than read into memory
+const MAX_MEMBER_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Whether `path` is an archive whose members get indexed.
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    match extension.as_str() {
+        "zip" => Some(ArchiveKind::Zip),
+        "tar" => Some(ArchiveKind::Tar),
+        _ => None,
+    }
+}
+
+/// Virtual path of `member` inside `archive_path`.
+pub fn member_path(archive_path: &Path, member: &str) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(MEMBER_SEPARATOR);
+    path.push(member);
+    PathBuf::from(path)
+}
+
+/// Splits a virtual path made by `member_path` into the archive and the member name.
+/// Returns `None` for ordinary paths.
+pub fn split_member_path(path: &Path) -> Option<(&Path, &str)> {
+    let path = path.to_str()?;
+    let mut search_from = 0;
+    // An archive may sit in a directory whose name contains the separator, so look
+    // for the first occurrence that follows an archive name
+    while let Some(offset) = path[search_from..].find(MEMBER_SEPARATOR) {
+        let at = search_from + offset;
+        let archive_path = Path::new(&path[..at]);
+        if is_archive(archive_path) {
+            return Some((archive_path, &path[at + MEMBER_SEPARATOR.len()..]));
+        }
+        search_from = at + MEMBER_SEPARATOR.len();
+    }
+    None
+}
+
+/// The file on disk that holds `path`: the archive for archive members, `path` otherwise.
+pub fn file_on_disk(path: &Path) -> &Path {
+    split_member_path(path).map(|(archive_path, _)| archive_path).unwrap_or(path)
+}
+
+/// Calls `visit` with the name and content of every regular member of `archive_path`
+/// for which `wanted` returns `true`, skipping members over `MAX_MEMBER_SIZE`.
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn for_each_member(
+    archive_path: &Path,
+    wanted: impl Fn(&str) -> bool,
+    mut visit: impl FnMut(&str, Vec<u8>),
+) -> Result<(), ()> {
+    let file = File::open(archive_path).map_err(|err| {
+        error!("ERROR: could not open archive {archive_path}: {err}", archive_path = archive_path.display());
+    })?;
+    let report = |err: &dyn std::fmt::Display| {
+        error!("ERROR: could not read archive {archive_path}: {err}", archive_path = archive_path.display());
+    };
+
+    match archive_kind(archive_path) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(file).map_err(|err| report(&err))?;
+            for index in 0..zip.len() {
+                let entry = zip.by_index(index).map_err(|err| report(&err))?;
+                let name = entry.name().to_string();
+                if entry.is_dir() || !wanted(&name) {
+                    continue;
+                }
+                if let Some(content) = read_member_content(entry, &name, archive_path) {
+                    visit(&name, content);
+                }
+            }
+        }
+        Some(ArchiveKind::Tar) => {
+            let mut tar = tar::Archive::new(file);
+            for entry in tar.entries().map_err(|err| report(&err))? {
+                let entry = entry.map_err(|err| report(&err))?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let Some(name) = tar_member_name(&entry) else { continue };
+                if !wanted(&name) {
+                    continue;
+                }
+                if let Some(content) = read_member_content(entry, &name, archive_path) {
+                    visit(&name, content);
+                }
+            }
+        }
+        None => return Err(()),
+    }
+    Ok(())
+}
+
+/// Name of a tar member with `/` separators and without a leading `./`.
+fn tar_member_name<R: Read>(entry: &tar::Entry<R>) -> Option<String> {
+    let path = entry.path().ok()?;
+    let name = path.to_str()?.trim_start_matches("./").to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Reads a member up to `MAX_MEMBER_SIZE`; `None` if it's larger or can't be read.
+fn read_member_content(entry: impl Read, name: &str, archive_path: &Path) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    // Read one byte past the limit to tell a member that is too large apart,
+    // without trusting the size stored in the archive
+    match entry.take(MAX_MEMBER_SIZE + 1).read_to_end(&mut content) {
+        Ok(_) if content.len() as u64 > MAX_MEMBER_SIZE => {
+            info!("Skipping {}: larger than {} MB", member_path(archive_path, name).display(), MAX_MEMBER_SIZE / (1024 * 1024));
+            None
+        }
+        Ok(_) => Some(content),
+        Err(err) => {
+            error!("ERROR: could not read {}: {err}", member_path(archive_path, name).display());
+            None
+        }
+    }
+}
+
+/// Reads the member a virtual path made by `member_path` points to.
+pub fn read_member(path: &Path) -> io::Result<Vec<u8>> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, format!("no archive member {}", path.display()));
+    let (archive_path, member) = split_member_path(path).ok_or_else(not_found)?;
+    let mut found = None;
+    for_each_member(archive_path, |name| name == member, |_, content| {
+        found.get_or_insert(content);
+    }).map_err(|()| io::Error::new(io::ErrorKind::InvalidData, format!("could not read archive {}", archive_path.display())))?;
+    found.ok_or_else(not_found)
+}
+
+/// Extracts the text of a member named `member` with the parser for its extension.
+/// Text formats are decoded in memory; the rest are parsed from a scratch copy.
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn parse_member(archive_path: &Path, member: &str, content: &[u8]) -> Result<String, ()> {
+    let member_path = member_path(archive_path, member);
+    let extension = Path::new(member).extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("pdf" | "xlsx" | "xml" | "xhtml") => {
+            let scratch = scratch_copy(member, content).map_err(|err| {
+                error!("ERROR: could not copy {member_path} for parsing: {err}", member_path = member_path.display());
+            })?;
+            let parsed = crate::parse_entire_file_by_extension(&scratch);
+            if let Some(dir) = scratch.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+            parsed
+        }
+        extension => {
+            let text = String::from_utf8(content.to_vec()).map_err(|err| {
+                error!("ERROR: could not read {member_path} as UTF-8: {err}", member_path = member_path.display());
+            })?;
+            Ok(if extension == Some("tex") { crate::latex::extract_text(&text) } else { text })
+        }
+    }
+}
+
+/// Extracts the text of the member a virtual path made by `member_path` points to.
+pub fn parse_member_path(path: &Path) -> io::Result<String> {
+    let (archive_path, member) = split_member_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no archive member {}", path.display())))?;
+    let content = read_member(path)?;
+    parse_member(archive_path, member, &content)
+        .map_err(|()| io::Error::new(io::ErrorKind::InvalidData, "could not extract text"))
+}
+
+/// Writes the member a virtual path points to into a temporary directory, for opening it
+/// in an editor. Returns where it was written.
+pub fn extract_to_temp(path: &Path) -> io::Result<PathBuf> {
+    let (_, member) = split_member_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no archive member {}", path.display())))?;
+    scratch_copy(member, &read_member(path)?)
+}
+
+/// Writes `content` to a new scratch directory under the file name of `member`, so parsers
+/// and editors still see its extension.
+fn scratch_copy(member: &str, content: &[u8]) -> io::Result<PathBuf> {
+    let file_name = Path::new(member).file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("archive member {member} has no file name")))?;
+    let dir = env::temp_dir().join(format!("khoj-archive-{}-{}", std::process::id(), crate::scratch_dir_suffix()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(file_name);
+    fs::write(&path, content)?;
+    Ok(path)
+}
@@ -1,29 +1,80 @@
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 use khoj::model::Model;
-use khoj::add_folder_to_model;
+use khoj::{add_folder_to_model, IndexOptions};
+
+/// Corpus indexed when no directory is given
+const DEFAULT_TARGET_DIR: &str = "annotatedCentralActs";
+
+/// Queries benchmarked when no terms are given; they suit the default corpus
+const DEFAULT_SEARCH_TERMS: &[&str] = &[
+    "act", "section", "government", "penalty", "offence",
+    "rule", "order", "court", "judge", "police"
+];
+
+/// Reads the search terms from `source`: a file with one query per line (blank lines and
+/// lines starting with `#` are skipped), or else a comma-separated list of queries.
+fn load_search_terms(source: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(source);
+    let terms: Vec<String> = if path.is_file() {
+        std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read search terms from {}: {}", path.display(), err))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    } else {
+        source.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect()
+    };
+    if terms.is_empty() {
+        return Err(format!("no search terms found in {:?}", source));
+    }
+    Ok(terms)
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        eprintln!("Usage: {} [DIR] [TERMS]", args[0]);
+        eprintln!("    DIR      folder to index (default: {})", DEFAULT_TARGET_DIR);
+        eprintln!("    TERMS    file with one query per line, or comma-separated queries");
+        return;
+    }
+
     println!("Starting benchmarks...");
 
     // 1. Setup paths
     let current_dir = std::env::current_dir().expect("Failed to get current directory");
-    let target_dir = current_dir.join("annotatedCentralActs");
+    let target_dir: PathBuf = current_dir.join(args.get(1).map(String::as_str).unwrap_or(DEFAULT_TARGET_DIR));
     
     if !target_dir.exists() {
-        eprintln!("Error: Directory {:?} not found. Please run this from the project root.", target_dir);
+        eprintln!("Error: Directory {:?} not found. Pass a directory or run this from the project root.", target_dir);
         return;
     }
 
+    let search_terms: Vec<String> = match args.get(2) {
+        Some(source) => match load_search_terms(source) {
+            Ok(terms) => terms,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                return;
+            }
+        },
+        None => DEFAULT_SEARCH_TERMS.iter().map(|t| t.to_string()).collect(),
+    };
+
     // 2. Indexing Benchmark
     println!("\n=== Indexing Benchmark ===");
     let model = Arc::new(Mutex::new(Model::default()));
     let start_time = Instant::now();
-    let mut processed_files = 0;
+    let processed = AtomicUsize::new(0);
     
-    match add_folder_to_model(&target_dir, Arc::clone(&model), &mut processed_files) {
+    match add_folder_to_model(&target_dir, Arc::clone(&model), &IndexOptions::default(), &processed) {
         Ok(_) => {
+            let processed_files = processed.load(Ordering::SeqCst);
             let duration = start_time.elapsed();
             println!("Indexed {} files in {:.2?}", processed_files, duration);
             if processed_files > 0 {
@@ -40,10 +91,7 @@ fn main() {
 
     // 3. Search Benchmark
     println!("\n=== Search Benchmark ===");
-    let search_terms = vec![
-        "act", "section", "government", "penalty", "offence", 
-        "rule", "order", "court", "judge", "police"
-    ];
+    println!("Queries: {}", search_terms.join(", "));
 
     let model_guard = model.lock().unwrap();
     let warmup_queries = 10;
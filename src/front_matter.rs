@@ -0,0 +1,96 @@
+//! YAML front matter of markdown notes, the `---` delimited block of `key: value` lines at
+//! the very top. Only the flat subset notes use is understood: scalars, `[a, b]` lists and
+//! `- item` lists below a key.
+
+/// Fields whose values are also counted as headings, so notes titled or tagged with a query
+/// word rank higher (see `RankingParams::heading_boost`)
+pub const WEIGHTED_FIELDS: &[&str] = &["title", "tags", "tag", "keywords"];
+
+/// Fields listing the tags searched by `tag:`
+const TAG_FIELDS: &[&str] = &["tags", "tag", "keywords"];
+
+pub struct FrontMatter {
+    /// Field names and their values, in the order they appear
+    pub fields: Vec<(String, Vec<String>)>,
+    /// The content with the front matter replaced by its bare values, on the lines they were
+    /// on, so line numbers still match the file
+    pub text: String,
+}
+
+impl FrontMatter {
+    /// Values of the fields in `WEIGHTED_FIELDS`.
+    pub fn weighted_values(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter()
+            .filter(|(key, _)| WEIGHTED_FIELDS.contains(&key.as_str()))
+            .flat_map(|(_, values)| values.iter().map(String::as_str))
+    }
+
+    /// Lowercased tags, for `tag:` filters.
+    pub fn tags(&self) -> Vec<String> {
+        self.fields.iter()
+            .filter(|(key, _)| TAG_FIELDS.contains(&key.as_str()))
+            .flat_map(|(_, values)| values.iter().map(|value| value.to_lowercase()))
+            .collect()
+    }
+}
+
+/// Parses the front matter at the start of `content`, if it has any.
+pub fn parse(content: &str) -> Option<FrontMatter> {
+    let mut lines = content.split_inclusive('\n');
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
+    let mut text = String::from("\n");
+    let mut closed = false;
+    for line in lines.by_ref() {
+        let trimmed = line.trim();
+        if trimmed == "---" || trimmed == "..." {
+            text.push('\n');
+            closed = true;
+            break;
+        }
+        let values = if let Some(item) = trimmed.strip_prefix("- ") {
+            // An item of the list below the last key
+            let item = unquote(item);
+            let (_, values) = fields.last_mut()?;
+            values.push(item.to_string());
+            vec![item]
+        } else if trimmed.is_empty() || trimmed.starts_with('#') {
+            Vec::new()
+        } else if line.starts_with([' ', '\t']) {
+            // A line of a multi-line value, like those of `description: >`
+            let (_, values) = fields.last_mut()?;
+            values.push(trimmed.to_string());
+            vec![trimmed]
+        } else {
+            let (key, value) = trimmed.split_once(':')?;
+            let values = scalar_or_list(value.trim().trim_start_matches(['|', '>']));
+            fields.push((key.trim().to_lowercase(), values.iter().map(|v| v.to_string()).collect()));
+            values
+        };
+        text.push_str(&values.join(" "));
+        text.push('\n');
+    }
+    if !closed {
+        return None;
+    }
+    text.extend(lines);
+    Some(FrontMatter { fields, text })
+}
+
+/// The values of `value`: the items of a `[a, b]` list, or the value itself.
+fn scalar_or_list(value: &str) -> Vec<&str> {
+    let items = match value.strip_prefix('[').and_then(|list| list.strip_suffix(']')) {
+        Some(list) => list.split(',').collect(),
+        None => vec![value],
+    };
+    items.into_iter().map(unquote).filter(|item| !item.is_empty()).collect()
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    ['"', '\''].iter()
+        .find_map(|&quote| value.strip_prefix(quote).and_then(|v| v.strip_suffix(quote)))
+        .unwrap_or(value)
+}
@@ -1,37 +1,139 @@
 //! Loads .khojignore patterns and provides a matcher for skipping ignored paths.
+//! Optionally also honors `.gitignore` files found under the indexed root.
 
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
 use std::path::Path;
-use std::sync::OnceLock;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
 
-/// Global ignore matcher (built once per run).
-static IGNORER: OnceLock<Gitignore> = OnceLock::new();
+/// Global ignore matcher. Built once by `init`; `reload` swaps in freshly read rules.
+static IGNORER: OnceLock<RwLock<Ignorer>> = OnceLock::new();
 
-/// Initializes the ignorer from `.khojignore` at `root`.
+struct Ignorer {
+    /// Root the rules were loaded from, kept so `reload` can read them again.
+    root: PathBuf,
+    use_gitignore: bool,
+    /// Rules from `.khojignore` at the root; these always win.
+    khojignore: Gitignore,
+    /// Rules from `.gitignore` files, ordered deepest directory first.
+    gitignores: Vec<Gitignore>,
+}
+
+/// Initializes the ignorer from `.khojignore` at `root`, plus any `.gitignore`
+/// files when `use_gitignore` is set.
 /// Call this once at startup. Safe to call multiple times; only the first call builds.
-pub fn init(root: &Path) {
-    IGNORER.get_or_init(|| build_ignorer(root));
+pub fn init(root: &Path, use_gitignore: bool) {
+    IGNORER.get_or_init(|| RwLock::new(Ignorer::load(root, use_gitignore)));
+}
+
+/// Re-reads `.khojignore` (and `.gitignore` files, if enabled) from the root given to `init`,
+/// so edits made while running take effect. Does nothing if `init` was never called.
+pub fn reload() {
+    let Some(ignorer) = IGNORER.get() else { return };
+    let (root, use_gitignore) = {
+        let ignorer = ignorer.read().unwrap();
+        (ignorer.root.clone(), ignorer.use_gitignore)
+    };
+    // Build outside the lock so matching isn't blocked while the tree is walked
+    let fresh = Ignorer::load(&root, use_gitignore);
+    *ignorer.write().unwrap() = fresh;
+}
+
+impl Ignorer {
+    fn load(root: &Path, use_gitignore: bool) -> Self {
+        Ignorer {
+            root: root.to_path_buf(),
+            use_gitignore,
+            khojignore: build_ignorer(root),
+            gitignores: if use_gitignore { build_gitignores(root) } else { Vec::new() },
+        }
+    }
 }
 
-fn build_ignorer(root: &Path) -> Gitignore {
+/// Builds the matcher for the `.khojignore` at `root`. Without one (or if it can't be
+/// built) the matcher is empty and ignores nothing.
+pub fn build_ignorer(root: &Path) -> Gitignore {
     let khojignore = root.join(".khojignore");
     let mut builder = GitignoreBuilder::new(root);
     if khojignore.is_file() {
         if let Some(err) = builder.add(&khojignore) {
-            eprintln!("WARN: could not parse .khojignore: {err}");
+            warn!("WARN: could not parse .khojignore: {err}");
         }
     }
     builder.build().unwrap_or_else(|e| {
-        eprintln!("WARN: failed to build ignore rules: {e}");
+        warn!("WARN: failed to build ignore rules: {e}");
         Gitignore::empty()
     })
 }
 
-/// Returns `true` if `path` should be ignored according to `.khojignore`.
+/// Collects the root `.gitignore` and any nested ones. Each file gets its own
+/// matcher rooted at its directory, so nested patterns stay relative to where they live.
+/// Directories already excluded by a parent `.gitignore` are not searched.
+fn build_gitignores(root: &Path) -> Vec<Gitignore> {
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+
+    let mut gitignores: Vec<Gitignore> = walker
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() == ".gitignore" && e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|e| {
+            let (gitignore, err) = Gitignore::new(e.path());
+            if let Some(err) = err {
+                warn!("WARN: could not parse {path}: {err}", path = e.path().display());
+            }
+            (!gitignore.is_empty()).then_some(gitignore)
+        })
+        .collect();
+
+    // Deeper .gitignore files override shallower ones, so check them first
+    gitignores.sort_by_key(|g| std::cmp::Reverse(g.path().components().count()));
+    gitignores
+}
+
+/// Matches `path` (or any of its parent directories) against `ig`, treating
+/// paths outside of the matcher's root as unmatched.
+fn matched(ig: &Gitignore, path: &Path, is_dir: bool) -> Match<()> {
+    // The ignore crate drops a leading `./` from roots, so compare without it
+    let path = path.strip_prefix(".").unwrap_or(path);
+    let root = ig.path().strip_prefix(".").unwrap_or(ig.path());
+    if path.is_absolute() != root.is_absolute() || !path.starts_with(root) {
+        return Match::None;
+    }
+    match ig.matched_path_or_any_parents(path, is_dir) {
+        Match::None => Match::None,
+        Match::Ignore(_) => Match::Ignore(()),
+        Match::Whitelist(_) => Match::Whitelist(()),
+    }
+}
+
+/// Returns `true` if `ig` ignores `path` or one of its parent directories. A `!` negation
+/// that re-includes the path counts as not ignored. `is_dir` should indicate whether the
+/// path is a directory, since patterns ending in `/` only match directories.
+pub fn matches(ig: &Gitignore, path: &Path, is_dir: bool) -> bool {
+    matched(ig, path, is_dir).is_ignore()
+}
+
+/// Returns `true` if `path` should be ignored according to `.khojignore`
+/// (and `.gitignore`, if enabled). A `.khojignore` match, including a `!`
+/// negation, takes precedence over any `.gitignore` rule.
 /// `is_dir` should indicate whether the path is a directory.
 pub fn is_ignored(path: &Path, is_dir: bool) -> bool {
-    IGNORER
-        .get()
-        .map(|ig| ig.matched(path, is_dir).is_ignore())
+    let Some(ignorer) = IGNORER.get() else { return false };
+    let ignorer = ignorer.read().unwrap();
+    match matched(&ignorer.khojignore, path, is_dir) {
+        Match::Ignore(_) => return true,
+        Match::Whitelist(_) => return false,
+        Match::None => {}
+    }
+    ignorer
+        .gitignores
+        .iter()
+        .map(|g| matched(g, path, is_dir))
+        .find(|m| !m.is_none())
+        .map(|m| m.is_ignore())
         .unwrap_or(false)
 }
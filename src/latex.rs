@@ -0,0 +1,162 @@
+//! Extracts the prose of LaTeX sources, so `.tex` files are indexed by their text rather
+//! than by command names, math and the words split apart by braces.
+
+/// Environments whose whole body is math and is left out
+const MATH_ENVIRONMENTS: &[&str] = &[
+    "equation", "equation*", "align", "align*", "alignat", "alignat*", "gather", "gather*",
+    "multline", "multline*", "flalign", "flalign*", "eqnarray", "eqnarray*", "math", "displaymath",
+];
+
+/// Commands whose arguments are labels, keys, file names or definitions rather than prose
+const NON_PROSE_COMMANDS: &[&str] = &[
+    "cite", "citep", "citet", "nocite", "ref", "eqref", "pageref", "autoref", "cref", "Cref", "label",
+    "usepackage", "documentclass", "input", "include", "includegraphics", "bibliography",
+    "bibliographystyle", "url", "newcommand", "renewcommand", "newenvironment", "setlength",
+    "hspace", "vspace", "color",
+];
+
+/// Returns the text of the LaTeX `source`: command names, their options, comments and math
+/// are dropped, while the arguments of commands like `\section{...}` or `\textbf{...}` are kept.
+/// Line breaks are kept too, so line numbers still match the source.
+pub fn extract_text(source: &str) -> String {
+    let source: Vec<char> = source.chars().collect();
+    let mut text = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        match source[i] {
+            '%' => i = skip_until(&source, i, &['\n'], &mut text),
+            '$' => {
+                // `$$...$$` or `$...$`
+                let delimiter: &[char] = if source.get(i + 1) == Some(&'$') { &['$', '$'] } else { &['$'] };
+                text.push(' ');
+                i = skip_past(&source, i + delimiter.len(), delimiter, &mut text);
+            }
+            '\\' => i = command(&source, i, &mut text),
+            '{' | '}' => i += 1,
+            '~' | '&' => {
+                text.push(' ');
+                i += 1;
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    text
+}
+
+/// Handles the command starting with the backslash at `start`. Returns where scanning resumes.
+fn command(source: &[char], start: usize, text: &mut String) -> usize {
+    let name_start = start + 1;
+    let Some(&first) = source.get(name_start) else { return name_start };
+    if !first.is_ascii_alphabetic() {
+        return match first {
+            // Display and inline math
+            '[' => skip_past(source, name_start + 1, &['\\', ']'], text),
+            '(' => skip_past(source, name_start + 1, &['\\', ')'], text),
+            // Line break
+            '\\' => {
+                text.push('\n');
+                name_start + 1
+            }
+            // Escaped special characters stand for themselves
+            '%' | '&' | '$' | '#' | '_' | '{' | '}' => {
+                text.push(first);
+                name_start + 1
+            }
+            // Accents like `\'e` apply to the letter that follows; spacing commands
+            _ => {
+                if matches!(first, ',' | ';' | ':' | ' ' | '!') {
+                    text.push(' ');
+                }
+                name_start + 1
+            }
+        };
+    }
+
+    let mut end = name_start;
+    while end < source.len() && source[end].is_ascii_alphabetic() {
+        end += 1;
+    }
+    let name: String = source[name_start..end].iter().collect();
+    if source.get(end) == Some(&'*') {
+        end += 1;
+    }
+
+    match name.as_str() {
+        "begin" | "end" => {
+            let (environment, after) = group(source, end);
+            let environment = environment.unwrap_or_default();
+            if name == "begin" && MATH_ENVIRONMENTS.contains(&environment.as_str()) {
+                let closing: Vec<char> = format!("\\end{{{environment}}}").chars().collect();
+                return skip_past(source, after, &closing, text);
+            }
+            skip_options(source, after)
+        }
+        name if NON_PROSE_COMMANDS.contains(&name) => {
+            let mut at = skip_options(source, end);
+            while let (Some(_), after) = group(source, at) {
+                at = skip_options(source, after);
+            }
+            at
+        }
+        _ => skip_options(source, end),
+    }
+}
+
+/// Skips any `[...]` options starting at `at`.
+fn skip_options(source: &[char], mut at: usize) -> usize {
+    while source.get(at) == Some(&'[') {
+        match source[at..].iter().position(|&c| c == ']') {
+            Some(offset) => at += offset + 1,
+            None => return at,
+        }
+    }
+    at
+}
+
+/// Reads the balanced `{...}` group starting at `at`, if there is one. Returns its content and
+/// where it ends.
+fn group(source: &[char], at: usize) -> (Option<String>, usize) {
+    if source.get(at) != Some(&'{') {
+        return (None, at);
+    }
+    let mut depth = 0;
+    for (i, &c) in source.iter().enumerate().skip(at) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (Some(source[at + 1..i].iter().collect()), i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    (Some(source[at + 1..].iter().collect()), source.len())
+}
+
+/// Skips to the first of `stops` at or after `at`, keeping the line breaks skipped over.
+/// Returns the position of the stop, so it is handled by the caller.
+fn skip_until(source: &[char], at: usize, stops: &[char], text: &mut String) -> usize {
+    let end = source[at..].iter().position(|c| stops.contains(c)).map_or(source.len(), |offset| at + offset);
+    keep_line_breaks(&source[at..end], text);
+    end
+}
+
+/// Skips past the next occurrence of `closing` at or after `at`, keeping the line breaks
+/// skipped over. An unclosed one runs to the end.
+fn skip_past(source: &[char], at: usize, closing: &[char], text: &mut String) -> usize {
+    let end = source.get(at..).unwrap_or_default()
+        .windows(closing.len())
+        .position(|window| window == closing)
+        .map_or(source.len(), |offset| at + offset + closing.len());
+    keep_line_breaks(source.get(at..end).unwrap_or_default(), text);
+    end
+}
+
+fn keep_line_breaks(skipped: &[char], text: &mut String) {
+    text.extend(skipped.iter().filter(|&&c| c == '\n'));
+}
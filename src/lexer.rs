@@ -1,10 +1,161 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on memoized stems per thread; the cache starts over once it fills up
+const STEM_CACHE_CAPACITY: usize = 100_000;
+
+thread_local! {
+    /// Surface form -> stem. Thread-local since indexing tokenizes files in parallel.
+    static STEM_CACHE: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Stems the lowercase `term` with the English snowball stemmer, reusing earlier results for
+/// repeated words.
+pub(crate) fn stem(term: String) -> String {
+    STEM_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(stemmed) = cache.get(&term) {
+            return stemmed.clone();
+        }
+        let mut env = crate::snowball::SnowballEnv::create(&term);
+        crate::snowball::algorithms::english_stemmer::stem(&mut env);
+        let stemmed = env.get_current().to_string();
+        if cache.len() >= STEM_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(term, stemmed.clone());
+        stemmed
+    })
+}
+
+/// Whether `path` is source code by its extension, as opposed to prose, markup or data.
+pub fn is_code_file(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|e| e.to_str()) else { return false };
+    matches!(extension.to_ascii_lowercase().as_str(),
+        "rs" | "js" | "jsx" | "ts" | "tsx"
+        | "py" | "go" | "java" | "kt" | "kts"
+        | "c" | "h" | "hpp" | "hh" | "cpp" | "cc" | "cxx"
+        | "cs" | "rb" | "php"
+        | "css" | "scss" | "less"
+        | "sh" | "bash" | "zsh" | "fish"
+        | "pl" | "sql" | "gradle" | "r"
+        | "vue" | "svelte" | "dart" | "erl" | "ex" | "exs" | "lua" | "nim")
+}
+
+/// How text is split into terms. Saved with the index, since queries only match if they
+/// are tokenized the same way as the indexed documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenizerConfig {
+    /// Reduce words to their English stem, so "penalties" matches "penalty"
+    pub stem: bool,
+    /// Tokens shorter than this many characters are dropped, like stray letters and punctuation
+    #[serde(default = "TokenizerConfig::legacy_min_token_len")]
+    pub min_token_len: usize,
+    /// Tokens longer than this many characters are dropped, like base64 blobs and minified code
+    #[serde(default = "TokenizerConfig::legacy_max_token_len")]
+    pub max_token_len: usize,
+    /// Also index the words of identifiers like `getUserName` and `get_user_name`
+    /// next to the identifier itself, so "user name" finds them
+    #[serde(default)]
+    pub split_identifiers: bool,
+    /// Keep numbers joined by `.` or `-`, like versions (`v1.2.3`), dates (`2023-01-15`) and
+    /// IP addresses, as one term next to their parts, so they can be searched as written
+    #[serde(default)]
+    pub dotted_numbers: bool,
+    /// Also stem the words of source code files (see `is_code_file`). Off, identifiers like
+    /// `running` and `runs` stay apart in code while prose is still stemmed.
+    #[serde(default = "TokenizerConfig::legacy_stem_code")]
+    pub stem_code: bool,
+}
+
+pub const DEFAULT_MIN_TOKEN_LEN: usize = 2;
+pub const DEFAULT_MAX_TOKEN_LEN: usize = 40;
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self { stem: true, min_token_len: DEFAULT_MIN_TOKEN_LEN, max_token_len: DEFAULT_MAX_TOKEN_LEN, split_identifiers: false, dotted_numbers: false, stem_code: true }
+    }
+}
+
+impl TokenizerConfig {
+    /// What indexes saved before a setting existed were built with: always stemmed,
+    /// keeping tokens of any length.
+    pub fn legacy() -> Self {
+        Self {
+            stem: true,
+            min_token_len: Self::legacy_min_token_len(),
+            max_token_len: Self::legacy_max_token_len(),
+            split_identifiers: false,
+            dotted_numbers: false,
+            stem_code: true,
+        }
+    }
+
+    /// How the file at `path` is tokenized: without stemming if it's source code and
+    /// `stem_code` is off, like this otherwise. Queries are tokenized the same way to match it.
+    pub fn for_path(self, path: &Path) -> Self {
+        if self.stem && !self.stem_code && is_code_file(path) {
+            Self { stem: false, ..self }
+        } else {
+            self
+        }
+    }
+
+    /// Whether some files are tokenized differently from others (see `for_path`).
+    pub fn varies_by_path(&self) -> bool {
+        self.stem && !self.stem_code
+    }
+
+    fn legacy_stem_code() -> bool {
+        true
+    }
+
+    fn legacy_min_token_len() -> usize {
+        1
+    }
+
+    fn legacy_max_token_len() -> usize {
+        usize::MAX
+    }
+
+    fn keeps_len(&self, len: usize) -> bool {
+        (self.min_token_len..=self.max_token_len).contains(&len)
+    }
+}
+
+impl fmt::Display for TokenizerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.stem { "stemming" } else { "--no-stem" })?;
+        match (self.min_token_len, self.max_token_len) {
+            (min, usize::MAX) => write!(f, ", tokens of at least {min} characters")?,
+            (min, max) => write!(f, ", tokens of {min}-{max} characters")?,
+        }
+        if self.split_identifiers {
+            f.write_str(", --split-identifiers")?;
+        }
+        if self.dotted_numbers {
+            f.write_str(", --dotted-numbers")?;
+        }
+        if self.varies_by_path() {
+            f.write_str(", --no-stem-code")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Lexer<'a> {
     content: &'a [char],
+    config: TokenizerConfig,
+    /// Words of the last identifier, in reverse, still to be returned after it
+    pending: Vec<String>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+    pub fn new(content: &'a [char], config: TokenizerConfig) -> Self {
+        Self { content, config, pending: Vec::new() }
     }
 
     fn trim_left(&mut self) {
@@ -27,26 +178,125 @@ impl<'a> Lexer<'a> {
         self.chop(n)
     }
 
-    pub fn next_token(&mut self) -> Option<String> {
-        self.trim_left();
-        if self.content.is_empty() {
-            return None
+    /// Chops an identifier: letters and digits, joined by `_` or `-` only when more follow.
+    fn chop_identifier(&mut self) -> &'a [char] {
+        let content = self.content;
+        let mut n = 0;
+        while n < content.len() {
+            let joined = matches!(content[n], '_' | '-') && content.get(n + 1).is_some_and(|x| x.is_alphanumeric());
+            if !content[n].is_alphanumeric() && !joined {
+                break;
+            }
+            n += 1;
+        }
+        self.chop(n)
+    }
+
+    /// Length of the dotted number at the start of the content, like `1.2.3` or `2023-01-15`,
+    /// counting a leading `v` of versions. `None` if it doesn't start with one.
+    fn dotted_number_len(&self) -> Option<usize> {
+        let content = self.content;
+        let start = usize::from(matches!(content[0], 'v' | 'V'));
+        let is_digit = |i: usize| content.get(i).is_some_and(|x| x.is_ascii_digit());
+        let mut n = start;
+        let mut separators = 0;
+        while is_digit(n) || (n > start && matches!(content.get(n), Some('.' | '-')) && is_digit(n + 1) && is_digit(n - 1)) {
+            if !is_digit(n) {
+                separators += 1;
+            }
+            n += 1;
         }
+        // A plain number, or a word like `v8`, is tokenized as usual
+        (separators > 0).then_some(n)
+    }
 
-        if self.content[0].is_numeric() {
-            return Some(self.chop_while(|x| x.is_numeric()).iter().collect());
+    /// The term a token is indexed as: words are lowercased and, if enabled, stemmed.
+    fn term(&self, token: &[char]) -> String {
+        if token[0].is_alphabetic() {
+            let term = token.iter().map(|x| x.to_ascii_lowercase()).collect::<String>();
+            return if self.config.stem { stem(term) } else { term };
         }
+        token.iter().collect()
+    }
 
-        if self.content[0].is_alphabetic() {
-            let term = self.chop_while(|x| x.is_alphanumeric()).iter().map(|x| x.to_ascii_lowercase()).collect::<String>();
-            let mut env = crate::snowball::SnowballEnv::create(&term);
-            crate::snowball::algorithms::english_stemmer::stem(&mut env);
-            let stemmed_term = env.get_current().to_string();
-            return Some(stemmed_term);
+    pub fn next_token(&mut self) -> Option<String> {
+        if let Some(part) = self.pending.pop() {
+            return Some(part);
         }
+        loop {
+            self.trim_left();
+            if self.content.is_empty() {
+                return None
+            }
 
-        return Some(self.chop(1).iter().collect());
+            let dotted = if self.config.dotted_numbers { self.dotted_number_len() } else { None };
+            let token = if let Some(n) = dotted {
+                // `v1.2.3` is the same version as `1.2.3`
+                let token = self.chop(n);
+                if token[0].is_ascii_digit() { token } else { &token[1..] }
+            } else if self.content[0].is_numeric() {
+                self.chop_while(|x| x.is_numeric())
+            } else if self.content[0].is_alphabetic() && self.config.split_identifiers {
+                self.chop_identifier()
+            } else if self.content[0].is_alphabetic() {
+                self.chop_while(|x| x.is_alphanumeric())
+            } else {
+                self.chop(1)
+            };
+
+            let parts = if dotted.is_some() {
+                token.split(|x| matches!(x, '.' | '-')).collect()
+            } else if self.config.split_identifiers {
+                identifier_parts(token)
+            } else {
+                Vec::new()
+            };
+            if parts.len() > 1 {
+                self.pending = parts.into_iter().rev()
+                    .filter(|part| self.config.keeps_len(part.len()))
+                    .map(|part| self.term(part))
+                    .collect();
+            }
+            // Filter on the length as written, before stemming shortens the word
+            if !self.config.keeps_len(token.len()) {
+                match self.pending.pop() {
+                    Some(part) => return Some(part),
+                    None => continue,
+                }
+            }
+            return Some(self.term(token));
+        }
+    }
+}
+
+/// Splits an identifier into its words: at `_` and `-`, where a lowercase letter or digit
+/// is followed by an uppercase one (`userName`), and before the last capital of an
+/// acronym (`HTTPServer`).
+fn identifier_parts(token: &[char]) -> Vec<&[char]> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for i in 0..token.len() {
+        if matches!(token[i], '_' | '-') {
+            if start < i {
+                parts.push(&token[start..i]);
+            }
+            start = i + 1;
+            continue;
+        }
+        if i == start || !token[i].is_uppercase() {
+            continue;
+        }
+        let previous = token[i - 1];
+        let ends_acronym = previous.is_uppercase() && token.get(i + 1).is_some_and(|x| x.is_lowercase());
+        if previous.is_lowercase() || previous.is_numeric() || ends_acronym {
+            parts.push(&token[start..i]);
+            start = i;
+        }
+    }
+    if start < token.len() {
+        parts.push(&token[start..]);
     }
+    parts
 }
 
 impl<'a> Iterator for Lexer<'a> {
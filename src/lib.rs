@@ -1,29 +1,42 @@
 use std::fs::{self, File};
-use std::path::{Path};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
 use xml::reader::{XmlEvent, EventReader};
 use xml::common::{Position, TextPosition};
 use std::env;
 use std::result::Result;
-use std::process::ExitCode;
 use std::str;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+#[macro_use]
+pub mod logging;
 pub mod model;
 use model::*;
 mod server;
 mod lexer;
 pub mod snowball;
+pub mod theme;
 pub mod ignore_rules;
+pub mod tui;
+pub mod archive;
+pub mod latex;
+pub mod front_matter;
 
 fn parse_entire_txt_file(file_path: &Path) -> Result<String, ()> {
     fs::read_to_string(file_path).map_err(|err| {
-        eprintln!("ERROR: coult not open file {file_path}: {err}", file_path = file_path.display());
+        error!("ERROR: coult not open file {file_path}: {err}", file_path = file_path.display());
     })
 }
 
-fn parse_entire_pdf_file(file_path: &Path) -> Result<String, ()> {
+fn parse_entire_tex_file(file_path: &Path) -> Result<String, ()> {
+    parse_entire_txt_file(file_path).map(|source| latex::extract_text(&source))
+}
+
+fn parse_entire_pdf_file(file_path: &Path, timeout: Option<Duration>) -> Result<String, ()> {
     use poppler::Document;
     use std::io::Read;
 
@@ -31,31 +44,192 @@ fn parse_entire_pdf_file(file_path: &Path) -> Result<String, ()> {
     File::open(file_path)
         .and_then(|mut file| file.read_to_end(&mut content))
         .map_err(|err| {
-            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+            error!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
         })?;
 
     let pdf = Document::from_data(&content, None).map_err(|err| {
-        eprintln!("ERROR: could not read file {file_path}: {err}",
+        error!("ERROR: could not read file {file_path}: {err}",
                   file_path = file_path.display());
     })?;
 
     let mut result = String::new();
+    let ocr_command = env::var("KHOJ_OCR_COMMAND").ok().filter(|c| !c.trim().is_empty());
 
     let n = pdf.n_pages();
     for i in 0..n {
         let page = pdf.page(i).expect(&format!("{i} is within the bounds of the range of the page"));
-        if let Some(content) = page.text() {
-            result.push_str(content.as_str());
+        let text = page.text().map(|content| content.as_str().to_string()).unwrap_or_default();
+        if !text.trim().is_empty() {
+            result.push_str(&text);
             result.push(' ');
+        } else if let Some(ocr_command) = &ocr_command {
+            // Scanned pages have no text layer; fall back to OCR if configured
+            if let Some(text) = ocr_pdf_page(file_path, i, ocr_command, timeout) {
+                result.push_str(&text);
+                result.push(' ');
+            }
         }
     }
 
     Ok(result)
 }
 
+/// Renders page `page_index` (0-based) of the PDF to a PNG with `pdftoppm` and runs
+/// `ocr_command` on it, returning whatever text it prints to stdout.
+/// `{}` in the command is replaced by the image path; without it the path is appended
+/// (e.g. `KHOJ_OCR_COMMAND="tesseract {} stdout"`). Both commands are killed after `timeout`.
+/// Returns `None` if any step fails.
+fn ocr_pdf_page(file_path: &Path, page_index: i32, ocr_command: &str, timeout: Option<Duration>) -> Option<String> {
+    use std::process::{Command, Stdio};
+
+    let work_dir = env::temp_dir().join(format!("khoj-ocr-{}-{}", std::process::id(), scratch_dir_suffix()));
+    fs::create_dir_all(&work_dir).ok()?;
+    let image_prefix = work_dir.join(format!("page-{page_index}"));
+    let image_path = image_prefix.with_extension("png");
+
+    let page = (page_index + 1).to_string();
+    let mut command = Command::new("pdftoppm");
+    command.args(["-f", &page, "-l", &page, "-r", "300", "-png", "-singlefile"])
+        .arg(file_path)
+        .arg(&image_prefix)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let text = match output_with_timeout(&mut command, timeout) {
+        Ok(Some((status, _))) if status.success() => run_text_command(ocr_command, &image_path, "OCR", timeout),
+        Ok(Some((status, _))) => {
+            warn!("WARN: pdftoppm failed on page {page} of {file_path} ({status})", file_path = file_path.display());
+            None
+        }
+        Ok(None) => {
+            warn!("WARN: gave up on OCR of page {page} of {file_path}: pdftoppm took longer than --parse-timeout", file_path = file_path.display());
+            None
+        }
+        Err(err) => {
+            warn!("WARN: could not run pdftoppm for OCR of {file_path}: {err}", file_path = file_path.display());
+            None
+        }
+    };
+
+    let _ = fs::remove_dir_all(&work_dir);
+    text.filter(|t| !t.trim().is_empty())
+}
+
+/// Renders the first page of the PDF to a PNG at most `size` pixels wide and high with
+/// `pdftoppm`, like `ocr_pdf_page` does for OCR, killing it after `timeout`. Returns `None`
+/// if that fails.
+pub(crate) fn render_pdf_thumbnail(file_path: &Path, size: u32, timeout: Option<Duration>) -> Option<Vec<u8>> {
+    use std::process::{Command, Stdio};
+
+    let work_dir = env::temp_dir().join(format!("khoj-thumb-{}-{}", std::process::id(), scratch_dir_suffix()));
+    fs::create_dir_all(&work_dir).ok()?;
+    let image_prefix = work_dir.join("thumb");
+
+    let mut command = Command::new("pdftoppm");
+    command.args(["-f", "1", "-l", "1", "-scale-to", &size.to_string(), "-png", "-singlefile"])
+        .arg(file_path)
+        .arg(&image_prefix)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let image = match output_with_timeout(&mut command, timeout) {
+        Ok(Some((status, _))) if status.success() => fs::read(image_prefix.with_extension("png")).ok(),
+        Ok(Some((status, _))) => {
+            warn!("WARN: pdftoppm failed on {file_path} ({status})", file_path = file_path.display());
+            None
+        }
+        Ok(None) => {
+            warn!("WARN: gave up on a thumbnail of {file_path}: pdftoppm took longer than --parse-timeout", file_path = file_path.display());
+            None
+        }
+        Err(err) => {
+            warn!("WARN: could not run pdftoppm for a thumbnail of {file_path}: {err}", file_path = file_path.display());
+            None
+        }
+    };
+
+    let _ = fs::remove_dir_all(&work_dir);
+    image
+}
+
+/// Runs `command` like `Command::output`, but kills it once it has run longer than `timeout`
+/// (`None` = wait forever), returning `Ok(None)`. Only stdout is captured.
+fn output_with_timeout(command: &mut std::process::Command, timeout: Option<Duration>) -> std::io::Result<Option<(std::process::ExitStatus, Vec<u8>)>> {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    // Read on another thread, so a command filling the pipe isn't stuck while we wait for it
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        output
+    });
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    Ok(Some((status, reader.join().unwrap_or_default())))
+}
+
+/// Runs a user-configured `command` on `file_path` and returns what it prints to stdout,
+/// killing it after `timeout`. `{}` in the command is replaced by the path; without it the
+/// path is appended. `kind` names the command in warnings.
+fn run_text_command(command: &str, file_path: &Path, kind: &str, timeout: Option<Duration>) -> Option<String> {
+    use std::process::{Command, Stdio};
+
+    let file = file_path.to_string_lossy();
+    let mut parts: Vec<String> = command.split_whitespace().map(|p| p.replace("{}", &file)).collect();
+    if !command.contains("{}") {
+        parts.push(file.to_string());
+    }
+    let (program, args) = parts.split_first()?;
+
+    let mut command = Command::new(program);
+    command.args(args).stdin(Stdio::null()).stderr(Stdio::null());
+    match output_with_timeout(&mut command, timeout) {
+        Ok(Some((status, stdout))) if status.success() => Some(String::from_utf8_lossy(&stdout).into_owned()),
+        Ok(Some((status, _))) => {
+            warn!("WARN: {kind} command {program} failed on {file} ({status})");
+            None
+        }
+        Ok(None) => {
+            warn!("WARN: skipping {file}: {kind} command {program} took longer than {timeout:?} (see --parse-timeout)", timeout = timeout.unwrap_or_default());
+            None
+        }
+        Err(err) => {
+            warn!("WARN: could not run {kind} command {program}: {err}");
+            None
+        }
+    }
+}
+
+/// The command configured with `KHOJ_PARSER_<EXT>` to extract the text of files with an
+/// extension khoj can't parse itself, e.g. `KHOJ_PARSER_DWG="dwg2txt {}"` for `.dwg` files.
+/// Extensions khoj parses are never handed to an external parser.
+pub(crate) fn external_parser(extension: &str) -> Option<String> {
+    if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) || allowlisted_extension(extension) {
+        return None;
+    }
+    env::var(format!("KHOJ_PARSER_{}", extension.to_ascii_uppercase())).ok().filter(|c| !c.trim().is_empty())
+}
+
+/// Distinguishes scratch directories of files being parsed in parallel by the indexer.
+fn scratch_dir_suffix() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
 fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
     let file = File::open(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
+        error!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
     })?;
     let er = EventReader::new(BufReader::new(file));
     let mut content = String::new();
@@ -63,7 +237,7 @@ fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
         let event = event.map_err(|err| {
             let TextPosition {row, column} = err.position();
             let msg = err.msg();
-            eprintln!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display());
+            error!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display());
         })?;
 
         if let XmlEvent::Characters(text) = event {
@@ -74,7 +248,139 @@ fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
     Ok(content)
 }
 
+/// Extracts the text of every cell of an Excel workbook, sheet by sheet. String cells are
+/// resolved through the shared strings table; numbers and booleans are kept as written.
+fn parse_entire_xlsx_file(file_path: &Path) -> Result<String, ()> {
+    let file = File::open(file_path).map_err(|err| {
+        error!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
+    })?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|err| {
+        error!("ERROR: could not read {file_path} as a workbook: {err}", file_path = file_path.display());
+    })?;
+
+    // Workbooks without any string cells have no shared strings table
+    let shared_strings = match archive.by_name("xl/sharedStrings.xml") {
+        Ok(entry) => read_xlsx_shared_strings(entry, file_path)?,
+        Err(zip::result::ZipError::FileNotFound) => Vec::new(),
+        Err(err) => {
+            error!("ERROR: could not read shared strings of {file_path}: {err}", file_path = file_path.display());
+            return Err(());
+        }
+    };
+
+    let mut sheets: Vec<String> = archive.file_names()
+        .filter(|name| name.strip_prefix("xl/worksheets/")
+            .is_some_and(|sheet| sheet.ends_with(".xml") && !sheet.contains('/')))
+        .map(String::from)
+        .collect();
+    if sheets.is_empty() {
+        error!("ERROR: {file_path} contains no worksheets", file_path = file_path.display());
+        return Err(());
+    }
+    // sheet2.xml before sheet10.xml
+    sheets.sort_by_key(|name| {
+        let digits: String = name.chars().filter(|c| c.is_ascii_digit()).collect();
+        (digits.parse::<usize>().unwrap_or(usize::MAX), name.clone())
+    });
+
+    let mut content = String::new();
+    for sheet in &sheets {
+        let entry = archive.by_name(sheet).map_err(|err| {
+            error!("ERROR: could not read {sheet} of {file_path}: {err}", file_path = file_path.display());
+        })?;
+        read_xlsx_sheet(entry, file_path, &shared_strings, &mut content)?;
+    }
+    Ok(content)
+}
+
+/// Reads the `<si>` entries of `xl/sharedStrings.xml`, joining rich text runs of each entry.
+fn read_xlsx_shared_strings(entry: impl std::io::Read, file_path: &Path) -> Result<Vec<String>, ()> {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let mut in_text = false;
+    for event in EventReader::new(BufReader::new(entry)) {
+        match xlsx_event(event, file_path)? {
+            XmlEvent::StartElement { name, .. } if name.local_name == "t" => in_text = true,
+            XmlEvent::EndElement { name } if name.local_name == "t" => in_text = false,
+            XmlEvent::EndElement { name } if name.local_name == "si" => strings.push(std::mem::take(&mut current)),
+            XmlEvent::Characters(text) | XmlEvent::Whitespace(text) if in_text => current.push_str(&text),
+            _ => {}
+        }
+    }
+    Ok(strings)
+}
+
+/// Appends the value of every cell of a worksheet to `content`. Formulas are skipped;
+/// their cached results are kept.
+fn read_xlsx_sheet(entry: impl std::io::Read, file_path: &Path, shared_strings: &[String], content: &mut String) -> Result<(), ()> {
+    let mut cell_type = String::new();
+    let mut in_value = false;
+    for event in EventReader::new(BufReader::new(entry)) {
+        match xlsx_event(event, file_path)? {
+            XmlEvent::StartElement { name, attributes, .. } => match name.local_name.as_str() {
+                "c" => {
+                    cell_type = attributes.into_iter()
+                        .find(|a| a.name.local_name == "t")
+                        .map(|a| a.value)
+                        .unwrap_or_default();
+                }
+                // `v` holds the value, `t` the text of an inline string
+                "v" | "t" => in_value = true,
+                _ => {}
+            },
+            XmlEvent::EndElement { name } if matches!(name.local_name.as_str(), "v" | "t") => in_value = false,
+            XmlEvent::Characters(text) if in_value => {
+                if cell_type == "s" {
+                    match text.trim().parse::<usize>().ok().and_then(|i| shared_strings.get(i)) {
+                        Some(string) => content.push_str(string),
+                        None => {
+                            error!("ERROR: {file_path}: invalid shared string index {text}", file_path = file_path.display());
+                            return Err(());
+                        }
+                    }
+                } else {
+                    content.push_str(&text);
+                }
+                content.push(' ');
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn xlsx_event(event: xml::reader::Result<XmlEvent>, file_path: &Path) -> Result<XmlEvent, ()> {
+    event.map_err(|err| {
+        let TextPosition {row, column} = err.position();
+        let msg = err.msg();
+        error!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display());
+    })
+}
+
+/// Like `parse_entire_file_by_extension`, but gives up after `timeout` on the formats whose
+/// parsing can take long: PDFs, spreadsheets, XML and external parsers (`KHOJ_PARSER_<EXT>`).
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn parse_entire_file_with_timeout(file_path: &Path, timeout: Option<Duration>) -> Result<String, ()> {
+    let extension = file_path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    if let Some(command) = external_parser(&extension) {
+        // An external parser is a process of its own, which is killed once it takes too long
+        return run_text_command(&command, file_path, "parser", timeout).ok_or(());
+    }
+    match timeout {
+        Some(timeout) if matches!(extension.as_str(), "pdf" | "xlsx" | "xml" | "xhtml") => {
+            // The processes OCR runs for scanned PDF pages are killed after `timeout` too
+            parse_with_timeout(file_path, timeout, move |file_path| parse_by_extension(file_path, Some(timeout)))
+        }
+        _ => parse_by_extension(file_path, timeout),
+    }
+}
+
 pub fn parse_entire_file_by_extension(file_path: &Path) -> Result<String, ()> {
+    parse_by_extension(file_path, None)
+}
+
+/// Parses `file_path` by its extension, killing the external processes it runs after `timeout`
+fn parse_by_extension(file_path: &Path, timeout: Option<Duration>) -> Result<String, ()> {
     let extension = match file_path.extension() {
         Some(ext) => ext.to_string_lossy().to_ascii_lowercase(),
         None => return Err(()),
@@ -92,90 +398,655 @@ pub fn parse_entire_file_by_extension(file_path: &Path) -> Result<String, ()> {
         | "mdx" | "ini" | "cfg" | "conf"
         | "sh" | "bash" | "zsh" | "fish"
         | "pl" | "sql" | "gradle" | "properties"
-        | "r" | "tex" | "rst"
+        | "r" | "rst"
         | "vue" | "svelte" | "dart" | "erl" | "ex" | "exs" | "lua" | "nim"
             => parse_entire_txt_file(file_path),
-        "pdf" => parse_entire_pdf_file(file_path),
-        _ => Err(()),
+        "tex" => parse_entire_tex_file(file_path),
+        "log" | "gz" if is_log_file(file_path) => parse_entire_log_file(file_path),
+        "pdf" => parse_entire_pdf_file(file_path, timeout),
+        "xlsx" => parse_entire_xlsx_file(file_path),
+        extension => match external_parser(extension) {
+            Some(command) => run_text_command(&command, file_path, "parser", timeout).ok_or(()),
+            None => Err(()),
+        },
+    }
+}
+
+/// File names of the index inside the indexed folder: portable JSON by default, or a binary
+/// encoding that loads much faster with `--binary-index`
+const JSON_INDEX_NAME: &str = ".finder.json";
+const BINARY_INDEX_NAME: &str = ".finder.bin";
+
+/// Shown by the TUI and the server's `/api/status` when indexing found nothing to index
+pub(crate) const EMPTY_INDEX_MESSAGE: &str = "No files indexed — check extensions/.khojignore";
+
+/// Where the index of `dir_path` is saved. `--binary-index` in `args` picks the binary format;
+/// otherwise whichever index exists is used, the newer one if there are both.
+pub(crate) fn index_path(dir_path: &Path, args: &[String]) -> PathBuf {
+    let json = dir_path.join(JSON_INDEX_NAME);
+    let binary = dir_path.join(BINARY_INDEX_NAME);
+    if args.iter().any(|a| a == "--binary-index") {
+        return binary;
+    }
+    let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+    match (modified(&json), modified(&binary)) {
+        (None, Some(_)) => binary,
+        (Some(json_time), Some(binary_time)) if binary_time > json_time => binary,
+        _ => json,
     }
 }
 
-fn save_model_as_json(model: &Model, index_path: &Path) -> Result<(), ()> {
-    println!("Saving {index_path}...", index_path = index_path.display());
+fn is_binary_index(index_path: &Path) -> bool {
+    index_path.file_name().is_some_and(|name| name == BINARY_INDEX_NAME)
+}
+
+/// Whether `path` is an index saved by khoj, or the temporary file `save_model` writes it to.
+/// Those are never indexed themselves, whatever their extension and whatever `--hidden` says.
+fn is_index_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else { return false };
+    let name = name.strip_suffix(".tmp").unwrap_or(name);
+    name == JSON_INDEX_NAME || name == BINARY_INDEX_NAME
+}
 
-    let index_file = File::create(index_path).map_err(|err| {
-        eprintln!("ERROR: could not create index file {index_path}: {err}",
+/// Loads the index saved at `index_path`, or an empty model if there is none yet. A binary
+/// index that doesn't exist yet starts from the JSON one next to it, converting it.
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn load_model(index_path: &Path) -> Result<Model, ()> {
+    let exists = index_path.try_exists().map_err(|err| {
+        eprintln!("ERROR: could not check the existence of file {index_path}: {err}",
                   index_path = index_path.display());
     })?;
+    if !exists {
+        let json = index_path.with_file_name(JSON_INDEX_NAME);
+        if is_binary_index(index_path) && json.is_file() {
+            info!("Converting {json} to {index_path}...", json = json.display(), index_path = index_path.display());
+            return load_model(&json);
+        }
+        return Ok(Model::default());
+    }
+
+    if is_binary_index(index_path) {
+        let bytes = fs::read(index_path).map_err(|err| {
+            eprintln!("ERROR: could not read index file {index_path}: {err}",
+                      index_path = index_path.display());
+        })?;
+        return bincode::deserialize(&bytes).map_err(|err| {
+            eprintln!("ERROR: could not parse index file {index_path}: {err}",
+                      index_path = index_path.display());
+        });
+    }
 
-    serde_json::to_writer(BufWriter::new(index_file), &model).map_err(|err| {
-        eprintln!("ERROR: could not serialize index into file {index_path}: {err}",
+    let index_file = File::open(index_path).map_err(|err| {
+        eprintln!("ERROR: could not open index file {index_path}: {err}",
                   index_path = index_path.display());
     })?;
+    serde_json::from_reader(BufReader::new(index_file)).map_err(|err| {
+        eprintln!("ERROR: could not parse index file {index_path}: {err}",
+                  index_path = index_path.display());
+    })
+}
 
-    Ok(())
+/// Saves `model` to `index_path`, as JSON or binary depending on its name (see `index_path`).
+/// The index is written to a temporary file first and renamed over the old one, so an
+/// interrupted save never leaves a truncated index behind.
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn save_model(model: &Model, index_path: &Path) -> Result<(), ()> {
+    info!("Saving {index_path}...", index_path = index_path.display());
+
+    let mut temp_path = index_path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let index_file = File::create(&temp_path).map_err(|err| {
+        eprintln!("ERROR: could not create index file {temp_path}: {err}",
+                  temp_path = temp_path.display());
+    })?;
+    let mut writer = BufWriter::new(index_file);
+
+    let result = if is_binary_index(index_path) {
+        bincode::serialize_into(&mut writer, &model).map_err(|err| err.to_string())
+    } else {
+        serde_json::to_writer(&mut writer, &model).map_err(|err| err.to_string())
+    };
+    let result = result.and_then(|()| writer.flush().map_err(|err| err.to_string()));
+    if let Err(err) = result {
+        eprintln!("ERROR: could not serialize index into file {temp_path}: {err}",
+                  temp_path = temp_path.display());
+        let _ = fs::remove_file(&temp_path);
+        return Err(());
+    }
+
+    fs::rename(&temp_path, index_path).map_err(|err| {
+        eprintln!("ERROR: could not replace index file {index_path}: {err}",
+                  index_path = index_path.display());
+        let _ = fs::remove_file(&temp_path);
+    })
 }
 
 use walkdir::WalkDir;
 use rayon::prelude::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-pub fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Model>>, processed: &mut usize) -> Result<(), ()> {
-    let files: Vec<_> = WalkDir::new(dir_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().to_owned())
-        .collect();
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Directory names skipped wherever they appear unless `--no-default-excludes` is given
+pub const DEFAULT_EXCLUDED_DIRS: &[&str] = &[".git", "node_modules", "target"];
+
+/// Names of generated lockfiles skipped wherever they appear unless `--no-default-excludes` is
+/// given. They are large, slow to index and fill searches with package names and hashes.
+pub const DEFAULT_EXCLUDED_FILES: &[&str] = &[
+    "package-lock.json", "npm-shrinkwrap.json", "yarn.lock", "pnpm-lock.yaml",
+    "Cargo.lock", "composer.lock", "Gemfile.lock", "poetry.lock", "Pipfile.lock", "go.sum",
+];
+
+/// Settings for walking a folder, taken from command line flags.
+#[derive(Clone, Debug)]
+pub struct IndexOptions {
+    /// Only index files at most this many directory levels below the folder (`None` = unlimited)
+    pub max_depth: Option<usize>,
+    /// Index files longer than this many lines as overlapping chunks (`None` = never)
+    pub chunk_lines: Option<usize>,
+    /// Also index dot files such as `.bashrc`
+    pub hidden: bool,
+    /// Tokenizer asked for on the command line (`--no-stem`, `--split-identifiers`, `--dotted-numbers`,
+    /// `--min-token-len`, `--max-token-len`); only a new index adopts it
+    pub tokenizer: Option<TokenizerConfig>,
+    /// Directories with any of these names are skipped entirely, at any depth
+    pub excluded_dirs: Vec<String>,
+    /// Files with any of these names are skipped, in any directory
+    pub excluded_files: Vec<String>,
+    /// Give up on a PDF, spreadsheet or XML file whose parsing takes longer than this (`None` = wait forever)
+    pub parse_timeout: Option<Duration>,
+    /// Leave files last modified before this alone, neither reindexing nor dropping them (`--since`)
+    pub since: Option<SystemTime>,
+    /// Warn once the index is estimated to take more bytes of memory than this (`--max-memory`)
+    pub max_memory: Option<usize>,
+    /// Stop indexing at `max_memory` instead of only warning, keeping the files indexed so far
+    pub stop_at_max_memory: bool,
+    /// Also index logs (`.log` and `.log.gz`, see `is_log_file`) and the times of their lines
+    pub logs: bool,
+    /// Find near-duplicate documents for `--penalize-duplicates` (see `Model::update_uniqueness`)
+    pub penalize_duplicates: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            max_depth: None,
+            chunk_lines: None,
+            hidden: false,
+            tokenizer: None,
+            excluded_dirs: DEFAULT_EXCLUDED_DIRS.iter().map(|name| name.to_string()).collect(),
+            excluded_files: DEFAULT_EXCLUDED_FILES.iter().map(|name| name.to_string()).collect(),
+            parse_timeout: Some(DEFAULT_PARSE_TIMEOUT),
+            since: None,
+            max_memory: None,
+            stop_at_max_memory: false,
+            logs: false,
+            penalize_duplicates: false,
+        }
+    }
+}
+
+/// The number of bytes of a size like `512M`, `2G`, `800K` or `1048576`. The suffixes are
+/// binary multiples and may be followed by `B` or `iB`, in either case.
+fn parse_size(value: &str) -> Option<usize> {
+    let upper = value.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, shift) = match digits.chars().last()? {
+        'K' => (&digits[..digits.len() - 1], 10),
+        'M' => (&digits[..digits.len() - 1], 20),
+        'G' => (&digits[..digits.len() - 1], 30),
+        'T' => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+    number.parse::<usize>().ok()?.checked_mul(1usize.checked_shl(shift)?)
+}
+
+/// The time `--since` stands for: `value` ago for a duration such as `45s`, `30m`, `12h`, `1d`
+/// or `2w`, or a point in time given as Unix seconds or a UTC date `YYYY-MM-DD[THH:MM[:SS]]`.
+pub(crate) fn parse_since(value: &str, now: SystemTime) -> Option<SystemTime> {
+    if let Some(unit) = value.chars().last().filter(char::is_ascii_alphabetic) {
+        let amount: u64 = value[..value.len() - 1].parse().ok()?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        return now.checked_sub(Duration::from_secs(amount.checked_mul(seconds)?));
+    }
+    if let Ok(seconds) = value.parse::<u64>() {
+        return UNIX_EPOCH.checked_add(Duration::from_secs(seconds));
+    }
+    parse_date_time(value)
+}
+
+/// The time of a UTC date `YYYY-MM-DD[THH:MM[:SS]]`, with a space instead of the `T` allowed.
+fn parse_date_time(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00"));
+    let mut date = date.splitn(3, '-').map(str::parse::<u32>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<u32>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next().unwrap_or(Ok(0)).ok()?);
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day)
+        || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    // Days since the epoch of the proleptic Gregorian calendar, counting years from March
+    // so the leap day comes last
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era_years = u64::from(year);
+    let days = era_years * 365 + era_years / 4 - era_years / 100 + era_years / 400
+        + u64::from((153 * month + 2) / 5 + day - 1)
+        - 719_468;
+    let seconds = days * 24 * 60 * 60 + u64::from(hour * 60 * 60 + minute * 60 + second);
+    UNIX_EPOCH.checked_add(Duration::from_secs(seconds))
+}
+
+/// How long parsing a single file may take by default before it is skipped
+const DEFAULT_PARSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consecutive chunks share this fraction (1/N) of their lines, so a passage cut by a
+/// chunk boundary still appears whole in one of the chunks
+const CHUNK_OVERLAP_DIVISOR: usize = 4;
+
+impl IndexOptions {
+    /// Reads `--max-depth N`, `--chunk-lines N`, `--hidden`, `--exclude-dir NAME`, `--exclude-file NAME`,
+    /// `--no-default-excludes`, `--parse-timeout SECS`, `--since TIME`, `--max-memory SIZE`,
+    /// `--stop-at-max-memory`, `--logs` and the tokenizer flags (see `tokenizer_from_args`) from `args`.
+    #[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+    pub fn from_args(args: &[String]) -> Result<Self, ()> {
+        let max_depth = match flag_value(args, "--max-depth") {
+            Some(v) => Some(v.parse::<usize>().map_err(|_| {
+                eprintln!("ERROR: invalid value for --max-depth: {v}");
+            })?),
+            None => None,
+        };
+        let chunk_lines = match flag_value(args, "--chunk-lines") {
+            Some(v) => Some(v.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(|| {
+                eprintln!("ERROR: invalid value for --chunk-lines: {v}");
+            })?),
+            None => None,
+        };
+        let hidden = args.iter().any(|a| a == "--hidden");
+        let tokenizer = tokenizer_from_args(args)?;
+        let (mut excluded_dirs, mut excluded_files) = if args.iter().any(|a| a == "--no-default-excludes") {
+            (Vec::new(), Vec::new())
+        } else {
+            let default = IndexOptions::default();
+            (default.excluded_dirs, default.excluded_files)
+        };
+        // --exclude-dir and --exclude-file may be repeated and take comma separated names
+        for pair in args.windows(2) {
+            let excluded = match pair[0].as_str() {
+                "--exclude-dir" => &mut excluded_dirs,
+                "--exclude-file" => &mut excluded_files,
+                _ => continue,
+            };
+            excluded.extend(pair[1].split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from));
+        }
+        let parse_timeout = match flag_value(args, "--parse-timeout") {
+            Some(v) => {
+                let seconds = v.parse::<u64>().map_err(|_| {
+                    eprintln!("ERROR: invalid value for --parse-timeout: {v}");
+                })?;
+                // 0 turns the timeout off
+                (seconds > 0).then(|| Duration::from_secs(seconds))
+            }
+            None => IndexOptions::default().parse_timeout,
+        };
+        let since = match flag_value(args, "--since") {
+            Some(v) => Some(parse_since(v, SystemTime::now()).ok_or_else(|| {
+                eprintln!("ERROR: invalid value for --since: {v} (expected a duration like 30m, 12h or 1d, a Unix timestamp or a date like 2024-05-01)");
+            })?),
+            None => None,
+        };
+        let max_memory = match flag_value(args, "--max-memory") {
+            Some(v) => Some(parse_size(v).filter(|&bytes| bytes > 0).ok_or_else(|| {
+                eprintln!("ERROR: invalid value for --max-memory: {v} (expected a size like 512M or 2G)");
+            })?),
+            None => None,
+        };
+        let stop_at_max_memory = args.iter().any(|a| a == "--stop-at-max-memory");
+        if stop_at_max_memory && max_memory.is_none() {
+            eprintln!("ERROR: --stop-at-max-memory needs --max-memory SIZE");
+            return Err(());
+        }
+        let logs = args.iter().any(|a| a == "--logs");
+        let penalize_duplicates = args.iter().any(|a| a == "--penalize-duplicates");
+        Ok(IndexOptions { max_depth, chunk_lines, hidden, tokenizer, excluded_dirs, excluded_files, parse_timeout, since, max_memory, stop_at_max_memory, logs, penalize_duplicates })
+    }
+
+    /// Whether a file last modified at `last_modified` is older than `--since` and left alone.
+    fn before_since(&self, last_modified: SystemTime) -> bool {
+        self.since.is_some_and(|since| last_modified < since)
+    }
+
+    /// Whether directories named `name` are skipped.
+    pub fn is_excluded_dir(&self, name: &std::ffi::OsStr) -> bool {
+        self.excluded_dirs.iter().any(|excluded| name == excluded.as_str())
+    }
+
+    /// Whether files named like `path` are skipped.
+    fn is_excluded_file(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.excluded_files.iter().any(|excluded| name == excluded.as_str()))
+    }
+
+    /// Whether `path`, found under `dir_path`, is inside a directory that is skipped.
+    fn in_excluded_dir(&self, dir_path: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(dir_path).unwrap_or(path);
+        relative.parent().is_some_and(|parent| parent.iter().any(|name| self.is_excluded_dir(name)))
+    }
+
+    /// Splits `content` of `file_path` into overlapping chunks of `chunk_lines` lines and tokenizes
+    /// each one. Returns `None` if chunking is off or the content is short enough to index whole.
+    fn chunk(&self, file_path: &Path, content: &str, tokenizer: TokenizerConfig) -> Option<Vec<Chunk>> {
+        let chunk_lines = self.chunk_lines?;
+        let lines: Vec<&str> = content.lines().collect();
+        if lines.len() <= chunk_lines {
+            return None;
+        }
+        let step = (chunk_lines - chunk_lines / CHUNK_OVERLAP_DIVISOR).max(1);
+        let mut chunks = Vec::new();
+        for first_line in (0..lines.len()).step_by(step) {
+            let last_line = (first_line + chunk_lines).min(lines.len());
+            let text = lines[first_line..last_line].join("\n");
+            let (count, tf, positions) = Model::compute_search_data(&text.chars().collect::<Vec<_>>(), tokenizer);
+            let heading_tf = Model::compute_heading_tf(file_path, &text, tokenizer);
+            chunks.push(Chunk { first_line, count, tf, positions, heading_tf });
+            if last_line == lines.len() {
+                break;
+            }
+        }
+        Some(chunks)
+    }
+
+    /// Whether `path`, found under `dir_path`, lies within the configured depth.
+    fn within_depth(&self, dir_path: &Path, path: &Path) -> bool {
+        let Some(max_depth) = self.max_depth else { return true };
+        match path.strip_prefix(dir_path) {
+            Ok(relative) => relative.components().count() <= max_depth,
+            Err(_) => true,
+        }
+    }
+}
+
+/// Directories modified this recently aren't remembered by `list_files`: on file systems with
+/// coarse timestamps, another change within the same tick wouldn't change the time again
+const DIR_LISTING_MIN_AGE: Duration = Duration::from_secs(2);
+
+/// The regular files under `dir_path`, not descending into excluded directories or below
+/// `--max-depth`, and the listing of each directory read. Directories whose modification time
+/// is still that of their listing in `previous` aren't read again, since their entries are
+/// the same; the files in them may have been written to, which the caller checks file by file.
+/// Listings hold every entry, so they stay valid when the options or `.khojignore` change.
+fn list_files(dir_path: &Path, options: &IndexOptions, previous: &HashMap<PathBuf, DirListing>) -> (Vec<PathBuf>, HashMap<PathBuf, DirListing>) {
+    let mut walk = DirWalk { options, previous, files: Vec::new(), listings: HashMap::new(), reused: 0 };
+    walk.visit(dir_path, 0);
+    trace!("Listed {} directories, {} unchanged since the last walk", walk.listings.len(), walk.reused);
+    (walk.files, walk.listings)
+}
+
+/// What `list_files` found so far
+struct DirWalk<'a> {
+    options: &'a IndexOptions,
+    previous: &'a HashMap<PathBuf, DirListing>,
+    files: Vec<PathBuf>,
+    listings: HashMap<PathBuf, DirListing>,
+    reused: usize,
+}
+
+impl<'a> DirWalk<'a> {
+    /// Whether the entries of a directory `depth` levels below the folder are within `--max-depth`.
+    fn lists(&self, depth: usize) -> bool {
+        self.options.max_depth.is_none_or(|max_depth| depth < max_depth)
+    }
+
+    /// The listing of `dir` in `previous`, if `dir` hasn't changed since.
+    fn unchanged(&self, dir: &Path, modified: SystemTime) -> Option<&'a DirListing> {
+        self.previous.get(dir).filter(|listing| listing.modified == modified)
+    }
+
+    /// Lists `dir`, `depth` levels below the folder: from `previous` if it's unchanged,
+    /// otherwise by walking it.
+    fn visit(&mut self, dir: &Path, depth: usize) {
+        if !self.lists(depth) {
+            return;
+        }
+        let Some(modified) = dir_modified(dir) else { return };
+        match self.unchanged(dir, modified) {
+            Some(listing) => self.visit_listed(dir, depth, listing),
+            None => self.walk(dir, depth, modified),
+        }
+    }
+
+    /// Lists `dir` from its unchanged `listing`. Its subdirectories are visited in turn, since
+    /// entries added to them don't change the time of `dir`.
+    fn visit_listed(&mut self, dir: &Path, depth: usize, listing: &'a DirListing) {
+        self.reused += 1;
+        self.files.extend(listing.files.iter().map(|name| dir.join(name)));
+        for name in &listing.dirs {
+            // Not descending into excluded directories at all is what makes skipping them fast
+            if !self.options.is_excluded_dir(OsStr::new(name)) {
+                self.visit(&dir.join(name), depth + 1);
+            }
+        }
+        self.listings.insert(dir.to_path_buf(), listing.clone());
+    }
+
+    /// Walks `dir`, last modified at `modified`, leaving the unchanged directories below it
+    /// to `visit_listed`.
+    fn walk(&mut self, dir: &Path, depth: usize, modified: SystemTime) {
+        let mut walker = WalkDir::new(dir);
+        if let Some(max_depth) = self.options.max_depth {
+            walker = walker.max_depth(max_depth - depth);
+        }
+        // The modification time and entries of each directory read
+        let mut read: HashMap<PathBuf, (SystemTime, Vec<OsString>, Vec<OsString>)> = HashMap::new();
+        read.insert(dir.to_path_buf(), (modified, Vec::new(), Vec::new()));
+        let mut entries = walker.into_iter();
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    trace!("Skipping {err}");
+                    // A directory that couldn't be read in full has no listing worth remembering
+                    if let Some(path) = err.path() {
+                        read.remove(path);
+                        path.parent().map(|parent| read.remove(parent));
+                    }
+                    continue;
+                }
+            };
+            if entry.depth() == 0 {
+                continue;
+            }
+            let listing = entry.path().parent().and_then(|parent| read.get_mut(parent));
+            if entry.file_type().is_file() {
+                if let Some((_, files, _)) = listing {
+                    files.push(entry.file_name().to_owned());
+                }
+                self.files.push(entry.into_path());
+            } else if entry.file_type().is_dir() {
+                if let Some((_, _, dirs)) = listing {
+                    dirs.push(entry.file_name().to_owned());
+                }
+                let depth = depth + entry.depth();
+                if !self.lists(depth) {
+                    continue;
+                }
+                // Not descending into excluded directories at all is what makes skipping them fast
+                if self.options.is_excluded_dir(entry.file_name()) {
+                    entries.skip_current_dir();
+                    continue;
+                }
+                let Some(modified) = dir_modified(entry.path()) else {
+                    entries.skip_current_dir();
+                    continue;
+                };
+                match self.unchanged(entry.path(), modified) {
+                    Some(listing) => {
+                        entries.skip_current_dir();
+                        self.visit_listed(entry.path(), depth, listing);
+                    }
+                    None => {
+                        read.insert(entry.into_path(), (modified, Vec::new(), Vec::new()));
+                    }
+                }
+            }
+        }
+        for (dir, (modified, files, dirs)) in read {
+            self.remember(dir, modified, files, dirs);
+        }
+    }
+
+    /// Keeps the listing of a directory that was read, unless it changed too recently to be trusted.
+    fn remember(&mut self, dir: PathBuf, modified: SystemTime, mut files: Vec<OsString>, mut dirs: Vec<OsString>) {
+        if !modified.elapsed().is_ok_and(|age| age >= DIR_LISTING_MIN_AGE) {
+            return;
+        }
+        // In a stable order, so listings can be compared with earlier ones
+        files.sort_unstable();
+        dirs.sort_unstable();
+        // Names that aren't UTF-8 can't be saved in the index, so such directories are read every time
+        let utf8 = |names: &[OsString]| names.iter().map(|name| name.to_str().map(String::from)).collect::<Option<Vec<_>>>();
+        if let (Some(files), Some(dirs)) = (utf8(&files), utf8(&dirs)) {
+            self.listings.insert(dir, DirListing { modified, files, dirs });
+        }
+    }
+}
+
+/// Modification time of the directory `dir`; `None` if it can't be read.
+fn dir_modified(dir: &Path) -> Option<SystemTime> {
+    match fs::metadata(dir).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => Some(modified),
+        Err(err) => {
+            trace!("Skipping {}: {}", dir.display(), err);
+            None
+        }
+    }
+}
 
-    let processed_count = AtomicUsize::new(0);
+/// Indexes every allowlisted file under `dir_path` into `model`, dropping documents whose
+/// files were deleted or are now ignored, too deep or hidden. `processed` is incremented as each file
+/// is (re)indexed or dropped, so other threads can poll it for progress.
+pub fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Model>>, options: &IndexOptions, processed: &AtomicUsize) -> Result<(), ()> {
+    let tokenizer = {
+        let mut model = model.lock().unwrap();
+        apply_requested_tokenizer(&mut model, options);
+        model.tokenizer
+    };
+    // Files that disappeared may have been renamed. Keep their documents until the walk is done,
+    // so a new file with the same content can take them over instead of being parsed again.
+    let vanished: Mutex<HashMap<u64, PathBuf>> = {
+        let model = model.lock().unwrap();
+        let paths: HashSet<&Path> = model.docs.keys().map(|key| split_chunk_key(key).0).collect();
+        let vanished = paths.into_iter()
+            .filter(|path| !path.exists())
+            .filter_map(|path| Some((model.content_hash(path)?, path.to_path_buf())))
+            .collect();
+        Mutex::new(vanished)
+    };
+    let vanished_paths: HashSet<PathBuf> = vanished.lock().unwrap().values().cloned().collect();
+    let dropped = model.lock().unwrap().retain_documents(|path| {
+        // Archive members are kept as long as their archive is
+        let path = archive::file_on_disk(path);
+        let kept = path.is_file() && !is_index_file(path) && !ignore_rules::is_ignored(path, false) && options.within_depth(dir_path, path)
+            && !options.in_excluded_dir(dir_path, path) && !options.is_excluded_file(path)
+            && (options.hidden || !is_dot_file(path)) && (options.logs || !is_log_file(path));
+        kept || vanished_paths.contains(path)
+    });
+    if dropped > 0 {
+        trace!("Dropped {dropped} deleted or ignored files from the index");
+        processed.fetch_add(dropped, Ordering::SeqCst);
+    }
 
+    let previous_listings = std::mem::take(&mut model.lock().unwrap().dirs);
+    let (files, listings) = list_files(dir_path, options, &previous_listings);
+    {
+        let mut model = model.lock().unwrap();
+        // Saving the index changes the time of its directory, which alone isn't worth saving it again
+        let same_entries = |a: &DirListing, b: &DirListing| a.files == b.files && a.dirs == b.dirs;
+        model.dirs_changed = listings.len() != previous_listings.len()
+            || listings.iter().any(|(dir, listing)| previous_listings.get(dir).is_none_or(|previous| !same_entries(listing, previous)));
+        model.dirs = listings;
+    }
+
+    let memory = MemoryCheck::default();
     files.par_iter().for_each(|file_path| {
+        if memory.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+
         // Skip if matched by .khojignore (checked inside is_ignored)
         if ignore_rules::is_ignored(file_path, false) {
+            trace!("Skipping {}: ignored", file_path.display());
+            return;
+        }
+
+        if options.is_excluded_file(file_path) {
+            trace!("Skipping {}: excluded file name", file_path.display());
             return;
         }
 
-        let dot_file = file_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .map(|s| s.starts_with("."))
-            .unwrap_or(false);
+        // The index itself is a dot file; never index it, even with --hidden
+        if is_index_file(file_path) {
+            trace!("Skipping {}: index file", file_path.display());
+            return;
+        }
 
-        if dot_file {
+        let dot_file = is_dot_file(file_path);
+        if dot_file && !options.hidden {
             return;
         }
 
         let extension = match file_path.extension() {
             Some(ext) => ext.to_string_lossy().to_ascii_lowercase(),
+            None if dot_file => String::new(),
             None => return,
         };
 
-        match extension.as_str() {
-            // Allowlist: text, markup, source code, configs
-            "txt" | "md" | "xml" | "xhtml" | "pdf"
-            | "rs" | "js" | "jsx" | "ts" | "tsx"
-            | "json" | "toml" | "yaml" | "yml"
-            | "py" | "go" | "java" | "kt" | "kts"
-            | "c" | "h" | "hpp" | "hh" | "cpp" | "cc" | "cxx"
-            | "cs" | "rb" | "php"
-            | "html" | "htm" | "css" | "scss" | "less"
-            | "mdx" | "ini" | "cfg" | "conf"
-            | "sh" | "bash" | "zsh" | "fish"
-            | "pl" | "sql" | "gradle" | "properties"
-            | "r" | "tex" | "rst"
-            | "vue" | "svelte" | "dart" | "erl" | "ex" | "exs" | "lua" | "nim"
-                => { /* supported */ }
-            _ => return,
+        // The walk only yields regular files, but the path may have been replaced since, and
+        // opening a named pipe or a device would block indexing forever
+        let metadata = match file_path.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                eprintln!("ERROR: could not get metadata for {}: {}", file_path.display(), err);
+                return;
+            }
+        };
+        if !metadata.is_file() {
+            info!("Skipping {}: not a regular file", file_path.display());
+            return;
+        }
+
+        if archive::is_archive(file_path) {
+            index_archive(file_path, &model, options, tokenizer, processed);
+            return;
         }
 
-        let last_modified = match file_path.metadata().and_then(|m| m.modified()) {
+        // Dot files like `.bashrc` or `.env.example` rarely have a known extension,
+        // so with --hidden any of them is read as plain text
+        let external = external_parser(&extension).is_some();
+        let plain_dot_file = match extension.as_str() {
+            extension if allowlisted_extension(extension) || external => false,
+            _ if options.logs && is_log_file(file_path) => false,
+            _ if dot_file => true,
+            _ => return,
+        };
+
+        let last_modified = match metadata.modified() {
             Ok(time) => time,
             Err(err) => {
                 eprintln!("ERROR: could not get metadata for {}: {}", file_path.display(), err);
                 return;
             }
         };
+        if options.before_since(last_modified) {
+            trace!("Skipping {}: not modified since --since", file_path.display());
+            return;
+        }
 
         // Check if reindexing is needed - requires lock, but quick check
         let needs_reindexing = {
@@ -184,94 +1055,1151 @@ pub fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Model>>, processed:
         };
 
         if needs_reindexing {
+             // Hashing is much cheaper than parsing, and spots files that were only renamed
+             let content_hash = hash_file(file_path);
+             let renamed_from = content_hash.and_then(|hash| vanished.lock().unwrap().remove(&hash));
+             if let Some(renamed_from) = renamed_from {
+                 trace!("Indexing {} as renamed from {}", file_path.display(), renamed_from.display());
+                 model.lock().unwrap().rename_document(&renamed_from, file_path, last_modified);
+                 processed.fetch_add(1, Ordering::SeqCst);
+                 return;
+             }
+
+             trace!("Indexing {}...", file_path.display());
+
+             // Text formats with NUL bytes are really binary data under a misleading name;
+             // tokenizing them would only fill the vocabulary with junk
+             if !matches!(extension.as_str(), "pdf" | "xlsx" | "gz") && !external && looks_binary(file_path) {
+                 info!("Skipping {}: looks like a binary file", file_path.display());
+                 return;
+             }
+
              // Parse content WITHOUT lock
-             let content = match parse_entire_file_by_extension(file_path) {
-                Ok(content) => content.chars().collect::<Vec<_>>(),
+             let parsed = if plain_dot_file {
+                 parse_entire_txt_file(file_path)
+             } else {
+                 parse_entire_file_with_timeout(file_path, options.parse_timeout)
+             };
+             let content = match parsed {
+                Ok(content) => content,
                 Err(()) => return,
             };
 
-            // Compute search data (tokenization) WITHOUT lock, in parallel
-            let (count, tf, positions) = Model::compute_search_data(&content);
+            // The file may have been modified while we were reading it, in which case the
+            // content doesn't correspond to `last_modified`. Skip it so the next run reindexes it.
+            match file_path.metadata().and_then(|m| m.modified()) {
+                Ok(time) if time == last_modified => {}
+                Ok(_) => {
+                    warn!("WARN: {} changed while being indexed, skipping it until the next run", file_path.display());
+                    return;
+                }
+                Err(err) => {
+                    eprintln!("ERROR: could not get metadata for {}: {}", file_path.display(), err);
+                    return;
+                }
+            }
 
-            // Add to model WITH lock - minimal critical section
-            {
-                let mut model = model.lock().unwrap();
-                model.add_document_precomputed(file_path.clone(), last_modified, count, tf, positions);
+            // Files without any words, like empty ones, are left out of the index, only remembered
+            // so they aren't parsed again until they change
+            if add_parsed_document(&model, file_path.clone(), last_modified, &content, content_hash, options, tokenizer) {
+                processed.fetch_add(1, Ordering::SeqCst);
+                memory.after_adding(&model, options);
             }
-            
-            processed_count.fetch_add(1, Ordering::SeqCst);
         }
     });
 
-    *processed += processed_count.load(Ordering::SeqCst);
+    // Whatever vanished and wasn't renamed is gone for good
+    let vanished: HashSet<PathBuf> = vanished.into_inner().unwrap().into_values().collect();
+    if !vanished.is_empty() {
+        let dropped = model.lock().unwrap().retain_documents(|path| !vanished.contains(path));
+        trace!("Dropped {dropped} deleted files from the index");
+        processed.fetch_add(dropped, Ordering::SeqCst);
+    }
+
+    // How alike documents are depends on all the others, changed or not
+    let mut model = model.lock().unwrap();
+    if options.penalize_duplicates {
+        model.update_uniqueness();
+    } else {
+        model.clear_uniqueness();
+    }
+
     Ok(())
 }
 
-fn usage(program: &str) {
-    eprintln!("Usage: {program} [SUBCOMMAND] [OPTIONS]");
-    eprintln!("Subcommands:");
-    eprintln!("    serve <folder> [address]       start local HTTP server with Web Interface");
+/// Files indexed between two estimates of the index's memory (see `MemoryCheck`)
+const MEMORY_CHECK_INTERVAL: usize = 500;
+
+/// Watches the estimated memory of the index while a folder is indexed, for `--max-memory`.
+#[derive(Default)]
+struct MemoryCheck {
+    /// Files added since indexing started
+    added: AtomicUsize,
+    /// Whether the limit was crossed and warned about
+    exceeded: AtomicBool,
+    /// Whether the remaining files are skipped (`--stop-at-max-memory`)
+    stopped: AtomicBool,
 }
 
-pub fn entry() -> Result<(), ()> {
-    let mut args = env::args();
-    let program = args.next().expect("path to program is provided");
+impl MemoryCheck {
+    /// Estimates the memory of the index every `MEMORY_CHECK_INTERVAL` files added, warning
+    /// the first time it's above `--max-memory` and stopping there if asked to.
+    fn after_adding(&self, model: &Mutex<Model>, options: &IndexOptions) {
+        let Some(max_memory) = options.max_memory else { return };
+        let added = self.added.fetch_add(1, Ordering::Relaxed) + 1;
+        if !added.is_multiple_of(MEMORY_CHECK_INTERVAL) || self.exceeded.load(Ordering::Relaxed) {
+            return;
+        }
+        let estimate = model.lock().unwrap().estimated_memory();
+        if estimate <= max_memory || self.exceeded.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let megabytes = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+        if options.stop_at_max_memory {
+            self.stopped.store(true, Ordering::SeqCst);
+            warn!("WARN: the index takes about {:.0} MiB, more than --max-memory {:.0} MiB; stopping here and keeping the files indexed so far",
+                  megabytes(estimate), megabytes(max_memory));
+        } else {
+            warn!("WARN: the index takes about {:.0} MiB, more than --max-memory {:.0} MiB; consider --exclude-dir, --max-depth or --stop-at-max-memory",
+                  megabytes(estimate), megabytes(max_memory));
+        }
+    }
+}
 
-    let subcommand = args.next().ok_or_else(|| {
-        usage(&program);
-        eprintln!("ERROR: no subcommand is provided");
-    })?;
+/// Parses `file_path` with `parse` on its own thread and gives up after `timeout`, so one
+/// pathological PDF or XML file can't stall indexing. A thread that is given up on can't be
+/// stopped; it keeps running in the background until the parser returns, and its result is discarded.
+fn parse_with_timeout(file_path: &Path, timeout: Duration, parse: impl FnOnce(&Path) -> Result<String, ()> + Send + 'static) -> Result<String, ()> {
+    use std::sync::mpsc::{self, RecvTimeoutError};
 
-    match subcommand.as_str() {
+    let (sender, receiver) = mpsc::channel();
+    let path = file_path.to_path_buf();
+    thread::spawn(move || {
+        // The receiver is gone if parsing took too long, which is fine
+        let _ = sender.send(parse(&path));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(parsed) => parsed,
+        Err(RecvTimeoutError::Timeout) => {
+            warn!("WARN: skipping {}: parsing took longer than {timeout:?} (see --parse-timeout)", file_path.display());
+            Err(())
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            eprintln!("ERROR: could not parse {}: the parser crashed", file_path.display());
+            Err(())
+        }
+    }
+}
+
+/// FNV-1a hash of the bytes of `file_path`, stable across runs and builds. `None` if it can't be read.
+fn hash_file(file_path: &Path) -> Option<u64> {
+    use std::io::Read;
+
+    let mut reader = BufReader::new(File::open(file_path).ok()?);
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf).ok()?;
+        if n == 0 {
+            return Some(hash);
+        }
+        for &byte in &buf[..n] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Whether `path` is a log file, indexed with `--logs`: `.log`, or `.log.gz` compressed with gzip.
+pub fn is_log_file(path: &Path) -> bool {
+    let extension = |path: &Path| path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match extension(path).as_deref() {
+        Some("log") => true,
+        Some("gz") => path.file_stem().is_some_and(|stem| extension(Path::new(stem)).as_deref() == Some("log")),
+        _ => false,
+    }
+}
+
+/// The time at the start of a log line, like `2024-01-15T10:23:45Z`, `2024-01-15 10:23:45,123`
+/// or `[2024-01-15 10:23:45]`, in Unix seconds. Times are taken as UTC, whatever the offset.
+pub fn log_line_time(line: &str) -> Option<u64> {
+    let line = line.trim_start().trim_start_matches('[');
+    let bytes = line.as_bytes();
+    let digits = |from: usize, to: usize| bytes.get(from..to).is_some_and(|b| b.iter().all(u8::is_ascii_digit));
+    let is = |at: usize, expected: &[u8]| bytes.get(at).is_some_and(|b| expected.contains(b));
+    if !(digits(0, 4) && is(4, b"-") && digits(5, 7) && is(7, b"-") && digits(8, 10)) {
+        return None;
+    }
+    let minutes = is(10, b"T ") && digits(11, 13) && is(13, b":") && digits(14, 16);
+    let seconds = minutes && is(16, b":") && digits(17, 19);
+    let end = if seconds { 19 } else if minutes { 16 } else { 10 };
+    let time = parse_date_time(&line[..end])?;
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+/// Logs compressed with gzip are decompressed up to this many bytes
+const MAX_DECOMPRESSED_LOG_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Reads a log, decompressing it if it's gzipped. Bytes that aren't UTF-8 are replaced
+/// rather than failing the whole file.
+fn parse_entire_log_file(file_path: &Path) -> Result<String, ()> {
+    use std::io::Read;
+
+    let report = |err: std::io::Error| {
+        error!("ERROR: could not read log {file_path}: {err}", file_path = file_path.display());
+    };
+    let file = File::open(file_path).map_err(report)?;
+    let mut bytes = Vec::new();
+    let gzipped = file_path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gz"));
+    if gzipped {
+        flate2::read::MultiGzDecoder::new(file).take(MAX_DECOMPRESSED_LOG_SIZE).read_to_end(&mut bytes).map_err(report)?;
+    } else {
+        BufReader::new(file).read_to_end(&mut bytes).map_err(report)?;
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Allowlist of extensions that get indexed: text, markup, source code, configs
+fn allowlisted_extension(extension: &str) -> bool {
+    matches!(extension,
+        "txt" | "md" | "xml" | "xhtml" | "pdf" | "xlsx"
+        | "rs" | "js" | "jsx" | "ts" | "tsx"
+        | "json" | "toml" | "yaml" | "yml"
+        | "py" | "go" | "java" | "kt" | "kts"
+        | "c" | "h" | "hpp" | "hh" | "cpp" | "cc" | "cxx"
+        | "cs" | "rb" | "php"
+        | "html" | "htm" | "css" | "scss" | "less"
+        | "mdx" | "ini" | "cfg" | "conf"
+        | "sh" | "bash" | "zsh" | "fish"
+        | "pl" | "sql" | "gradle" | "properties"
+        | "r" | "tex" | "rst"
+        | "vue" | "svelte" | "dart" | "erl" | "ex" | "exs" | "lua" | "nim")
+}
+
+/// Tokenizes `content` and adds it to `model` under `key`, in chunks if `options` asks for them.
+/// `content_hash` is the hash of the file's bytes, if it's a file of its own (see `hash_file`).
+/// Returns whether the index changed; content without any tokens is never added.
+fn add_parsed_document(model: &Mutex<Model>, key: PathBuf, last_modified: SystemTime, content: &str, content_hash: Option<u64>, options: &IndexOptions, tokenizer: TokenizerConfig) -> bool {
+    let tokenizer = tokenizer.for_path(&key);
+    // Front matter keys and delimiters aren't words of the note; its title and tags count as headings
+    let front_matter = if is_markdown(&key) { front_matter::parse(content) } else { None };
+    let content = front_matter.as_ref().map_or(content, |front_matter| front_matter.text.as_str());
+    let add_front_matter_headings = |heading_tf: &mut TermFreq| {
+        let Some(front_matter) = &front_matter else { return };
+        for value in front_matter.weighted_values() {
+            for term in lexer::Lexer::new(&value.chars().collect::<Vec<_>>(), tokenizer) {
+                *heading_tf.entry(term).or_default() += 1;
+            }
+        }
+    };
+    let tags = front_matter.as_ref().map(front_matter::FrontMatter::tags).unwrap_or_default();
+    // The time of each line of a log, for `since:` and `until:` filters
+    let line_times: Vec<Option<u64>> = if is_log_file(&key) { content.lines().map(log_line_time).collect() } else { Vec::new() };
+    let lines_per_doc = options.chunk_lines.unwrap_or(usize::MAX);
+    let surface_forms = Model::compute_surface_forms(&content.chars().collect::<Vec<_>>(), tokenizer);
+
+    // Compute search data (tokenization) WITHOUT lock, in parallel
+    if let Some(mut chunks) = options.chunk(&key, content, tokenizer) {
+        trace!("Indexing {} as {} chunks", key.display(), chunks.len());
+        if let Some(first) = chunks.first_mut() {
+            add_front_matter_headings(&mut first.heading_tf);
+        }
+        let mut model = model.lock().unwrap();
+        let changed = model.add_chunked_document(key.clone(), last_modified, chunks);
+        model.add_surface_forms(surface_forms);
+        if let Some(hash) = content_hash {
+            model.set_content_hash(&key, hash);
+        }
+        model.set_tags(&key, tags);
+        model.set_time_ranges(&key, &line_times, lines_per_doc);
+        changed
+    } else {
+        let (count, tf, positions) = Model::compute_search_data(&content.chars().collect::<Vec<_>>(), tokenizer);
+        if count == 0 {
+            trace!("Skipping {}: no words to index", key.display());
+        }
+        let mut heading_tf = Model::compute_heading_tf(&key, content, tokenizer);
+        add_front_matter_headings(&mut heading_tf);
+
+        // Add to model WITH lock - minimal critical section
+        let mut model = model.lock().unwrap();
+        let changed = model.add_document_precomputed(key.clone(), last_modified, count, tf, positions, heading_tf);
+        model.add_surface_forms(surface_forms);
+        if let Some(hash) = content_hash {
+            model.set_content_hash(&key, hash);
+        }
+        model.set_tags(&key, tags);
+        model.set_time_ranges(&key, &line_times, lines_per_doc);
+        changed
+    }
+}
+
+/// Indexes every allowlisted member of the archive at `archive_path` under its virtual path
+/// (see `archive::member_path`), replacing the members indexed from an older version of it.
+fn index_archive(archive_path: &Path, model: &Mutex<Model>, options: &IndexOptions, tokenizer: TokenizerConfig, processed: &AtomicUsize) {
+    let modified = |archive_path: &Path| archive_path.metadata().and_then(|m| m.modified()).map_err(|err| {
+        eprintln!("ERROR: could not get metadata for {}: {}", archive_path.display(), err);
+    });
+    let Ok(last_modified) = modified(archive_path) else { return };
+    if options.before_since(last_modified) {
+        trace!("Skipping {}: not modified since --since", archive_path.display());
+        return;
+    }
+
+    // All members share the modification time of the archive, so any of them will do
+    let needs_reindexing = {
+        let mut model = model.lock().unwrap();
+        let indexed_member = model.docs.keys()
+            .map(|key| split_chunk_key(key).0)
+            .find(|path| archive::split_member_path(path).is_some_and(|(archive, _)| archive == archive_path))
+            .map(Path::to_path_buf);
+        match indexed_member {
+            Some(member) => model.requires_reindexing(&member, last_modified),
+            // Archives without indexable members are read again on every run
+            None => true,
+        }
+    };
+    if !needs_reindexing {
+        return;
+    }
+    trace!("Indexing archive {}...", archive_path.display());
+
+    let mut members = Vec::new();
+    let wanted = |name: &str| {
+        let member = Path::new(name);
+        let extension = member.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        // Dot files in archives are mostly metadata, like the `._*` files macOS adds
+        !is_dot_file(member) && extension.is_some_and(|extension| allowlisted_extension(&extension))
+    };
+    let read = archive::for_each_member(archive_path, wanted, |name, content| {
+        let member_path = archive::member_path(archive_path, name);
+        let extension = Path::new(name).extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        if !matches!(extension.as_deref(), Some("pdf" | "xlsx")) && content[..content.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+            info!("Skipping {}: looks like a binary file", member_path.display());
+            return;
+        }
+        if let Ok(text) = archive::parse_member(archive_path, name, &content) {
+            members.push((member_path, text));
+        }
+    });
+    if read.is_err() {
+        return;
+    }
+
+    match modified(archive_path) {
+        Ok(time) if time == last_modified => {}
+        Ok(_) => {
+            warn!("WARN: {} changed while being indexed, skipping it until the next run", archive_path.display());
+            return;
+        }
+        Err(()) => return,
+    }
+
+    // Members that were deleted from the archive must not linger in the index
+    model.lock().unwrap().retain_documents(|path| archive::file_on_disk(path) != archive_path);
+    trace!("Indexing {} members of {}", members.len(), archive_path.display());
+    for (member_path, text) in members {
+        add_parsed_document(model, member_path, last_modified, &text, None, options, tokenizer);
+    }
+    processed.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Reads `--no-stem`, `--no-stem-code`, `--split-identifiers`, `--dotted-numbers`, `--min-token-len N` and `--max-token-len N` from
+/// `args`. Returns `None` if none of them is given, so an existing index isn't asked to change
+/// its tokenizer.
+fn tokenizer_from_args(args: &[String]) -> Result<Option<TokenizerConfig>, ()> {
+    let no_stem = args.iter().any(|a| a == "--no-stem");
+    let split_identifiers = args.iter().any(|a| a == "--split-identifiers");
+    let dotted_numbers = args.iter().any(|a| a == "--dotted-numbers");
+    let no_stem_code = args.iter().any(|a| a == "--no-stem-code");
+    let length = |flag: &str| -> Result<Option<usize>, ()> {
+        match flag_value(args, flag) {
+            Some(v) => Ok(Some(v.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(|| {
+                eprintln!("ERROR: invalid value for {flag}: {v}");
+            })?)),
+            None => Ok(None),
+        }
+    };
+    let min_token_len = length("--min-token-len")?;
+    let max_token_len = length("--max-token-len")?;
+    if !no_stem && !split_identifiers && !dotted_numbers && !no_stem_code && min_token_len.is_none() && max_token_len.is_none() {
+        return Ok(None);
+    }
+
+    let default = TokenizerConfig::default();
+    let config = TokenizerConfig {
+        stem: !no_stem,
+        min_token_len: min_token_len.unwrap_or(default.min_token_len),
+        max_token_len: max_token_len.unwrap_or(default.max_token_len),
+        split_identifiers,
+        dotted_numbers,
+        stem_code: !no_stem_code,
+    };
+    if config.min_token_len > config.max_token_len {
+        eprintln!("ERROR: --min-token-len {} is greater than --max-token-len {}", config.min_token_len, config.max_token_len);
+        return Err(());
+    }
+    Ok(Some(config))
+}
+
+/// Gives a new `model` the tokenizer requested in `options`. An existing index keeps its own,
+/// since queries must be tokenized like the documents were; warns if the request differs.
+pub(crate) fn apply_requested_tokenizer(model: &mut Model, options: &IndexOptions) {
+    let Some(requested) = options.tokenizer else { return };
+    if !model.request_tokenizer(requested) {
+        warn!("WARN: the index was built with {built_with} and keeps using it; run `reindex` to rebuild it with the new setting",
+              built_with = model.tokenizer);
+    }
+}
+
+fn is_dot_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Number of leading bytes inspected by `looks_binary`
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Returns `true` if the first few KB of the file contain a NUL byte, which text never does.
+fn looks_binary(file_path: &Path) -> bool {
+    use std::io::Read;
+
+    let Ok(file) = File::open(file_path) else { return false };
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    match file.take(BINARY_SNIFF_LEN as u64).read_to_end(&mut head) {
+        Ok(_) => head.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// Runs `add_folder_to_model` while printing a live count of processed files and
+/// the throughput to stderr. Returns the number of files indexed and how long it took.
+#[allow(clippy::result_unit_err, reason = "errors are printed where they occur, like everywhere else in khoj")]
+pub fn add_folder_to_model_with_progress(dir_path: &Path, model: Arc<Mutex<Model>>, options: &IndexOptions) -> Result<(usize, Duration), ()> {
+    let processed = AtomicUsize::new(0);
+    let start = Instant::now();
+
+    let (result, duration) = thread::scope(|s| {
+        let indexer = s.spawn(|| (add_folder_to_model(dir_path, model, options, &processed), start.elapsed()));
+        for frame in PROGRESS_SPINNER.iter().cycle() {
+            if indexer.is_finished() {
+                break;
+            }
+            // Verbose mode prints a line per file, which a live progress line would garble
+            if !logging::enabled(logging::Level::Verbose) {
+                report_progress(*frame, processed.load(Ordering::SeqCst), start.elapsed());
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        indexer.join().expect("indexing thread does not panic")
+    });
+    result?;
+
+    let processed = processed.load(Ordering::SeqCst);
+    report_progress(' ', processed, duration);
+    if logging::enabled(logging::Level::Normal) {
+        eprintln!();
+    }
+    Ok((processed, duration))
+}
+
+/// Frames of the spinner shown while indexing, so a folder full of slow files doesn't look stuck
+const PROGRESS_SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+fn report_progress(spinner: char, processed: usize, elapsed: Duration) {
+    if !logging::enabled(logging::Level::Normal) {
+        return;
+    }
+    let fps = processed as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    eprint!("\r{spinner} Indexed {processed} files in {elapsed:.2?} ({fps:.2} files/sec)");
+}
+
+/// Brings the index of `context.dir_path` up to date on a background thread, saving it if
+/// anything changed. `context.indexing` must already be set; it is cleared once the pass finishes.
+fn spawn_refresh_index(context: &server::Context) {
+    let model = Arc::clone(&context.model);
+    let indexing = Arc::clone(&context.indexing);
+    let dir_path = context.dir_path.clone();
+    let index_path = context.index_path.clone();
+    let options = context.index_options.clone();
+    thread::spawn(move || refresh_index(&dir_path, model, &index_path, &options, &indexing));
+}
+
+/// Re-runs `spawn_refresh_index` every `interval`, for `serve --refresh-interval`. A refresh
+/// that comes due while another pass (the initial one or `/api/reindex`) is running is skipped.
+fn spawn_periodic_refresh(context: &server::Context, interval: Duration) {
+    let model = Arc::clone(&context.model);
+    let indexing = Arc::clone(&context.indexing);
+    let dir_path = context.dir_path.clone();
+    let index_path = context.index_path.clone();
+    let options = context.index_options.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if indexing.swap(true, Ordering::SeqCst) {
+            info!("INFO: indexing is still in progress, skipping the scheduled refresh");
+            continue;
+        }
+        ignore_rules::reload();
+        refresh_index(&dir_path, Arc::clone(&model), &index_path, &options, &indexing);
+    });
+}
+
+fn refresh_index(dir_path: &Path, model: Arc<Mutex<Model>>, index_path: &Path, options: &IndexOptions, indexing: &AtomicBool) {
+    /// Clears `indexing` however the pass ends, even by panicking, so a failed pass doesn't
+    /// make every later refresh and `/api/reindex` look like it's still running
+    struct Finished<'a>(&'a AtomicBool);
+    impl Drop for Finished<'_> {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::SeqCst);
+        }
+    }
+    let _finished = Finished(indexing);
+
+    let processed = AtomicUsize::new(0);
+    if add_folder_to_model(dir_path, Arc::clone(&model), options, &processed).is_err() {
+        error!("ERROR: could not index {dir_path}", dir_path = dir_path.display());
+        return;
+    }
+    let model = model.lock().unwrap();
+    // An index converted to another format is saved even if no file changed
+    if (processed.load(Ordering::SeqCst) > 0 || model.dirs_changed || !index_path.exists()) && save_model(&model, index_path).is_err() {
+        // Searches keep using the index in memory; only saving it failed
+        error!("ERROR: could not save the index of {dir_path}", dir_path = dir_path.display());
+    }
+    info!("Finished indexing");
+    if model.docs.is_empty() {
+        warn!("WARN: no files were indexed in {dir_path}; check file extensions and .khojignore",
+              dir_path = dir_path.display());
+    }
+}
+
+/// Saves the index and exits when the server gets SIGINT or SIGTERM, so files indexed so far
+/// aren't lost if it is stopped before the indexing thread finishes.
+fn install_shutdown_handler(context: &server::Context) {
+    let model = Arc::clone(&context.model);
+    let index_path = context.index_path.clone();
+    let installed = ctrlc::set_handler(move || {
+        info!("Shutting down...");
+        // Indexing only holds the lock while adding a single file, so this leaves a
+        // consistent index; the files not reached yet are indexed on the next start
+        let model = model.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let code = if save_model(&model, &index_path).is_ok() { 0 } else { 1 };
+        std::process::exit(code);
+    });
+    if let Err(err) = installed {
+        warn!("WARN: could not install the shutdown handler, the index is only saved once indexing finishes: {err}");
+    }
+}
+
+/// Flags that take a value; the value is never a positional argument.
+const VALUE_FLAGS: &[&str] = &["--max-depth", "--chunk-lines", "--query-log", "--tie-break", "--idf", "--exclude-dir", "--exclude-file", "--min-token-len", "--max-token-len", "--synonyms", "--heading-boost", "--name-weight", "--directory-weight", "--parse-timeout", "--index", "--refresh-interval", "--search-timeout", "--since", "--format", "--position-decay", "--max-memory"];
+
+/// Returns the value following `flag` in `args`, if the flag is present.
+pub(crate) fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).map(|v| v.as_str())
+}
+
+/// Reads the search settings `--idf classic|smoothed` and `--tie-break path|recency` from `args`.
+pub(crate) fn ranking_params_from_args(args: &[String]) -> Result<RankingParams, ()> {
+    let mut params = RankingParams::default();
+    if let Some(v) = flag_value(args, "--idf") {
+        params.idf = IdfFormula::parse(v).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --idf: {v} (expected `classic` or `smoothed`)");
+        })?;
+    }
+    if let Some(v) = flag_value(args, "--tie-break") {
+        params.tie_break = TieBreak::parse(v).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --tie-break: {v} (expected `path` or `recency`)");
+        })?;
+    }
+    if let Some(v) = flag_value(args, "--heading-boost") {
+        params.heading_boost = v.parse::<f32>().ok().filter(|w| w.is_finite() && *w >= 0.0).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --heading-boost: {v} (expected a number of at least 0)");
+        })?;
+    }
+    if let Some(v) = flag_value(args, "--name-weight") {
+        params.name_weight = v.parse::<f32>().ok().filter(|w| w.is_finite() && *w >= 0.0).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --name-weight: {v} (expected a number of at least 0)");
+        })?;
+    }
+    if let Some(v) = flag_value(args, "--directory-weight") {
+        params.directory_weight = v.parse::<f32>().ok().filter(|w| w.is_finite() && *w >= 0.0).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --directory-weight: {v} (expected a number of at least 0)");
+        })?;
+    }
+    if let Some(v) = flag_value(args, "--position-decay") {
+        params.position_decay = v.parse::<f32>().ok().filter(|d| d.is_finite() && *d >= 0.0).ok_or_else(|| {
+            eprintln!("ERROR: invalid value for --position-decay: {v} (expected a number of at least 0)");
+        })?;
+    }
+    params.penalize_duplicates = args.iter().any(|a| a == "--penalize-duplicates");
+    params.boosts = !args.iter().any(|a| a == "--no-boosts");
+    Ok(params)
+}
+
+/// Loads the synonym groups of `--synonyms <path>`; without the flag no query is expanded.
+pub(crate) fn synonyms_from_args(args: &[String]) -> Result<Synonyms, ()> {
+    let Some(path) = flag_value(args, "--synonyms") else { return Ok(Synonyms::default()) };
+    let text = fs::read_to_string(path).map_err(|err| {
+        eprintln!("ERROR: could not read synonyms file {path}: {err}");
+    })?;
+    let synonyms = Synonyms::parse(&text);
+    if synonyms.is_empty() {
+        warn!("WARN: {path} has no synonym groups; expected lines like `car, automobile, vehicle`");
+    }
+    Ok(synonyms)
+}
+
+/// Returns the arguments that are neither flags nor the values of `VALUE_FLAGS`.
+fn positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+/// Brings the index of `dir_path` saved at `index_path` up to date, the same way `serve` does on
+/// startup, and saves it if anything changed. Returns the model, the number of files (re)indexed
+/// or dropped, and how long that took.
+fn update_saved_index(dir_path: &Path, index_path: &Path, options: &IndexOptions) -> Result<(Model, usize, Duration), ()> {
+    let model = Arc::new(Mutex::new(load_model(index_path)?));
+    let (processed, duration) = add_folder_to_model_with_progress(dir_path, Arc::clone(&model), options)?;
+    let model = Arc::try_unwrap(model)
+        .map_err(|_| eprintln!("ERROR: the index is still shared after indexing"))?
+        .into_inner()
+        .expect("indexing thread does not panic");
+    // An index converted to another format is saved even if no file changed
+    if processed > 0 || model.dirs_changed || !index_path.exists() {
+        save_model(&model, index_path)?;
+    }
+    Ok((model, processed, duration))
+}
+
+/// Number of results printed per query by `repl` and `search`
+const REPL_RESULT_LIMIT: usize = 10;
+
+/// Writes the term -> documents view of `model` (see `Model::inverted_index`) to `out_path` as JSON.
+fn export_inverted_index(model: &Model, out_path: &Path) -> Result<(), ()> {
+    let out_file = File::create(out_path).map_err(|err| {
+        eprintln!("ERROR: could not create export file {out_path}: {err}", out_path = out_path.display());
+    })?;
+    let mut writer = BufWriter::new(out_file);
+    serde_json::to_writer(&mut writer, &model.inverted_index()).map_err(|err| err.to_string())
+        .and_then(|()| writer.flush().map_err(|err| err.to_string()))
+        .map_err(|err| {
+            eprintln!("ERROR: could not write export file {out_path}: {err}", out_path = out_path.display());
+        })
+}
+
+/// Answers queries read from stdin, one per line, until EOF. A prompt is shown only when
+/// stdin is a terminal, so piped input gives clean output.
+fn repl(model: &Model) -> Result<(), ()> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+        }
+        let Some(line) = lines.next() else { break };
+        let query = line.map_err(|err| {
+            eprintln!("ERROR: could not read query from stdin: {err}");
+        })?;
+        let query = query.trim();
+        if query.is_empty() {
+            continue;
+        }
+
+        print_results(query, model.search_query(&query.chars().collect::<Vec<_>>()));
+    }
+    Ok(())
+}
+
+/// Prints `query` and the first `REPL_RESULT_LIMIT` of its matching `results`, one per line.
+fn print_results(query: &str, results: Vec<(PathBuf, f32)>) {
+    let results: Vec<_> = results.into_iter()
+        .filter(|(_, rank)| *rank > 0.0)
+        .take(REPL_RESULT_LIMIT)
+        .collect();
+    println!("{query}");
+    if results.is_empty() {
+        println!("    (no results)");
+    }
+    for (i, (path, rank)) in results.iter().enumerate() {
+        println!("{n:>4}. {rank:.4}  {path}", n = i + 1, path = path.display());
+    }
+    println!();
+}
+
+/// Machine-readable encodings of `khoj search` results, chosen with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// One path per line
+    Plain,
+    /// An array of `{path, score, snippet}` objects
+    Json,
+    /// Paths each followed by a NUL byte, for `xargs -0`
+    Null,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "plain" => Some(Self::Plain),
+            "json" => Some(Self::Json),
+            "null" => Some(Self::Null),
+            _ => None,
+        }
+    }
+}
+
+/// Writes every one of the matching `results` of `query` to stdout in `format`.
+fn write_results(format: OutputFormat, query: &str, results: &[(PathBuf, f32)]) -> std::io::Result<()> {
+    #[derive(serde::Serialize)]
+    struct JsonResult<'a> {
+        path: &'a Path,
+        score: f32,
+        snippet: Option<String>,
+    }
+
+    let results = results.iter().filter(|(_, rank)| *rank > 0.0);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    match format {
+        OutputFormat::Plain => {
+            for (path, _) in results {
+                writeln!(out, "{}", path.display())?;
+            }
+        }
+        OutputFormat::Null => {
+            for (path, _) in results {
+                out.write_all(path.as_os_str().as_encoded_bytes())?;
+                out.write_all(b"\0")?;
+            }
+        }
+        OutputFormat::Json => {
+            let results: Vec<JsonResult> = results
+                .map(|(path, score)| JsonResult { path, score: *score, snippet: tui::snippet(path, query) })
+                .collect();
+            serde_json::to_writer(&mut out, &results)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+/// The text of `path` that gets indexed, as extracted by its parser, giving up after `timeout`
+/// if there is one (see `parse_with_timeout`).
+pub(crate) fn extracted_text(path: &Path, timeout: Option<Duration>) -> Option<String> {
+    if archive::split_member_path(path).is_none() {
+        return parse_entire_file_with_timeout(path, timeout).ok();
+    }
+    fn parse_member(path: &Path) -> Result<String, ()> {
+        archive::parse_member_path(path).map_err(|_| ())
+    }
+    match timeout {
+        Some(timeout) => parse_with_timeout(path, timeout, parse_member).ok(),
+        None => parse_member(path).ok(),
+    }
+}
+
+/// The lines of `text`, the extracted text of `path`, that hold a word of `query` (see
+/// `Model::matching_lines`), numbered from 1.
+pub fn matching_lines(model: &Model, path: &Path, query: &[char], text: &str) -> Vec<(usize, String)> {
+    // Front matter is indexed as its bare values, on the lines they were on
+    let front_matter = if is_markdown(path) { front_matter::parse(text) } else { None };
+    let indexed = front_matter.as_ref().map_or(text, |front_matter| front_matter.text.as_str());
+    let lines: Vec<&str> = text.lines().collect();
+    model.matching_lines(path, query, indexed).into_iter()
+        .filter_map(|index| Some((index + 1, lines.get(index)?.to_string())))
+        .collect()
+}
+
+/// Prints the lines of the matching `results` of `query` that hold its words as
+/// `path:line: text`, like grep, the best ranked file first.
+fn print_matching_lines(models: &[Model], query: &str, results: &[(PathBuf, f32)]) -> std::io::Result<()> {
+    let query: Vec<char> = query.chars().collect();
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    for (path, _) in results.iter().filter(|(_, rank)| *rank > 0.0) {
+        let Some(text) = extracted_text(path, None) else { continue };
+        // A file found in several indexes has the same lines in each
+        let lines = models.iter()
+            .map(|model| matching_lines(model, path, &query, &text))
+            .find(|lines| !lines.is_empty())
+            .unwrap_or_default();
+        for (number, line) in lines {
+            writeln!(out, "{path}:{number}: {line}", path = path.display())?;
+        }
+    }
+    out.flush()
+}
+
+/// Version of this build, reported by `--version` and the server's `/version` endpoint
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Whether `args` ask for the version with `--version` or `-V`.
+pub(crate) fn wants_version(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--version" || a == "-V")
+}
+
+fn usage(program: &str) {
+    eprintln!("Usage: {program} [SUBCOMMAND] [OPTIONS]");
+    eprintln!("Subcommands:");
+    eprintln!("    serve <folder> [address]       start local HTTP server with Web Interface");
+    eprintln!("        --query-log <path>         append each search to <path> as a JSON line");
+    eprintln!("        --auto-port                if the address is in use, listen on the next free port");
+    eprintln!("        --refresh-interval SECS    re-walk the folder and update the index every SECS seconds");
+    eprintln!("        --search-timeout MS        return partial results of searches that take longer than MS milliseconds");
+    eprintln!("    reindex <folder>               rebuild the index of the folder from scratch and exit");
+    eprintln!("    index <folder>                 update the index of the folder, save it and exit");
+    eprintln!("    export <folder> <out>          write the index as JSON mapping each term to the files containing it");
+    eprintln!("    search --index <path> <query>  rank files across prebuilt indexes; repeat --index for each one");
+    eprintln!("        --lines                    print the lines holding the query words as path:line: text, like grep");
+    eprintln!("        --format plain|json|null   print every match as a path per line, a JSON array or NUL-terminated paths");
+    eprintln!("    check <folder> [--fix]         verify the statistics stored in the index; --fix recomputes them");
+    eprintln!("    theme preview <file>           draw a sample of the TUI with the colors in a theme file and exit");
+    eprintln!("    repl <folder>                  read queries from stdin, one per line, and print ranked results");
+    eprintln!("Without a subcommand khoj indexes the current directory and opens the interactive TUI.");
+    eprintln!("Options:");
+    eprintln!("    --gitignore                    also skip paths matched by .gitignore files");
+    eprintln!("    --max-depth N                  only index files at most N directory levels deep");
+    eprintln!("    --chunk-lines N                index files longer than N lines as overlapping chunks");
+    eprintln!("    --hidden                       also index dot files such as .bashrc");
+    eprintln!("    --exclude-dir NAME[,NAME...]   skip directories with these names anywhere in the tree");
+    eprintln!("    --exclude-file NAME[,NAME...]  skip files with these names anywhere in the tree");
+    eprintln!("    --no-default-excludes          don't skip .git, node_modules and target directories or lockfiles");
+    eprintln!("    --since TIME                   only (re)index files modified since TIME: 1d, 12h, a Unix timestamp or 2024-05-01");
+    eprintln!("    --parse-timeout SECS           skip PDF, XLSX and XML files that take longer to parse (default 30, 0 = never)");
+    eprintln!("    --logs                         also index .log and .log.gz files, with the times of their lines for since: and until:");
+    eprintln!("    --max-memory SIZE              warn once the index is estimated to take more memory, like 512M or 2G");
+    eprintln!("    --stop-at-max-memory           stop indexing at --max-memory, keeping the files indexed so far");
+    eprintln!("    --no-stem                      index and match words as written, without stemming (new indexes only)");
+    eprintln!("    --no-stem-code                 stem prose but match words in source code as written (new indexes only)");
+    eprintln!("    --split-identifiers            also index the words of getUserName and get_user_name (new indexes only)");
+    eprintln!("    --dotted-numbers               also index 1.2.3, 2023-01-15 and 192.168.1.1 whole (new indexes only)");
+    eprintln!("    --min-token-len N              drop tokens shorter than N characters (default 2, new indexes only)");
+    eprintln!("    --max-token-len N              drop tokens longer than N characters (default 40, new indexes only)");
+    eprintln!("    --tie-break path|recency       order equally ranked results by path (default) or newest first");
+    eprintln!("    --idf classic|smoothed         smoothed keeps terms found in every file from scoring zero");
+    eprintln!("    --heading-boost W              extra weight of words in markdown headings (default 3, 0 turns it off)");
+    eprintln!("    --name-weight W                weight of query words in a file's name (default 1, 0 turns it off)");
+    eprintln!("    --directory-weight W           weight of query words in a file's directories (default 0.25)");
+    eprintln!("    --position-decay D             count later occurrences of a word less, by e^(-D) at the end (default 0)");
+    eprintln!("    --penalize-duplicates          rank files much like many others, such as boilerplate, lower (also give it when indexing)");
+    eprintln!("    --no-boosts                    rank by summed tf-idf, without the coverage and phrase boosts");
+    eprintln!("    --synonyms <path>              also match synonyms listed in <path>, one group per line");
+    eprintln!("    --binary-index                 save the index as .finder.bin, which loads faster than .finder.json");
+    eprintln!("    --quiet, -q                    only print errors");
+    eprintln!("    --version, -V                  print the version of khoj and exit");
+    eprintln!("    --verbose, -v                  also print a trace line for every file indexed or skipped");
+}
+
+pub fn entry() -> Result<(), ()> {
+    let all_args: Vec<String> = env::args().collect();
+    if wants_version(&all_args) {
+        println!("khoj {VERSION}");
+        return Ok(());
+    }
+    logging::set_level(logging::level_from_args(&all_args));
+
+    let mut args = env::args();
+    let program = args.next().expect("path to program is provided");
+
+    // Without a subcommand (only flags, or nothing) run the interactive TUI
+    let subcommand = match args.next() {
+        Some(subcommand) if !subcommand.starts_with('-') => subcommand,
+        _ => return tui::main().map_err(|err| {
+            eprintln!("ERROR: {err}");
+        }),
+    };
+
+    match subcommand.as_str() {
         "serve" => {
             let dir_path = args.next().ok_or_else(|| {
                 usage(&program);
                 eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
             })?;
 
-            // Initialize ignore rules from .khojignore
-            ignore_rules::init(Path::new(&dir_path));
+            let rest: Vec<String> = args.collect();
+            let use_gitignore = rest.iter().any(|a| a == "--gitignore");
 
-            let mut index_path = Path::new(&dir_path).to_path_buf();
-            index_path.push(".finder.json");
+            // Initialize ignore rules from .khojignore (and .gitignore if requested)
+            ignore_rules::init(Path::new(&dir_path), use_gitignore);
 
-            let address = args.next().unwrap_or("127.0.0.1:6969".to_string());
+            let index_path = index_path(Path::new(&dir_path), &rest);
+
+            let index_options = IndexOptions::from_args(&rest)?;
+            let ranking = ranking_params_from_args(&rest)?;
+            let synonyms = synonyms_from_args(&rest)?;
+            let query_log = match flag_value(&rest, "--query-log") {
+                Some(path) => Some(server::QueryLog::open(Path::new(path))?),
+                None => None,
+            };
+
+            let refresh_interval = match flag_value(&rest, "--refresh-interval") {
+                Some(v) => Some(v.parse::<u64>().ok().filter(|secs| *secs > 0).map(Duration::from_secs).ok_or_else(|| {
+                    eprintln!("ERROR: invalid value for --refresh-interval: {v} (expected a positive number of seconds)");
+                })?),
+                None => None,
+            };
+
+            let search_timeout = match flag_value(&rest, "--search-timeout") {
+                Some(v) => Some(v.parse::<u64>().ok().filter(|ms| *ms > 0).map(Duration::from_millis).ok_or_else(|| {
+                    eprintln!("ERROR: invalid value for --search-timeout: {v} (expected a positive number of milliseconds)");
+                })?),
+                None => None,
+            };
+
+            let address = positional_args(&rest).first()
+                .map(|a| a.to_string())
+                .unwrap_or("127.0.0.1:6969".to_string());
+
+            let model = Arc::new(Mutex::new(load_model(&index_path)?));
+            {
+                let mut model = model.lock().unwrap();
+                model.ranking = ranking;
+                model.synonyms = synonyms;
+            }
+
+            let context = server::Context {
+                model,
+                indexing: Arc::new(AtomicBool::new(true)),
+                dir_path: PathBuf::from(&dir_path),
+                index_path,
+                index_options,
+                query_log,
+                search_timeout,
+                thumbnails: Arc::default(),
+            };
+            install_shutdown_handler(&context);
+            spawn_refresh_index(&context);
+            if let Some(interval) = refresh_interval {
+                spawn_periodic_refresh(&context, interval);
+            }
+
+            let auto_port = rest.iter().any(|a| a == "--auto-port");
+            server::start(&address, auto_port, context)
+        }
+
+        "reindex" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
+            })?;
+
+            let rest: Vec<String> = args.collect();
+            let use_gitignore = rest.iter().any(|a| a == "--gitignore");
+
+            // Initialize ignore rules from .khojignore (and .gitignore if requested)
+            ignore_rules::init(Path::new(&dir_path), use_gitignore);
+
+            let index_options = IndexOptions::from_args(&rest)?;
+            let index_path = index_path(Path::new(&dir_path), &rest);
 
             let exists = index_path.try_exists().map_err(|err| {
                 eprintln!("ERROR: could not check the existence of file {index_path}: {err}",
                           index_path = index_path.display());
             })?;
-
-            let model: Arc<Mutex<Model>>;
             if exists {
-                let index_file = File::open(&index_path).map_err(|err| {
-                    eprintln!("ERROR: could not open index file {index_path}: {err}",
+                info!("Removing {index_path}...", index_path = index_path.display());
+                fs::remove_file(&index_path).map_err(|err| {
+                    eprintln!("ERROR: could not remove index file {index_path}: {err}",
                               index_path = index_path.display());
                 })?;
+            }
 
-                model = Arc::new(Mutex::new(serde_json::from_reader(index_file).map_err(|err| {
-                    eprintln!("ERROR: could not parse index file {index_path}: {err}",
-                              index_path = index_path.display());
-                })?));
-            } else {
-                model = Arc::new(Mutex::new(Default::default()));
+            let model = Arc::new(Mutex::new(Model::default()));
+            let (processed, duration) = add_folder_to_model_with_progress(Path::new(&dir_path), Arc::clone(&model), &index_options)?;
+
+            let model = model.lock().unwrap();
+            save_model(&model, &index_path)?;
+
+            info!("Reindexed {processed} files in {duration:.2?}");
+            info!("Vocabulary: {terms} distinct terms", terms = model.df.len());
+            Ok(())
+        }
+        "theme" => {
+            match args.next().as_deref() {
+                Some("preview") => {}
+                _ => {
+                    usage(&program);
+                    eprintln!("ERROR: unknown {subcommand} command; expected `{subcommand} preview <file>`");
+                    return Err(());
+                }
             }
+            let theme_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no theme file is provided for {subcommand} preview");
+            })?;
+            tui::preview_theme(Path::new(&theme_path)).map_err(|err| {
+                eprintln!("ERROR: {err}");
+            })
+        }
 
-            {
-                let model = Arc::clone(&model);
-                thread::spawn(move || {
-                    let mut processed = 0;
-                    // TODO: what should we do in case indexing thread crashes
-                    add_folder_to_model(Path::new(&dir_path), Arc::clone(&model), &mut processed).unwrap();
-                    if processed > 0 {
-                        let model = model.lock().unwrap();
-                        save_model_as_json(&model, &index_path).unwrap();
-                    }
-                    println!("Finished indexing");
+        "search" => {
+            let rest: Vec<String> = args.collect();
+            // Each --index is a folder or the index file of one
+            let indexes: Vec<&str> = rest.windows(2)
+                .filter(|pair| pair[0] == "--index")
+                .map(|pair| pair[1].as_str())
+                .collect();
+            if indexes.is_empty() {
+                usage(&program);
+                eprintln!("ERROR: no --index is provided for {subcommand} subcommand");
+                return Err(());
+            }
+            let query = positional_args(&rest).join(" ");
+            if query.is_empty() {
+                usage(&program);
+                eprintln!("ERROR: no query is provided for {subcommand} subcommand");
+                return Err(());
+            }
+            let ranking = ranking_params_from_args(&rest)?;
+            let synonyms = synonyms_from_args(&rest)?;
+
+            let mut models = Vec::new();
+            for index in indexes {
+                let index = Path::new(index);
+                let index_path = if index.is_dir() { index_path(index, &rest) } else { index.to_path_buf() };
+                if !index_path.exists() {
+                    eprintln!("ERROR: there is no index at {index_path}", index_path = index_path.display());
+                    return Err(());
+                }
+                let mut model = load_model(&index_path)?;
+                model.ranking = ranking;
+                model.synonyms = synonyms.clone();
+                models.push(model);
+            }
+
+            let results = search_merged(&models, &query.chars().collect::<Vec<_>>());
+            if rest.iter().any(|a| a == "--lines") {
+                if rest.iter().any(|a| a == "--format") {
+                    eprintln!("ERROR: --lines and --format can't be combined");
+                    return Err(());
+                }
+                return print_matching_lines(&models, &query, &results).map_err(|err| {
+                    eprintln!("ERROR: could not write the results: {err}");
                 });
             }
+            match flag_value(&rest, "--format") {
+                None => {
+                    print_results(&query, results);
+                    Ok(())
+                }
+                Some(format) => {
+                    let format = OutputFormat::parse(format).ok_or_else(|| {
+                        eprintln!("ERROR: invalid value for --format: {format} (expected `plain`, `json` or `null`)");
+                    })?;
+                    write_results(format, &query, &results).map_err(|err| {
+                        eprintln!("ERROR: could not write the results: {err}");
+                    })
+                }
+            }
+        }
+
+        "repl" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
+            })?;
+
+            let rest: Vec<String> = args.collect();
+            let use_gitignore = rest.iter().any(|a| a == "--gitignore");
+            ignore_rules::init(Path::new(&dir_path), use_gitignore);
+            let index_options = IndexOptions::from_args(&rest)?;
+            let ranking = ranking_params_from_args(&rest)?;
+            let synonyms = synonyms_from_args(&rest)?;
+
+            // Bring the saved index up to date before taking queries
+            let index_path = index_path(Path::new(&dir_path), &rest);
+            let (mut model, _, _) = update_saved_index(Path::new(&dir_path), &index_path, &index_options)?;
+            model.ranking = ranking;
+            model.synonyms = synonyms;
+
+            repl(&model)
+        }
+        "index" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
+            })?;
+
+            let rest: Vec<String> = args.collect();
+            let use_gitignore = rest.iter().any(|a| a == "--gitignore");
+            ignore_rules::init(Path::new(&dir_path), use_gitignore);
+            let index_options = IndexOptions::from_args(&rest)?;
+
+            let index_path = index_path(Path::new(&dir_path), &rest);
+            let (model, processed, duration) = update_saved_index(Path::new(&dir_path), &index_path, &index_options)?;
+            info!("Updated {processed} files in {duration:.2?}; the index has {files} files",
+                  files = model.file_count());
+            Ok(())
+        }
+
+        "export" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
+            })?;
+            let out_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no output file is provided for {subcommand} subcommand");
+            })?;
+
+            let rest: Vec<String> = args.collect();
+            let index_path = index_path(Path::new(&dir_path), &rest);
+            if !index_path.exists() {
+                eprintln!("ERROR: {dir_path} has no index yet; run `index {dir_path}` first");
+                return Err(());
+            }
+            let model = load_model(&index_path)?;
+            export_inverted_index(&model, Path::new(&out_path))?;
+            info!("Exported {terms} terms from {files} files to {out_path}",
+                  terms = model.df.len(), files = model.file_count());
+            Ok(())
+        }
+
+        "check" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
+            })?;
 
-            server::start(&address, Arc::clone(&model))
+            let rest: Vec<String> = args.collect();
+            let index_path = index_path(Path::new(&dir_path), &rest);
+            if !index_path.exists() {
+                eprintln!("ERROR: {dir_path} has no index yet; run `index {dir_path}` first");
+                return Err(());
+            }
+            let mut model = load_model(&index_path)?;
+            let problems = model.check();
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if problems.is_empty() {
+                println!("{index_path}: no problems in {files} files and {terms} terms",
+                         index_path = index_path.display(), files = model.file_count(), terms = model.df.len());
+                return Ok(());
+            }
+            if !rest.iter().any(|a| a == "--fix") {
+                eprintln!("ERROR: found {count} problems in {index_path}; run `check {dir_path} --fix` to recompute the affected statistics",
+                          count = problems.len(), index_path = index_path.display());
+                return Err(());
+            }
+            model.repair();
+            save_model(&model, &index_path)?;
+            let positions = problems.iter().filter(|problem| matches!(problem, IndexProblem::Positions { .. })).count();
+            if positions > 0 {
+                warn!("WARN: the positions of {positions} documents were dropped; `reindex` to get phrase matching back for them");
+            }
+            println!("Fixed {count} problems in {index_path}", count = problems.len(), index_path = index_path.display());
+            Ok(())
         }
 
         _ => {
@@ -282,12 +2210,4 @@ pub fn entry() -> Result<(), ()> {
     }
 }
 
-fn main() -> ExitCode {
-    match entry() {
-        Ok(()) => ExitCode::SUCCESS,
-        Err(()) => ExitCode::FAILURE,
-    }
-}
-
 // TODO: search result must consist of clickable links
-// TODO: synonym terms
@@ -0,0 +1,82 @@
+//! Process-wide log level for khoj's informational output.
+//! Errors are always printed; `--quiet` hides everything else and `--verbose` adds per-file traces.
+//! While the TUI draws on the terminal nothing is printed at all, see `set_silent`.
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Errors only
+    Quiet = 0,
+    /// Errors, warnings and progress/status messages (the default)
+    Normal = 1,
+    /// Everything above plus per-file indexing traces
+    Verbose = 2,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Drops every message, errors included, while `silent`. The TUI turns this on while it owns
+/// the terminal, since a parser complaining about a broken PDF would print over its screen.
+pub fn set_silent(silent: bool) {
+    SILENT.store(silent, Ordering::Relaxed);
+}
+
+pub fn silent() -> bool {
+    SILENT.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if messages of `level` should be printed.
+pub fn enabled(level: Level) -> bool {
+    !silent() && LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Picks the level from `--quiet`/`-q` and `--verbose`/`-v` in `args`; the last one given wins.
+pub fn level_from_args(args: &[String]) -> Level {
+    args.iter().fold(Level::Normal, |level, arg| match arg.as_str() {
+        "--quiet" | "-q" => Level::Quiet,
+        "--verbose" | "-v" => Level::Verbose,
+        _ => level,
+    })
+}
+
+/// Prints an informational message to stdout unless running with `--quiet`.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Normal) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints an error to stderr, unless the TUI is drawing (see `set_silent`).
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if !$crate::logging::silent() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a warning to stderr unless running with `--quiet`.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Normal) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a per-file trace to stdout, only when running with `--verbose`.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Verbose) {
+            println!($($arg)*);
+        }
+    };
+}
@@ -1,16 +1,405 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
 use std::path::{PathBuf, Path};
 use serde::{Deserialize, Serialize};
 use super::lexer::Lexer;
-use std::time::SystemTime;
+pub use super::lexer::TokenizerConfig;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub type DocFreq = HashMap<String, usize>;
 pub type TermFreq = HashMap<String, usize>;
 
+/// A filter on document metadata, written as `field:value` in a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldFilter {
+    /// `ext:md` - the file extension equals the value (case-insensitive)
+    Ext(String),
+    /// `path:notes` - the path contains the value (case-insensitive)
+    Path(String),
+    /// `tag:recipes` - the front matter of the note lists the value as a tag (case-insensitive)
+    Tag(String),
+    /// `since:2024-01-01` - a log (see `--logs`) has lines from this time on; like `--since`,
+    /// the value may also be a duration such as `12h`, or Unix seconds
+    Since(u64),
+    /// `until:2024-01-31` - a log has lines from before the end of this time; a date means
+    /// its whole day
+    Until(u64),
+}
+
+impl FieldFilter {
+    fn parse(token: &str) -> Option<Self> {
+        let (field, value) = token.split_once(':')?;
+        if value.is_empty() {
+            return None;
+        }
+        match field.to_lowercase().as_str() {
+            "ext" => Some(Self::Ext(value.trim_start_matches('.').to_lowercase())),
+            "path" => Some(Self::Path(value.to_lowercase())),
+            "tag" => Some(Self::Tag(value.trim_start_matches('#').to_lowercase())),
+            "since" => Some(Self::Since(unix_seconds(crate::parse_since(value, SystemTime::now())?))),
+            "until" => {
+                let until = unix_seconds(crate::parse_since(value, SystemTime::now())?);
+                let date_only = value.len() == "YYYY-MM-DD".len() && value.contains('-');
+                Some(Self::Until(if date_only { until + 24 * 60 * 60 - 1 } else { until }))
+            }
+            _ => None,
+        }
+    }
+
+    /// `time_range` is that of the lines of the document if it's a log, see `Model::set_time_ranges`.
+    fn matches(&self, path: &Path, tags: &[String], time_range: Option<(u64, u64)>) -> bool {
+        match self {
+            Self::Since(since) => time_range.is_some_and(|(_, last)| last >= *since),
+            Self::Until(until) => time_range.is_some_and(|(first, _)| first <= *until),
+            Self::Ext(ext) => path.extension()
+                .map(|e| e.to_string_lossy().to_lowercase() == *ext)
+                .unwrap_or(false),
+            Self::Path(needle) => path.to_string_lossy().to_lowercase().contains(needle.as_str()),
+            Self::Tag(tag) => tags.contains(tag),
+        }
+    }
+
+    fn same_field(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// A query with its `field:value` filters separated from the free text that gets ranked.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedQuery {
+    pub text: Vec<char>,
+    pub filters: Vec<FieldFilter>,
+}
+
+impl ParsedQuery {
+    /// Filters on the same field are alternatives (`ext:md ext:txt`), different fields must all match.
+    /// `tags` are those of the file's front matter, see `Model::tags`, and `time_range` the
+    /// times of a log's lines, see `Model::time_range`.
+    pub fn matches_filters(&self, path: &Path, tags: &[String], time_range: Option<(u64, u64)>) -> bool {
+        self.filters.iter().all(|filter| {
+            self.filters.iter()
+                .filter(|other| other.same_field(filter))
+                .any(|other| other.matches(path, tags, time_range))
+        })
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Splits recognized `field:value` tokens out of `query`. Tokens with unknown fields
+/// stay in the text and are searched literally.
+pub fn parse_query(query: &[char]) -> ParsedQuery {
+    let query: String = query.iter().collect();
+    let mut parsed = ParsedQuery::default();
+    let mut text = Vec::new();
+    for word in query.split_whitespace() {
+        match FieldFilter::parse(word) {
+            Some(filter) => parsed.filters.push(filter),
+            None => text.push(word),
+        }
+    }
+    parsed.text = text.join(" ").chars().collect();
+    parsed
+}
+
+/// Separates the file path from the chunk number in the keys of chunked documents (`notes.md#3`)
+const CHUNK_SEPARATOR: char = '#';
+
+/// Key of chunk `index` of `file_path` in `Model::docs`.
+pub fn chunk_key(file_path: &Path, index: usize) -> PathBuf {
+    let mut key = file_path.as_os_str().to_owned();
+    key.push(format!("{CHUNK_SEPARATOR}{index}"));
+    PathBuf::from(key)
+}
+
+/// Splits a key of `Model::docs` into the file path and, for chunks of a long file,
+/// the chunk number.
+pub fn split_chunk_key(key: &Path) -> (&Path, Option<usize>) {
+    let chunk = key.to_str()
+        .and_then(|key| key.rsplit_once(CHUNK_SEPARATOR))
+        .filter(|(_, index)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|(path, index)| Some((Path::new(path), index.parse().ok()?)));
+    match chunk {
+        Some((path, index)) => (path, Some(index)),
+        None => (key, None),
+    }
+}
+
+/// Token statistics of one chunk of a long file, see `Model::add_chunked_document`.
+pub struct Chunk {
+    /// 0-based line of the file the chunk starts at
+    pub first_line: usize,
+    pub count: usize,
+    pub tf: TermFreq,
+    pub positions: HashMap<String, Vec<usize>>,
+    /// Occurrences of terms in headings, see `compute_heading_tf`
+    pub heading_tf: TermFreq,
+}
+
+/// How results with equal scores are ordered. Either way the order doesn't depend on
+/// `HashMap` iteration order, so the same query always lists results the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// By path, ascending
+    #[default]
+    Path,
+    /// Most recently modified first, then by path
+    Recency,
+}
+
+impl TieBreak {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "path" => Some(Self::Path),
+            "recency" => Some(Self::Recency),
+            _ => None,
+        }
+    }
+}
+
+/// How the inverse document frequency of a term is computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdfFormula {
+    /// `log10(n / m)`: a term found in every document is worth nothing
+    #[default]
+    Classic,
+    /// BM25-style `ln((n - m + 0.5) / (m + 0.5) + 1)`: always positive, so results for a
+    /// term found everywhere are still ordered by how often each document uses it
+    Smoothed,
+}
+
+impl IdfFormula {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::Classic),
+            "smoothed" => Some(Self::Smoothed),
+            _ => None,
+        }
+    }
+}
+
+/// Search settings. They don't change what is indexed, so they aren't saved with the index.
+#[derive(Clone, Copy, Debug)]
+pub struct RankingParams {
+    pub idf: IdfFormula,
+    pub tie_break: TieBreak,
+    /// Extra occurrences a term in a markdown heading counts as, on top of itself
+    pub heading_boost: f32,
+    /// Weight of a query term found in the file name (without extension), relative to a
+    /// document made of nothing but that term
+    pub name_weight: f32,
+    /// Like `name_weight`, for the names of the directories below the indexed folder
+    pub directory_weight: f32,
+    /// Scale ranks by `Doc::uniqueness`, so boilerplate found in many files ranks lower
+    pub penalize_duplicates: bool,
+    /// Scale ranks of multi-word queries by how many of the words a document has and boost
+    /// documents with the exact phrase. Off gives the plain sum of the words' tf-idf.
+    pub boosts: bool,
+    /// How much less later occurrences of a term count: one at relative position `r` of the
+    /// document (0 at the start, 1 at the end) counts `e^(-position_decay * r)` times. 0, the
+    /// default, counts them all alike. Chunks of a long file are documents of their own here.
+    pub position_decay: f32,
+}
+
+pub const DEFAULT_HEADING_BOOST: f32 = 3.0;
+pub const DEFAULT_NAME_WEIGHT: f32 = 1.0;
+pub const DEFAULT_DIRECTORY_WEIGHT: f32 = 0.25;
+
+impl Default for RankingParams {
+    fn default() -> Self {
+        Self {
+            idf: IdfFormula::default(),
+            tie_break: TieBreak::default(),
+            heading_boost: DEFAULT_HEADING_BOOST,
+            name_weight: DEFAULT_NAME_WEIGHT,
+            directory_weight: DEFAULT_DIRECTORY_WEIGHT,
+            penalize_duplicates: false,
+            boosts: true,
+            position_decay: 0.0,
+        }
+    }
+}
+
+/// Groups of interchangeable words or phrases, e.g. `car, automobile` or `ml = machine learning`.
+/// A query containing one entry of a group also matches the others (see `SYNONYM_WEIGHT`).
+#[derive(Clone, Debug, Default)]
+pub struct Synonyms {
+    groups: Vec<Vec<String>>,
+}
+
+/// Share of a literal match's score given to a match of one of its synonyms
+const SYNONYM_WEIGHT: f32 = 0.5;
+
+impl Synonyms {
+    /// Parses one group per line, its entries separated by `,` or `=`. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Self {
+        let groups = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split([',', '='])
+                .map(|entry| entry.trim().to_lowercase())
+                .filter(|entry| !entry.is_empty())
+                .collect::<Vec<_>>())
+            .filter(|group| group.len() > 1)
+            .collect();
+        Synonyms { groups }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Finds the entries of each group in the query `tokens`. Returns the range of tokens each
+    /// occurrence covers with the other entries of its group, tokenized by `tokenizer`.
+    fn expand(&self, tokens: &[String], tokenizer: TokenizerConfig) -> Vec<(Range<usize>, Vec<Vec<String>>)> {
+        let mut expansions = Vec::new();
+        for group in &self.groups {
+            let entries: Vec<Vec<String>> = group.iter()
+                .map(|entry| Lexer::new(&entry.chars().collect::<Vec<_>>(), tokenizer).collect::<Vec<_>>())
+                .filter(|entry| !entry.is_empty())
+                .collect();
+            for entry in &entries {
+                let alternatives: Vec<Vec<String>> = entries.iter().filter(|other| *other != entry).cloned().collect();
+                if alternatives.is_empty() {
+                    continue;
+                }
+                for (start, window) in tokens.windows(entry.len()).enumerate() {
+                    if window == entry.as_slice() {
+                        expansions.push((start..start + entry.len(), alternatives.clone()));
+                    }
+                }
+            }
+        }
+        expansions
+    }
+}
+
+/// The words of a query as tokenized for one kind of document (see `TokenizerConfig::for_path`).
+struct QueryTerms {
+    tokens: Vec<String>,
+    /// Distinct tokens, for the multi-term coverage boost
+    distinct: HashSet<String>,
+    /// Synonyms of spans of `tokens` (see `Synonyms::expand`)
+    expansions: Vec<(Range<usize>, Vec<Vec<String>>)>,
+}
+
+impl QueryTerms {
+    fn new(text: &[char], tokenizer: TokenizerConfig, synonyms: &Synonyms) -> Self {
+        let tokens = Lexer::new(text, tokenizer).collect::<Vec<_>>();
+        let distinct = tokens.iter().cloned().collect();
+        let expansions = synonyms.expand(&tokens, tokenizer);
+        Self { tokens, distinct, expansions }
+    }
+}
+
+/// The index turned inside out, as written by `khoj export`: for every term, the documents
+/// that contain it. Unlike `Model` this layout is documented and kept stable for other tools.
+#[derive(Serialize)]
+pub struct InvertedIndex<'a> {
+    /// How the terms were produced from the text
+    pub tokenizer: TokenizerConfig,
+    /// Number of indexed files
+    pub files: usize,
+    /// Terms in alphabetical order, each with its documents ordered by path
+    pub terms: BTreeMap<&'a str, Vec<Posting<'a>>>,
+}
+
+/// A document containing a term, see `InvertedIndex`.
+#[derive(Serialize)]
+pub struct Posting<'a> {
+    pub path: &'a Path,
+    /// Occurrences of the term in the document
+    pub tf: usize,
+    /// For files indexed in chunks (`--chunk-lines`), the 0-based line the chunk starts at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_line: Option<usize>,
+}
+
+/// An indexed file, as listed by the server's `/api/files`.
+#[derive(Serialize)]
+pub struct IndexedFile<'a> {
+    pub path: &'a Path,
+    /// Number of tokens, summed over all chunks
+    pub tokens: usize,
+    /// Modification time the file was indexed at, in seconds since the Unix epoch
+    pub last_modified: u64,
+}
+
+/// An inconsistency between the statistics stored in an index, found by `Model::check`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexProblem {
+    /// `df` says `stored` files contain `term`, but `actual` do
+    DocFreq { term: String, stored: usize, actual: usize },
+    /// The document's `count` isn't the sum of its term frequencies, `actual`
+    Count { key: PathBuf, stored: usize, actual: usize },
+    /// The document's term positions don't match its term frequencies or count
+    Positions { key: PathBuf },
+    /// The document has no tokens, which should have kept it out of the index
+    Empty { key: PathBuf },
+}
+
+impl fmt::Display for IndexProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexProblem::DocFreq { term, stored, actual } => {
+                write!(f, "document frequency of {term:?} is {stored}, but {actual} files contain it")
+            }
+            IndexProblem::Count { key, stored, actual } => {
+                write!(f, "{}: token count is {stored}, but its term frequencies add up to {actual}", key.display())
+            }
+            IndexProblem::Positions { key } => {
+                write!(f, "{}: term positions don't match its term frequencies", key.display())
+            }
+            IndexProblem::Empty { key } => write!(f, "{}: has no tokens", key.display()),
+        }
+    }
+}
+
 #[derive(Default, Serialize, Deserialize, Clone)]
 pub struct Model {
     pub docs: HashMap<PathBuf, Doc>,
+    /// Number of files containing each term; the chunks of a file count as one
     pub df: DocFreq,
+    #[serde(skip)]
+    pub ranking: RankingParams,
+    /// Query expansions from `--synonyms`; like `ranking`, a search-time setting
+    #[serde(skip)]
+    pub synonyms: Synonyms,
+    /// How the documents were tokenized; queries are tokenized the same way
+    #[serde(default = "TokenizerConfig::legacy")]
+    pub tokenizer: TokenizerConfig,
+    /// What each directory of the last walk contained, so unchanged ones needn't be read again
+    /// (see `add_folder_to_model`)
+    #[serde(default)]
+    pub dirs: HashMap<PathBuf, DirListing>,
+    /// Whether the last walk changed `dirs`, so the index is worth saving even if no file changed
+    #[serde(skip)]
+    pub dirs_changed: bool,
+    /// Stem -> the shortest word of the documents it was stemmed from, so suggestions show
+    /// "penalty" rather than "penalti". Terms that are words as written aren't listed.
+    #[serde(default)]
+    pub surface_forms: HashMap<String, String>,
+    /// Files left out of `docs` for having no tokens, like empty ones, with their modification
+    /// time, so they aren't parsed again until they change
+    #[serde(default)]
+    pub empty_files: HashMap<PathBuf, SystemTime>,
+}
+
+/// The entries of a directory when it was last read, whether indexed or not
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirListing {
+    /// Modification time of the directory itself, which changes when an entry is added,
+    /// removed or renamed, but not when a file in it is written to
+    pub modified: SystemTime,
+    /// Names of the regular files in it
+    pub files: Vec<String>,
+    /// Names of the directories in it
+    pub dirs: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -20,41 +409,520 @@ pub struct Doc {
     last_modified: SystemTime,
     #[serde(default)]
     positions: HashMap<String, Vec<usize>>, // token -> positions in sequence
+    /// 0-based line of the file this document starts at; only non-zero for chunks
+    #[serde(default)]
+    first_line: usize,
+    /// Hash of the file's bytes, used to recognize it after a rename (see `rename_document`).
+    /// Only kept on the document of the whole file or its first chunk.
+    #[serde(default)]
+    content_hash: Option<u64>,
+    /// Occurrences of terms in markdown headings; they're also counted in `tf`
+    #[serde(default)]
+    heading_tf: TermFreq,
+    /// Lowercased tags from the front matter of a markdown file, for `tag:` filters.
+    /// Only kept on the document of the whole file or its first chunk, like `content_hash`.
+    #[serde(default)]
+    tags: Vec<String>,
+    /// First and last time, in Unix seconds, of the lines of a log that start with one
+    /// (see `Model::set_time_ranges`); `None` for other files
+    #[serde(default)]
+    time_range: Option<(u64, u64)>,
+    /// Min-hash signature of the document's word sequences (see `signature`), filled in by
+    /// `update_uniqueness`; empty in indexes built without `--penalize-duplicates`
+    #[serde(default)]
+    signature: Vec<u32>,
+    /// 1 for a document unlike any other, down to almost 0 for one of many near-duplicates
+    /// like license headers. Recomputed for the whole index by `update_uniqueness`.
+    #[serde(default = "full_uniqueness")]
+    uniqueness: f32,
+    /// Tokens of the file's name and directories, so queries needn't tokenize them again.
+    /// Only kept on the document of the whole file or its first chunk, like `content_hash`;
+    /// `None` in indexes built before it existed.
+    #[serde(default)]
+    path_tokens: Option<PathTokens>,
+}
+
+/// Tokens of the path of a file, scored with `--name-weight` and `--directory-weight`
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct PathTokens {
+    /// Tokens of the file name without its extension
+    stem: Vec<String>,
+    /// Tokens of each directory the file is in, outermost first, one entry per path component
+    directories: Vec<Vec<String>>,
+}
+
+impl PathTokens {
+    fn new(path: &Path, tokenizer: TokenizerConfig) -> Self {
+        let tokenize = |text: &std::ffi::OsStr| Lexer::new(&text.to_string_lossy().chars().collect::<Vec<_>>(), tokenizer).collect::<Vec<_>>();
+        PathTokens {
+            stem: path.file_stem().map(tokenize).unwrap_or_default(),
+            directories: path.parent().map(|parent| parent.iter().map(tokenize).collect()).unwrap_or_default(),
+        }
+    }
+
+    /// Tokens of the directories below the first `skip` components of the path.
+    fn directories_below(&self, skip: usize) -> Vec<&str> {
+        self.directories.iter().skip(skip).flatten().map(String::as_str).collect()
+    }
+}
+
+fn full_uniqueness() -> f32 {
+    1.0
+}
+
+/// Number of min-hashes in a document's signature
+const SIGNATURE_LEN: usize = 32;
+/// Signature rows per band: documents whose signatures agree on all rows of a band are
+/// counted as near-duplicates. 8 rows make that likely above ~85% similarity and rare below 60%.
+const SIGNATURE_BAND_ROWS: usize = 8;
+/// Consecutive words hashed together, so documents must share phrases to look alike
+const SHINGLE_LEN: usize = 3;
+
+/// Min-hash signature of the word `SHINGLE_LEN`-grams of a document, using one permutation:
+/// each shingle is hashed once into one of `SIGNATURE_LEN` buckets, which keep their smallest
+/// hash. Buckets no shingle fell into, as in short documents, hold `u32::MAX`.
+/// Signatures are saved in the index, so the hashes must not change between builds.
+fn signature(count: usize, positions: &HashMap<String, Vec<usize>>) -> Vec<u32> {
+    if positions.is_empty() {
+        return Vec::new();
+    }
+    let mut sequence = vec![0u64; count];
+    for (term, term_positions) in positions {
+        // FNV-1a, like `hash_file`
+        let hash = term.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        for &position in term_positions {
+            if let Some(slot) = sequence.get_mut(position) {
+                *slot = hash;
+            }
+        }
+    }
+    let mut signature = vec![u32::MAX; SIGNATURE_LEN];
+    for shingle in sequence.windows(SHINGLE_LEN.min(count).max(1)) {
+        let hash = shingle.iter().fold(0u64, |acc, &h| mix(acc.rotate_left(21) ^ h));
+        let bucket = (hash % SIGNATURE_LEN as u64) as usize;
+        signature[bucket] = signature[bucket].min((hash >> 32) as u32);
+    }
+    signature
+}
+
+/// A hash of each band of `signature` and its number. Bands with rows left empty by short
+/// documents are skipped, since they would make unrelated documents look alike.
+fn signature_bands(signature: &[u32]) -> impl Iterator<Item = u64> + '_ {
+    signature.chunks_exact(SIGNATURE_BAND_ROWS).enumerate()
+        .filter(|(_, rows)| !rows.contains(&u32::MAX))
+        .map(|(band, rows)| rows.iter().fold(band as u64, |hash, &row| mix(hash.rotate_left(21) ^ row as u64)))
+}
+
+/// The splitmix64 finalizer, which spreads the bits of `x` over the whole hash
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
 }
 
 impl Model {
-    fn remove_document(&mut self, file_path: &Path) {
-        if let Some(doc) = self.docs.remove(file_path) {
-            for t in doc.tf.keys() {
-                if let Some(f) = self.df.get_mut(t) {
-                    *f -= 1;
+    /// Removes the document of `file_path`, or all of its chunks if it was indexed in chunks.
+    /// Returns whether there was anything to remove.
+    fn remove_document(&mut self, file_path: &Path) -> bool {
+        let mut removed: Vec<Doc> = self.docs.remove(file_path).into_iter().collect();
+        for index in 0.. {
+            let Some(doc) = self.docs.remove(&chunk_key(file_path, index)) else { break };
+            removed.push(doc);
+        }
+        // `df` counts files, so a term in several chunks of the file counts once
+        let terms: HashSet<&String> = removed.iter().flat_map(|doc| doc.tf.keys()).collect();
+        for t in terms {
+            if let Some(f) = self.df.get_mut(t) {
+                *f -= 1;
+                // A zero document frequency would make the idf of the term infinite
+                if *f == 0 {
+                    self.df.remove(t);
+                    self.surface_forms.remove(t);
                 }
             }
         }
+        !removed.is_empty()
+    }
+
+    /// Switches to the `requested` tokenizer if the index is still empty. A populated index
+    /// keeps the tokenizer it was built with; returns `false` if that differs from `requested`.
+    pub fn request_tokenizer(&mut self, requested: TokenizerConfig) -> bool {
+        if self.docs.is_empty() {
+            self.tokenizer = requested;
+        }
+        self.tokenizer == requested
+    }
+
+    /// Rough number of bytes the index takes in memory, from its documents, the terms of each
+    /// and of the vocabulary, and the positions of every token. Meant to warn before the index
+    /// outgrows the machine (`--max-memory`), not to be exact.
+    pub fn estimated_memory(&self) -> usize {
+        /// A document besides its terms: its key, its maps and their tables
+        const DOC_BYTES: usize = 256;
+        /// A term in a map: the string and its buffer, the value and the table's slack
+        const TERM_BYTES: usize = 64;
+        let docs: usize = self.docs.iter().map(|(key, doc)| {
+            let path_terms = doc.path_tokens.as_ref().map_or(0, |path_tokens| path_tokens.stem.len() + path_tokens.directories.iter().map(Vec::len).sum::<usize>());
+            let terms = doc.tf.len() + doc.positions.len() + doc.heading_tf.len() + path_terms;
+            DOC_BYTES + key.as_os_str().len()
+                + terms * TERM_BYTES
+                + doc.count * std::mem::size_of::<usize>()
+                + doc.signature.len() * std::mem::size_of::<u32>()
+        }).sum();
+        let empty_files: usize = self.empty_files.keys().map(|path| TERM_BYTES + path.as_os_str().len()).sum();
+        docs + (self.df.len() + self.surface_forms.len()) * TERM_BYTES + empty_files
+    }
+
+    /// Number of indexed files, counting a file indexed in chunks once.
+    pub fn file_count(&self) -> usize {
+        self.docs.keys().filter(|key| matches!(split_chunk_key(key).1, None | Some(0))).count()
+    }
+
+    /// Every indexed file for which `keep` returns `true`, ordered by path.
+    pub fn indexed_files(&self, keep: impl Fn(&Path) -> bool) -> Vec<IndexedFile<'_>> {
+        let mut files: BTreeMap<&Path, IndexedFile> = BTreeMap::new();
+        for (key, doc) in &self.docs {
+            let path = split_chunk_key(key).0;
+            if !keep(path) {
+                continue;
+            }
+            let last_modified = doc.last_modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            files.entry(path)
+                .or_insert(IndexedFile { path, tokens: 0, last_modified })
+                .tokens += doc.count;
+        }
+        files.into_values().collect()
+    }
+
+    /// Removes every file for which `keep` returns `false`. Returns how many were removed.
+    pub fn retain_documents(&mut self, keep: impl Fn(&Path) -> bool) -> usize {
+        let stale: HashSet<PathBuf> = self.docs.keys()
+            .map(|key| split_chunk_key(key).0)
+            .filter(|path| !keep(path))
+            .map(Path::to_path_buf)
+            .collect();
+        for path in &stale {
+            self.remove_document(path);
+        }
+        let empty_files = self.empty_files.len();
+        self.empty_files.retain(|path, _| keep(path));
+        stale.len() + empty_files - self.empty_files.len()
+    }
+
+    /// The document of `file_path`, or its first chunk if it was indexed in chunks.
+    fn file_doc(&self, file_path: &Path) -> Option<&Doc> {
+        self.docs.get(file_path).or_else(|| self.docs.get(&chunk_key(file_path, 0)))
+    }
+
+    /// The document of `file_path`, or all of its chunks if it was indexed in chunks.
+    fn file_docs(&self, file_path: &Path) -> Vec<&Doc> {
+        match self.docs.get(file_path) {
+            Some(doc) => vec![doc],
+            None => (0..).map_while(|index| self.docs.get(&chunk_key(file_path, index))).collect(),
+        }
+    }
+
+    /// Records the hash of the bytes of the indexed `file_path`, see `rename_document`.
+    pub fn set_content_hash(&mut self, file_path: &Path, hash: u64) {
+        let key = if self.docs.contains_key(file_path) { file_path.to_path_buf() } else { chunk_key(file_path, 0) };
+        if let Some(doc) = self.docs.get_mut(&key) {
+            doc.content_hash = Some(hash);
+        }
+    }
+
+    /// Records the front matter tags of the indexed `file_path`, see `FieldFilter::Tag`.
+    pub fn set_tags(&mut self, file_path: &Path, tags: Vec<String>) {
+        let key = if self.docs.contains_key(file_path) { file_path.to_path_buf() } else { chunk_key(file_path, 0) };
+        if let Some(doc) = self.docs.get_mut(&key) {
+            doc.tags = tags;
+        }
+    }
+
+    /// Records the times of the lines of the log `file_path`, one per line, `None` for lines
+    /// without a time. Each of its documents gets the range of its own lines, those from its
+    /// first line on, at most `lines_per_doc` of them.
+    pub fn set_time_ranges(&mut self, file_path: &Path, line_times: &[Option<u64>], lines_per_doc: usize) {
+        if line_times.is_empty() {
+            return;
+        }
+        let time_range = |first_line: usize| {
+            let lines = line_times.iter().skip(first_line).take(lines_per_doc).flatten();
+            lines.fold(None, |range: Option<(u64, u64)>, &time| match range {
+                Some((first, last)) => Some((first.min(time), last.max(time))),
+                None => Some((time, time)),
+            })
+        };
+        if let Some(doc) = self.docs.get_mut(file_path) {
+            doc.time_range = time_range(0);
+            return;
+        }
+        for index in 0.. {
+            let Some(doc) = self.docs.get_mut(&chunk_key(file_path, index)) else { break };
+            doc.time_range = time_range(doc.first_line);
+        }
+    }
+
+    /// The times recorded by `set_time_ranges` for `file_path`, over all of its chunks.
+    pub fn time_range(&self, file_path: &Path) -> Option<(u64, u64)> {
+        self.file_docs(file_path).iter().filter_map(|doc| doc.time_range).reduce(|(first1, last1), (first2, last2)| (first1.min(first2), last1.max(last2)))
+    }
+
+    /// The tags recorded by `set_tags` for `file_path`.
+    pub fn tags(&self, file_path: &Path) -> &[String] {
+        self.file_doc(file_path).map_or(&[], |doc| doc.tags.as_slice())
+    }
+
+    /// The hash recorded by `set_content_hash` for `file_path`, if any.
+    pub fn content_hash(&self, file_path: &Path) -> Option<u64> {
+        self.file_doc(file_path).and_then(|doc| doc.content_hash)
+    }
+
+    /// Moves the document of `from`, or all of its chunks, to `to` without re-tokenizing it.
+    /// Document frequencies are unaffected since the content is the same. Returns whether
+    /// `from` was indexed.
+    pub fn rename_document(&mut self, from: &Path, to: &Path, last_modified: SystemTime) -> bool {
+        if self.file_doc(from).is_none() {
+            return false;
+        }
+        self.remove_document(to);
+        self.empty_files.remove(to);
+        let mut moved = Vec::new();
+        if let Some(doc) = self.docs.remove(from) {
+            moved.push((to.to_path_buf(), doc));
+        }
+        for index in 0.. {
+            let Some(doc) = self.docs.remove(&chunk_key(from, index)) else { break };
+            moved.push((chunk_key(to, index), doc));
+        }
+        if let Some((_, doc)) = moved.first_mut() {
+            doc.path_tokens = Some(PathTokens::new(to, self.tokenizer.for_path(to)));
+        }
+        for (key, mut doc) in moved {
+            doc.last_modified = last_modified;
+            self.docs.insert(key, doc);
+        }
+        true
+    }
+
+    /// Orders two equally ranked files according to `ranking.tie_break`. Files that aren't
+    /// indexed count as the oldest.
+    pub fn tie_break_cmp(&self, a: &Path, b: &Path) -> Ordering {
+        match self.ranking.tie_break {
+            TieBreak::Path => a.cmp(b),
+            TieBreak::Recency => self.recency_cmp(a, b),
+        }
+    }
+
+    /// Orders two files newest first, then by path. Files that aren't indexed count as the oldest.
+    pub fn recency_cmp(&self, a: &Path, b: &Path) -> Ordering {
+        let modified = |path| self.file_doc(path).map(|doc| doc.last_modified);
+        modified(b).cmp(&modified(a)).then_with(|| a.cmp(b))
     }
 
     pub fn requires_reindexing(&mut self, file_path: &Path, last_modified: SystemTime) -> bool {
-        if let Some(doc) = self.docs.get(file_path) {
+        if let Some(doc) = self.file_doc(file_path) {
             return doc.last_modified < last_modified;
         }
+        if let Some(&empty_modified) = self.empty_files.get(file_path) {
+            return empty_modified < last_modified;
+        }
         return true;
     }
 
+    /// Ranks the indexed files against `query`. Chunked files are ranked by their best chunk.
     pub fn search_query(&self, query: &[char]) -> Vec<(PathBuf, f32)> {
-        let mut result = Vec::new();
-        let tokens = Lexer::new(&query).collect::<Vec<_>>();
-        // Distinct token set for multi-term coverage boost
-        let distinct: HashSet<&str> = tokens.iter().map(|s| s.as_str()).collect();
-        let distinct_len = distinct.len().max(1) as f32;
-        for (path, doc) in &self.docs {
-            let mut rank = 0f32;
+        without_lines(self.search_query_best_chunks(query))
+    }
+
+    /// Like `search_query`, but stops ranking once `deadline` has passed and returns the files
+    /// ranked until then, which are an arbitrary part of the index. The flag tells whether the
+    /// search was cut short.
+    pub fn search_query_until(&self, query: &[char], deadline: Instant) -> (Vec<(PathBuf, f32)>, bool) {
+        let (result, truncated) = self.rank_chunks(query, |_, _| Instant::now() < deadline);
+        (without_lines(result), truncated)
+    }
+
+    /// Like `search_query` (or `search_query_until`, with a `deadline`), but every `interval`
+    /// while ranking, calls `progress` with the best `limit` files among those ranked so far
+    /// and how many documents that was, so they can be shown before the search is done.
+    pub fn search_query_progressive(
+        &self,
+        query: &[char],
+        deadline: Option<Instant>,
+        interval: Duration,
+        limit: usize,
+        mut progress: impl FnMut(Vec<(PathBuf, f32)>, usize),
+    ) -> (Vec<(PathBuf, f32)>, bool) {
+        let mut last_progress = Instant::now();
+        let (result, truncated) = self.rank_chunks(query, |ranked, best| {
+            if last_progress.elapsed() >= interval {
+                progress(without_lines(self.top_results(best, limit)), ranked);
+                // Counted from the end, so slow progress reports can't crowd out the ranking
+                last_progress = Instant::now();
+            }
+            deadline.is_none_or(|deadline| Instant::now() < deadline)
+        });
+        (without_lines(result), truncated)
+    }
+
+    /// Like `search_query`, but also returns the 0-based line where the best-ranked
+    /// chunk of each file starts (0 for files that aren't chunked).
+    pub fn search_query_best_chunks(&self, query: &[char]) -> Vec<(PathBuf, f32, usize)> {
+        self.rank_chunks(query, |_, _| true).0
+    }
+
+    /// How many times the words of `query` occur in the indexed `file_path`; a word the
+    /// query repeats counts once. Chunks overlap, so adding theirs up
+    /// would count words twice: a file indexed in chunks counts the chunk with the most.
+    pub fn match_count(&self, file_path: &Path, query: &[char]) -> usize {
+        let query = parse_query(query);
+        let tokens: HashSet<String> = Lexer::new(&query.text, self.tokenizer.for_path(file_path)).collect();
+        let count = |doc: &Doc| tokens.iter().filter_map(|token| doc.tf.get(token)).sum::<usize>();
+        if let Some(doc) = self.docs.get(file_path) {
+            return count(doc);
+        }
+        (0..).map_while(|index| self.docs.get(&chunk_key(file_path, index))).map(count).max().unwrap_or(0)
+    }
+
+    /// The 0-based lines of `text`, the content of `file_path` as it was indexed, holding a
+    /// word of `query`. They are found from the positions of the words in the index, so only
+    /// whole words count, stemmed the same way as in search. Positions that no longer hold
+    /// the word, because the file changed since it was indexed, are skipped.
+    pub fn matching_lines(&self, file_path: &Path, query: &[char], text: &str) -> Vec<usize> {
+        let query = parse_query(query);
+        let tokenizer = self.tokenizer.for_path(file_path);
+        let tokens: HashSet<String> = Lexer::new(&query.text, tokenizer).collect();
+        let lines: Vec<&str> = text.lines().collect();
+        let mut found = BTreeSet::new();
+        for doc in self.file_docs(file_path) {
+            // The line of each token of the document (or chunk), in order
+            let token_lines: Vec<(usize, String)> = lines.iter().enumerate().skip(doc.first_line)
+                .flat_map(|(index, line)| {
+                    let chars: Vec<char> = line.chars().collect();
+                    Lexer::new(&chars, tokenizer).map(|token| (index, token)).collect::<Vec<_>>()
+                })
+                .take(doc.count)
+                .collect();
             for token in &tokens {
-                rank += compute_tf(token, doc) * compute_idf(&token, self.docs.len(), &self.df);
+                let Some(positions) = doc.positions.get(token) else { continue };
+                found.extend(positions.iter()
+                    .filter_map(|&position| token_lines.get(position))
+                    .filter(|(_, at)| at == token)
+                    .map(|(line, _)| *line));
+            }
+        }
+        found.into_iter().collect()
+    }
+
+    /// The tokenizer of source code files, if they aren't tokenized like the rest (see
+    /// `TokenizerConfig::for_path`).
+    fn code_tokenizer(&self) -> Option<TokenizerConfig> {
+        self.tokenizer.varies_by_path().then_some(TokenizerConfig { stem: false, ..self.tokenizer })
+    }
+
+    /// Files containing any word of `query` and passing its field filters, however they rank.
+    /// Unlike a rank above 0, this also holds for words found in every file.
+    pub fn matching_files(&self, query: &[char]) -> HashSet<&Path> {
+        let query = parse_query(query);
+        let tokens = Lexer::new(&query.text, self.tokenizer).collect::<Vec<_>>();
+        let code_tokens = self.code_tokenizer().map(|tokenizer| Lexer::new(&query.text, tokenizer).collect::<Vec<_>>());
+        self.docs.iter()
+            .filter(|(key, doc)| {
+                let tokens = match &code_tokens {
+                    Some(code_tokens) if self.tokenizer.for_path(split_chunk_key(key).0) != self.tokenizer => code_tokens,
+                    _ => &tokens,
+                };
+                tokens.iter().any(|token| doc.tf.contains_key(token))
+            })
+            .map(|(key, doc)| (split_chunk_key(key).0, doc))
+            .filter(|(path, doc)| query.matches_filters(path, self.tags(path), doc.time_range))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// The files matching `query` at all (see `matching_files`) with their rank, newest first
+    /// regardless of the rank.
+    pub fn search_query_by_recency(&self, query: &[char]) -> Vec<(PathBuf, f32)> {
+        let matching = self.matching_files(query);
+        let mut result = self.search_query(query);
+        result.retain(|(path, _)| matching.contains(path.as_path()));
+        result.sort_by(|(a, _), (b, _)| self.recency_cmp(a, b));
+        result
+    }
+
+    /// Ranks the documents against `query`, calling `keep_going` every so often with the number
+    /// of documents ranked so far and the best rank and chunk line of each file among them.
+    /// Ranking stops early if it returns false, which the returned flag tells.
+    fn rank_chunks(&self, query: &[char], mut keep_going: impl FnMut(usize, &HashMap<&Path, (f32, usize)>) -> bool) -> (Vec<(PathBuf, f32, usize)>, bool) {
+        /// Number of documents ranked between two calls of `keep_going`
+        const CHECK_INTERVAL: usize = 256;
+
+        let mut truncated = false;
+        let mut best: HashMap<&Path, (f32, usize)> = HashMap::new();
+        let query = parse_query(query);
+        let prose_terms = QueryTerms::new(&query.text, self.tokenizer, &self.synonyms);
+        let code_terms = self.code_tokenizer().map(|tokenizer| QueryTerms::new(&query.text, tokenizer, &self.synonyms));
+        let score_paths = self.ranking.name_weight > 0.0 || self.ranking.directory_weight > 0.0;
+        // Directories every file is in, like the indexed folder itself, tell the files apart by nothing
+        let root_components = if score_paths { common_directory(self.docs.keys()).iter().count() } else { 0 };
+        // Like `df`, the idf counts files: chunks of one long file aren't separate documents
+        let files = self.file_count();
+        for (i, (key, doc)) in self.docs.iter().enumerate() {
+            if i % CHECK_INTERVAL == 0 && !keep_going(i, &best) {
+                truncated = true;
+                break;
             }
-            if distinct.len() > 1 {
+            let path = split_chunk_key(key).0;
+            if !query.matches_filters(path, self.tags(path), doc.time_range) {
+                continue;
+            }
+            let tokenizer = self.tokenizer.for_path(path);
+            let QueryTerms { tokens, distinct, expansions } = match &code_terms {
+                Some(code_terms) if tokenizer != self.tokenizer => code_terms,
+                _ => &prose_terms,
+            };
+            let computed;
+            let path_tokens = match self.file_doc(path).and_then(|doc| doc.path_tokens.as_ref()) {
+                _ if !score_paths => None,
+                Some(path_tokens) => Some(path_tokens),
+                None => {
+                    computed = PathTokens::new(path, tokenizer);
+                    Some(&computed)
+                }
+            };
+            let stem_tokens: &[String] = path_tokens.map_or(&[], |path_tokens| &path_tokens.stem);
+            let directory_tokens = path_tokens.map(|path_tokens| path_tokens.directories_below(root_components)).unwrap_or_default();
+            let in_path = |token: &str| stem_tokens.iter().any(|t| t == token) || directory_tokens.contains(&token);
+            let mut rank = 0f32;
+            let term_score = |token: &str| {
+                let in_stem = stem_tokens.iter().filter(|t| *t == token).count() as f32;
+                let in_directories = directory_tokens.iter().filter(|t| **t == token).count() as f32;
+                let tf = compute_positional_tf(token, doc, self.ranking.position_decay)
+                    + self.ranking.heading_boost * compute_heading_tf(token, doc)
+                    + self.ranking.name_weight * in_stem
+                    + self.ranking.directory_weight * in_directories;
+                tf * compute_idf(token, files, &self.df, self.ranking.idf)
+            };
+            for token in tokens {
+                rank += term_score(token);
+            }
+            // Query words whose synonym is in this doc count as present for the coverage boost
+            let mut covered: HashSet<&str> = HashSet::new();
+            for (span, alternatives) in expansions {
+                // Synonyms are OR-combined: the best one found scores, as much as one word would
+                let best = alternatives.iter()
+                    .filter(|alternative| alternative.iter().all(|t| doc.tf.contains_key(t) || in_path(t)))
+                    .map(|alternative| alternative.iter().map(|t| term_score(t)).sum::<f32>() / alternative.len() as f32)
+                    .max_by(f32::total_cmp);
+                if let Some(best) = best {
+                    rank += SYNONYM_WEIGHT * best;
+                    covered.extend(tokens[span.clone()].iter().map(String::as_str));
+                }
+            }
+            if self.ranking.boosts && distinct.len() > 1 {
                 // Count how many distinct query tokens are present in this doc
-                let present = distinct.iter().filter(|t| doc.tf.contains_key(**t)).count() as f32;
-                let coverage = present / distinct_len; // 0..1
+                let present = distinct.iter().filter(|t| doc.tf.contains_key(*t) || in_path(t) || covered.contains(t.as_str())).count() as f32;
+                let coverage = present / distinct.len() as f32; // 0..1
                 // New scheme: strong penalty for partial coverage, bonus for full coverage
                 const FULL_COVER_BONUS: f32 = 0.5; // extra 50% if all terms present
                 const PARTIAL_EXP: f32 = 2.0; // coverage exponent for partial docs
@@ -67,25 +935,228 @@ impl Model {
                 rank *= coverage_factor;
             }
             // Phrase boost: if full ordered sequence of tokens appears contiguously
-            if tokens.len() > 1 && phrase_in_doc(&tokens, doc) {
+            if self.ranking.boosts && tokens.len() > 1 && phrase_in_doc(tokens, doc) {
                 const PHRASE_BOOST: f32 = 2.0; // multiplicative boost for exact phrase
                 rank *= PHRASE_BOOST;
             }
-            // TODO: investigate the sources of NaN
-            if !rank.is_nan() {
-                result.push((path.clone(), rank));
+            if self.ranking.penalize_duplicates {
+                rank *= doc.uniqueness;
+            }
+            debug_assert!(rank.is_finite(), "rank of {} is {rank}", key.display());
+            // Collapse chunks back to their file, keeping the best one
+            let entry = best.entry(path).or_insert((rank, doc.first_line));
+            if rank > entry.0 {
+                *entry = (rank, doc.first_line);
             }
         }
-        result.sort_by(|(_, rank1), (_, rank2)| rank1.partial_cmp(rank2).expect(&format!("{rank1} and {rank2} are not comparable")));
-        result.reverse();
+        (self.top_results(&best, best.len()), truncated)
+    }
+
+    /// The best `limit` files of `best` with their rank and chunk line, best first.
+    fn top_results(&self, best: &HashMap<&Path, (f32, usize)>, limit: usize) -> Vec<(PathBuf, f32, usize)> {
+        let mut result: Vec<(&Path, f32, usize)> = best.iter()
+            .map(|(path, &(rank, first_line))| (*path, rank, first_line))
+            .collect();
+        let order = |(path1, rank1, _): &(&Path, f32, usize), (path2, rank2, _): &(&Path, f32, usize)| {
+            rank2.total_cmp(rank1).then_with(|| self.tie_break_cmp(path1, path2))
+        };
+        if limit < result.len() {
+            result.select_nth_unstable_by(limit, order);
+            result.truncate(limit);
+        }
+        result.sort_by(order);
+        result.into_iter().map(|(path, rank, first_line)| (path.to_path_buf(), rank, first_line)).collect()
+    }
+
+    /// Ranks indexed documents purely by how well their file name matches the words of
+    /// `query`, skipping the content index entirely.
+    pub fn search_filenames(&self, query: &str) -> Vec<(PathBuf, f32)> {
+        let query = parse_query(&query.chars().collect::<Vec<_>>());
+        let query_lower = query.text.iter().collect::<String>().to_lowercase();
+        let query_words: Vec<&str> = query_lower.split_whitespace().collect();
+        let paths: HashSet<&Path> = self.docs.keys().map(|key| split_chunk_key(key).0).collect();
+        let mut result: Vec<(PathBuf, f32)> = paths.into_iter()
+            .filter(|path| query.matches_filters(path, self.tags(path), self.time_range(path)))
+            .filter_map(|path| {
+                let filename_lower = path.file_name()?.to_str()?.to_lowercase();
+                let score = filename_match_score(&filename_lower, &query_words);
+                (score > 0).then(|| (path.to_path_buf(), score as f32))
+            })
+            .collect();
+        result.sort_by(|(path1, rank1), (path2, rank2)| {
+            rank2.total_cmp(rank1).then_with(|| self.tie_break_cmp(path1, path2))
+        });
         result
     }
 
-    pub fn compute_search_data(content: &[char]) -> (usize, TermFreq, HashMap<String, Vec<usize>>) {
+    /// Builds the term -> documents view of the index written by `khoj export`.
+    pub fn inverted_index(&self) -> InvertedIndex<'_> {
+        let mut terms: BTreeMap<&str, Vec<Posting>> = BTreeMap::new();
+        for (key, doc) in &self.docs {
+            let (path, chunk) = split_chunk_key(key);
+            for (term, &tf) in &doc.tf {
+                terms.entry(term.as_str()).or_default().push(Posting {
+                    path,
+                    tf,
+                    first_line: chunk.map(|_| doc.first_line),
+                });
+            }
+        }
+        for postings in terms.values_mut() {
+            postings.sort_by(|a, b| a.path.cmp(b.path).then(a.first_line.cmp(&b.first_line)));
+        }
+        InvertedIndex { tokenizer: self.tokenizer, files: self.file_count(), terms }
+    }
+
+    /// Verifies that the statistics derived from each other agree: `df` with the documents'
+    /// terms, and each document's count and positions with its term frequencies. Documents
+    /// indexed before positions were kept have none, which isn't a problem.
+    pub fn check(&self) -> Vec<IndexProblem> {
+        let mut problems = Vec::new();
+        let mut keys: Vec<&PathBuf> = self.docs.keys().collect();
+        keys.sort();
+        for key in keys {
+            let doc = &self.docs[key];
+            let actual: usize = doc.tf.values().sum();
+            if actual == 0 {
+                problems.push(IndexProblem::Empty { key: key.clone() });
+                continue;
+            }
+            if doc.count != actual {
+                problems.push(IndexProblem::Count { key: key.clone(), stored: doc.count, actual });
+            }
+            if !doc.positions.is_empty() && !positions_match(doc) {
+                problems.push(IndexProblem::Positions { key: key.clone() });
+            }
+        }
+        let actual_df = self.computed_df();
+        let mut terms: Vec<&String> = self.df.keys().chain(actual_df.keys()).collect::<HashSet<_>>().into_iter().collect();
+        terms.sort();
+        for term in terms {
+            let stored = self.df.get(term).copied().unwrap_or(0);
+            let actual = actual_df.get(term).copied().unwrap_or(0);
+            if stored != actual {
+                problems.push(IndexProblem::DocFreq { term: term.clone(), stored, actual });
+            }
+        }
+        problems
+    }
+
+    /// Fixes what `check` finds by recomputing the derived statistics: empty documents are
+    /// dropped, counts are summed from term frequencies again and `df` is rebuilt. Positions
+    /// can't be recovered without the text, so mismatching ones are dropped, which only turns
+    /// off the phrase boost for those documents until they are reindexed.
+    pub fn repair(&mut self) {
+        self.docs.retain(|_, doc| doc.tf.values().sum::<usize>() > 0);
+        for doc in self.docs.values_mut() {
+            doc.count = doc.tf.values().sum();
+            if !positions_match(doc) {
+                doc.positions.clear();
+            }
+        }
+        self.df = self.computed_df();
+        let df = &self.df;
+        self.surface_forms.retain(|term, _| df.contains_key(term));
+    }
+
+    /// Document frequencies as they follow from the documents' terms, counting the chunks
+    /// of a file as one document.
+    fn computed_df(&self) -> DocFreq {
+        let mut df = DocFreq::new();
+        let mut counted: HashSet<(&Path, &String)> = HashSet::new();
+        for (key, doc) in &self.docs {
+            let path = split_chunk_key(key).0;
+            for term in doc.tf.keys().filter(|term| counted.insert((path, term))) {
+                *df.entry(term.clone()).or_default() += 1;
+            }
+        }
+        df
+    }
+
+    /// Returns up to `limit` vocabulary words starting with `prefix`, most frequent
+    /// (by document frequency) first. Stemmed terms are matched and returned as written
+    /// (see `surface_forms`).
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<(String, usize)> {
+        let prefix = prefix.to_lowercase();
+        let mut words: HashMap<&str, usize> = HashMap::new();
+        for (term, &freq) in &self.df {
+            let word = self.surface_form(term);
+            if term.starts_with(&prefix) || word.starts_with(&prefix) {
+                let entry = words.entry(word).or_default();
+                *entry = (*entry).max(freq);
+            }
+        }
+        let mut words: Vec<(String, usize)> = words.into_iter().map(|(word, freq)| (word.to_string(), freq)).collect();
+        words.sort_by(|(w1, f1), (w2, f2)| f2.cmp(f1).then_with(|| w1.cmp(w2)));
+        words.truncate(limit);
+        words
+    }
+
+    /// The word `term` was indexed from, see `surface_forms`.
+    pub fn surface_form<'a>(&'a self, term: &'a str) -> &'a str {
+        self.surface_forms.get(term).map_or(term, String::as_str)
+    }
+
+    /// Records the words stemmed terms were indexed from, keeping the shortest for each.
+    pub fn add_surface_forms(&mut self, forms: HashMap<String, String>) {
+        for (term, word) in forms {
+            prefer_surface_form(&mut self.surface_forms, term, word);
+        }
+    }
+
+    /// Rewrites `query` with every word that isn't in the vocabulary replaced by the closest
+    /// indexed word (see `surface_forms`). Returns `None` if all words are known or nothing is close enough.
+    /// Field filters are kept as they are.
+    pub fn did_you_mean(&self, query: &str) -> Option<String> {
+        let mut changed = false;
+        let words: Vec<String> = query.split_whitespace().map(|word| {
+            if FieldFilter::parse(word).is_some() {
+                return word.to_string();
+            }
+            let chars = word.chars().collect::<Vec<_>>();
+            // Words of source code may be indexed unstemmed (`--no-stem-code`)
+            let known = |tokenizer| Lexer::new(&chars, tokenizer).all(|t| self.df.contains_key(&t));
+            if known(self.tokenizer) || self.code_tokenizer().is_some_and(known) {
+                return word.to_string();
+            }
+            // Known tokens are kept as written and corrections shown as words, not stems
+            let as_written = Lexer::new(&chars, TokenizerConfig { stem: false, ..self.tokenizer });
+            let corrected: Option<Vec<String>> = Lexer::new(&chars, self.tokenizer).zip(as_written).map(|(token, written)| {
+                if self.df.contains_key(&token) || !token.chars().all(char::is_alphabetic) {
+                    Some(written)
+                } else {
+                    self.closest_term(&token).map(|term| self.surface_form(&term).to_string())
+                }
+            }).collect();
+            match corrected {
+                Some(corrected) => {
+                    changed = true;
+                    corrected.join(" ")
+                }
+                None => word.to_string(),
+            }
+        }).collect();
+        changed.then(|| words.join(" "))
+    }
+
+    /// The vocabulary term with the smallest edit distance to `token`, preferring more common
+    /// terms on ties. Short tokens only tolerate a single edit.
+    fn closest_term(&self, token: &str) -> Option<String> {
+        let len = token.chars().count();
+        let max_distance = if len <= 4 { 1 } else { 2 };
+        self.df.iter()
+            .filter(|(term, _)| term.chars().count().abs_diff(len) <= max_distance)
+            .map(|(term, &freq)| (edit_distance(token, term), std::cmp::Reverse(freq), term))
+            .filter(|(distance, _, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, _, term)| term.clone())
+    }
+
+    pub fn compute_search_data(content: &[char], tokenizer: TokenizerConfig) -> (usize, TermFreq, HashMap<String, Vec<usize>>) {
         let mut tf = TermFreq::new();
         let mut count = 0;
         let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
-        for (idx, t) in Lexer::new(content).enumerate() {
+        for (idx, t) in Lexer::new(content, tokenizer).enumerate() {
             if let Some(f) = tf.get_mut(&t) {
                 *f += 1;
             } else {
@@ -97,43 +1168,293 @@ impl Model {
         (count, tf, positions)
     }
 
+    /// The words of `content` that `tokenizer` changes by stemming, by their term (see
+    /// `surface_forms`). Stemming keeps the tokens apart, so the stemmed and unstemmed
+    /// tokens line up one to one.
+    pub fn compute_surface_forms(content: &[char], tokenizer: TokenizerConfig) -> HashMap<String, String> {
+        let mut forms = HashMap::new();
+        if !tokenizer.stem {
+            return forms;
+        }
+        let words = Lexer::new(content, TokenizerConfig { stem: false, ..tokenizer });
+        for (term, word) in Lexer::new(content, tokenizer).zip(words) {
+            if term != word {
+                prefer_surface_form(&mut forms, term, word);
+            }
+        }
+        forms
+    }
+
+    /// Counts the terms in the headings of `content` if `file_path` is a markdown file, so
+    /// matches there can be ranked higher (see `RankingParams::heading_boost`). Headings in
+    /// fenced code blocks, like shell comments, don't count.
+    pub fn compute_heading_tf(file_path: &Path, content: &str, tokenizer: TokenizerConfig) -> TermFreq {
+        let mut heading_tf = TermFreq::new();
+        if !is_markdown(file_path) {
+            return heading_tf;
+        }
+        let mut in_fence = false;
+        for line in content.lines() {
+            let line = line.trim_start();
+            if line.starts_with("```") || line.starts_with("~~~") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence {
+                continue;
+            }
+            let Some(heading) = markdown_heading(line) else { continue };
+            for term in Lexer::new(&heading.chars().collect::<Vec<_>>(), tokenizer) {
+                *heading_tf.entry(term).or_default() += 1;
+            }
+        }
+        heading_tf
+    }
+
+    /// Indexes a document from its precomputed statistics, replacing any earlier version.
+    /// A document without any tokens, like an empty file, is only noted in `empty_files`.
+    /// Returns whether the index changed.
     pub fn add_document_precomputed(
         &mut self,
         file_path: PathBuf,
         last_modified: SystemTime,
         count: usize,
         tf: TermFreq,
-        positions: HashMap<String, Vec<usize>>
-    ) {
-        self.remove_document(&file_path);
+        positions: HashMap<String, Vec<usize>>,
+        heading_tf: TermFreq,
+    ) -> bool {
+        let removed = self.remove_document(&file_path);
+        if count == 0 {
+            return self.empty_files.insert(file_path, last_modified) != Some(last_modified) || removed;
+        }
+        self.empty_files.remove(&file_path);
+        self.insert_entries(vec![(file_path, Doc {count, tf, last_modified, positions, first_line: 0, content_hash: None, heading_tf, tags: Vec::new(), time_range: None, signature: Vec::new(), uniqueness: 1.0, path_tokens: None})]);
+        true
+    }
+
+    /// Indexes a long file as one document per chunk, keyed by `chunk_key`, so a query
+    /// matching a small part of the file isn't drowned out by the rest of it. Chunks without
+    /// any tokens are left out, like empty documents. Returns whether the index changed.
+    pub fn add_chunked_document(&mut self, file_path: PathBuf, last_modified: SystemTime, chunks: Vec<Chunk>) -> bool {
+        let removed = self.remove_document(&file_path);
+        let chunks: Vec<Chunk> = chunks.into_iter().filter(|chunk| chunk.count > 0).collect();
+        if chunks.is_empty() {
+            return self.empty_files.insert(file_path, last_modified) != Some(last_modified) || removed;
+        }
+        self.empty_files.remove(&file_path);
+        let entries = chunks.into_iter().enumerate().map(|(index, chunk)| {
+            let Chunk { first_line, count, tf, positions, heading_tf } = chunk;
+            (chunk_key(&file_path, index), Doc {count, tf, last_modified, positions, first_line, content_hash: None, heading_tf, tags: Vec::new(), time_range: None, signature: Vec::new(), uniqueness: 1.0, path_tokens: None})
+        }).collect();
+        self.insert_entries(entries);
+        true
+    }
+
+    /// Recomputes `Doc::uniqueness` from how many documents have a signature band in common
+    /// with each one, which is cheap enough to redo after every indexing pass. Documents
+    /// without a signature yet get one first.
+    pub fn update_uniqueness(&mut self) {
+        let mut bands: HashMap<u64, usize> = HashMap::new();
+        for doc in self.docs.values_mut() {
+            if doc.signature.is_empty() {
+                doc.signature = signature(doc.count, &doc.positions);
+            }
+            for band in signature_bands(&doc.signature) {
+                *bands.entry(band).or_default() += 1;
+            }
+        }
+        for doc in self.docs.values_mut() {
+            let alike = signature_bands(&doc.signature).map(|band| bands[&band]).max().unwrap_or(1);
+            doc.uniqueness = 1.0 / (alike as f32).sqrt();
+        }
+    }
 
-        for t in tf.keys() {
+    /// Drops the signatures and uniqueness `update_uniqueness` computed, for an index kept
+    /// without `--penalize-duplicates`.
+    pub fn clear_uniqueness(&mut self) {
+        for doc in self.docs.values_mut() {
+            doc.signature = Vec::new();
+            doc.uniqueness = 1.0;
+        }
+    }
+
+    /// Adds the documents of one file: the whole file or its chunks. Each of their terms
+    /// counts once towards `df`, however many chunks contain it.
+    fn insert_entries(&mut self, mut entries: Vec<(PathBuf, Doc)>) {
+        if let Some((key, doc)) = entries.first_mut() {
+            let path = split_chunk_key(key).0;
+            doc.path_tokens = Some(PathTokens::new(path, self.tokenizer.for_path(path)));
+        }
+        let terms: HashSet<&String> = entries.iter().flat_map(|(_, doc)| doc.tf.keys()).collect();
+        for t in terms {
             if let Some(f) = self.df.get_mut(t) {
                 *f += 1;
             } else {
                 self.df.insert(t.to_string(), 1);
             }
         }
-
-        self.docs.insert(file_path, Doc {count, tf, last_modified, positions});
+        self.docs.extend(entries);
     }
 
     pub fn add_document(&mut self, file_path: PathBuf, last_modified: SystemTime, content: &[char]) {
-        let (count, tf, positions) = Self::compute_search_data(content);
-        self.add_document_precomputed(file_path, last_modified, count, tf, positions);
+        let tokenizer = self.tokenizer.for_path(&file_path);
+        let (count, tf, positions) = Self::compute_search_data(content, tokenizer);
+        let heading_tf = Self::compute_heading_tf(&file_path, &content.iter().collect::<String>(), tokenizer);
+        let surface_forms = Self::compute_surface_forms(content, tokenizer);
+        if self.add_document_precomputed(file_path, last_modified, count, tf, positions, heading_tf) {
+            self.add_surface_forms(surface_forms);
+        }
+    }
+}
+
+/// Keeps `word` as the surface form of `term` if it's shorter than the one known, or
+/// alphabetically first among equally long ones, so the choice doesn't depend on file order.
+fn prefer_surface_form(forms: &mut HashMap<String, String>, term: String, word: String) {
+    match forms.get_mut(&term) {
+        Some(known) => {
+            if (word.len(), &word) < (known.len(), &*known) {
+                *known = word;
+            }
+        }
+        None => {
+            forms.insert(term, word);
+        }
+    }
+}
+
+pub(crate) fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("mdx"))
+}
+
+/// The text of an ATX heading line such as `## Installation`, without the closing `#`s.
+pub(crate) fn markdown_heading(line: &str) -> Option<&str> {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    Some(rest.trim().trim_end_matches('#').trim_end())
+}
+
+/// Ranks the files of several separately built indexes against `query` as one list. Each
+/// index scores with its own document frequencies, so its ranks are divided by its best one
+/// to make them comparable. Files without a match are left out; a file found in more than
+/// one index keeps its best rank.
+pub fn search_merged(models: &[Model], query: &[char]) -> Vec<(PathBuf, f32)> {
+    let mut merged: HashMap<PathBuf, f32> = HashMap::new();
+    for model in models {
+        let results = model.search_query(query);
+        let best = results.first().map(|(_, rank)| *rank).unwrap_or(0.0);
+        if best <= 0.0 {
+            continue;
+        }
+        for (path, rank) in results.into_iter().filter(|(_, rank)| *rank > 0.0) {
+            let rank = rank / best;
+            let entry = merged.entry(path).or_insert(rank);
+            *entry = entry.max(rank);
+        }
+    }
+    let mut result: Vec<(PathBuf, f32)> = merged.into_iter().collect();
+    result.sort_by(|(path1, rank1), (path2, rank2)| rank2.total_cmp(rank1).then_with(|| path1.cmp(path2)));
+    result
+}
+
+/// Scores a lowercase file name against lowercase query words: 100 for each word
+/// equal to the whole name, 50 for each word it merely contains.
+pub fn filename_match_score(filename_lower: &str, query_words: &[&str]) -> usize {
+    query_words.iter()
+        .filter(|word| filename_lower.contains(**word))
+        .map(|word| if filename_lower == *word { 100 } else { 50 })
+        .sum()
+}
+
+/// Levenshtein distance between `a` and `b`, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
     }
+    prev[b.len()]
 }
 
 fn compute_tf(t: &str, doc: &Doc) -> f32 {
+    // Documents without any tokens (empty files) would otherwise give 0/0
+    if doc.count == 0 {
+        return 0.0;
+    }
     let n = doc.count as f32;
     let m = doc.tf.get(t).cloned().unwrap_or(0) as f32;
     m / n
 }
 
-fn compute_idf(t: &str, n: usize, df: &DocFreq) -> f32 {
-    let n = n as f32;
-    let m = df.get(t).cloned().unwrap_or(1) as f32;
-    (n / m).log10()
+/// Like `compute_tf`, but each occurrence of `t` counts less the later it is in `doc` (see
+/// `RankingParams::position_decay`). Documents indexed before positions were kept have no
+/// positions to weigh and get the plain `compute_tf`.
+fn compute_positional_tf(t: &str, doc: &Doc, decay: f32) -> f32 {
+    let positions = doc.positions.get(t).filter(|positions| !positions.is_empty());
+    let (Some(positions), true) = (positions, decay > 0.0 && doc.count > 0) else {
+        return compute_tf(t, doc);
+    };
+    let n = doc.count as f32;
+    let weighted: f32 = positions.iter().map(|&p| (-decay * p as f32 / n).exp()).sum();
+    weighted / n
+}
+
+/// Like `compute_tf`, for the occurrences of `t` in headings.
+fn compute_heading_tf(t: &str, doc: &Doc) -> f32 {
+    if doc.count == 0 {
+        return 0.0;
+    }
+    doc.heading_tf.get(t).cloned().unwrap_or(0) as f32 / doc.count as f32
+}
+
+/// Always finite and non-negative: a missing or zero document frequency counts as 1,
+/// and a stale frequency above `n` can't push the logarithm below zero.
+fn compute_idf(t: &str, n: usize, df: &DocFreq, formula: IdfFormula) -> f32 {
+    let n = n.max(1) as f32;
+    let m = (df.get(t).cloned().unwrap_or(1).max(1) as f32).min(n);
+    match formula {
+        IdfFormula::Classic => (n / m).log10().max(0.0),
+        IdfFormula::Smoothed => ((n - m + 0.5) / (m + 0.5) + 1.0).ln(),
+    }
+}
+
+/// The deepest directory containing every file of `keys`.
+fn common_directory<'a>(keys: impl Iterator<Item = &'a PathBuf>) -> PathBuf {
+    let mut common: Option<&Path> = None;
+    for key in keys {
+        let path = split_chunk_key(key).0;
+        let mut dir = common.unwrap_or_else(|| path.parent().unwrap_or(Path::new("")));
+        while !path.starts_with(dir) {
+            dir = dir.parent().unwrap_or(Path::new(""));
+        }
+        common = Some(dir);
+    }
+    common.map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Drops the chunk lines of results of `Model::search_query_best_chunks`.
+fn without_lines(results: Vec<(PathBuf, f32, usize)>) -> Vec<(PathBuf, f32)> {
+    results.into_iter().map(|(path, rank, _)| (path, rank)).collect()
+}
+
+/// Whether every term of `doc` has as many positions as occurrences and no position is
+/// past the end of the document.
+fn positions_match(doc: &Doc) -> bool {
+    doc.positions.len() == doc.tf.len()
+        && doc.tf.iter().all(|(term, &tf)| {
+            doc.positions.get(term).is_some_and(|positions| positions.len() == tf && positions.iter().all(|&p| p < doc.count))
+        })
 }
 
 fn phrase_in_doc(tokens: &[String], doc: &Doc) -> bool {
@@ -1,10 +1,96 @@
 use std::str;
-use std::io;
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use super::model::*;
 
-use tiny_http::{Server, Request, Response, Header, Method, StatusCode};
+use tiny_http::{Server, Request, Response, Header, HTTPVersion, Method, StatusCode};
+
+/// State shared by the request handlers.
+pub struct Context {
+    pub model: Arc<Mutex<Model>>,
+    /// Set while an indexing pass over `dir_path` is running
+    pub indexing: Arc<AtomicBool>,
+    /// Folder being served
+    pub dir_path: PathBuf,
+    /// Where the index of `dir_path` is saved
+    pub index_path: PathBuf,
+    /// How `dir_path` is walked when (re)indexing
+    pub index_options: crate::IndexOptions,
+    /// Where searches are recorded, if `--query-log` was given
+    pub query_log: Option<QueryLog>,
+    /// How long a content search may take before its partial results are returned,
+    /// if `--search-timeout` was given
+    pub search_timeout: Option<Duration>,
+    /// PDF thumbnails rendered so far, so `/api/thumb` renders each version once
+    pub thumbnails: Arc<Thumbnails>,
+}
+
+/// Thumbnails `/api/thumb` has rendered, shared with the threads that render them
+#[derive(Default)]
+pub struct Thumbnails {
+    /// Default-size PNGs with the ETag of the PDF they were rendered from, by PDF
+    images: Mutex<HashMap<PathBuf, (String, Vec<u8>)>>,
+    /// How many thumbnails are being rendered right now
+    rendering: AtomicUsize,
+}
+
+/// Appends one JSON line per search to a file. Writes happen on a background thread,
+/// so a slow disk never holds up a search.
+pub struct QueryLog {
+    sender: mpsc::Sender<String>,
+}
+
+impl QueryLog {
+    pub fn open(path: &Path) -> Result<Self, ()> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| {
+            eprintln!("ERROR: could not open query log {path}: {err}", path = path.display());
+        })?;
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || write_query_log(BufWriter::new(file), receiver));
+        Ok(QueryLog { sender })
+    }
+
+    fn record(&self, query: &str, results: usize) {
+        #[derive(serde::Serialize)]
+        struct Entry<'a> {
+            /// Seconds since the Unix epoch
+            timestamp: f64,
+            query: &'a str,
+            results: usize,
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        match serde_json::to_string(&Entry { timestamp, query, results }) {
+            // The writer thread only goes away if writing failed, which it already reported
+            Ok(line) => { let _ = self.sender.send(line); }
+            Err(err) => eprintln!("ERROR: could not convert query log entry to JSON: {err}"),
+        }
+    }
+}
+
+/// Writes queued lines, flushing whenever the queue runs dry so the log can be tailed.
+fn write_query_log(mut writer: BufWriter<File>, receiver: mpsc::Receiver<String>) {
+    while let Ok(line) = receiver.recv() {
+        let mut result = writeln!(writer, "{line}");
+        while let (Ok(()), Ok(line)) = (&result, receiver.try_recv()) {
+            result = writeln!(writer, "{line}");
+        }
+        if let Err(err) = result.and_then(|()| writer.flush()) {
+            eprintln!("ERROR: could not write to the query log, disabling it: {err}");
+            return;
+        }
+    }
+}
+
+/// Maximum number of terms returned by `/api/suggest`.
+const SUGGEST_LIMIT: usize = 10;
 
 fn serve_404(request: Request) -> io::Result<()> {
     request.respond(Response::from_string("404").with_status_code(StatusCode(404)))
@@ -18,9 +104,67 @@ fn serve_400(request: Request, message: &str) -> io::Result<()> {
     request.respond(Response::from_string(format!("400: {message}")).with_status_code(StatusCode(400)))
 }
 
+/// Liveness check for load balancers. Deliberately never touches the model lock,
+/// so it answers immediately even while indexing holds it.
+fn serve_healthz(request: Request) -> io::Result<()> {
+    request.respond(Response::from_string("ok"))
+}
+
+/// Reports the version of the running build as `{"version": "..."}`.
+fn serve_version(request: Request) -> io::Result<()> {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Version {
+        version: &'static str,
+    }
+
+    let json = match serde_json::to_string(&Version { version: crate::VERSION }) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("ERROR: could not convert version to JSON: {err}");
+            return serve_500(request)
+        }
+    };
+    let content_type_header = Header::from_bytes("Content-Type", "application/json")
+        .expect("That we didn't put any garbage in the headers");
+    request.respond(Response::from_string(&json).with_header(content_type_header))
+}
+
+/// Returns the percent-decoded value of `key` in the query string of `url`, if present.
+fn query_param(url: &str, key: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query.split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(b) => { out.push(b); i += 2; }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 // TODO: the errors of serve_api_search should probably return JSON
 // 'Cause that's what expected from them.
-fn serve_api_search(model: Arc<Mutex<Model>>, mut request: Request) -> io::Result<()> {
+fn serve_api_search(context: &Context, mut request: Request) -> io::Result<()> {
     let mut buf = Vec::new();
     if let Err(err) = request.as_reader().read_to_end(&mut buf) {
         eprintln!("ERROR: could not read the body of the request: {err}");
@@ -35,10 +179,84 @@ fn serve_api_search(model: Arc<Mutex<Model>>, mut request: Request) -> io::Resul
         }
     };
 
-    let model = model.lock().unwrap();
-    let result = model.search_query(&body);
+    // `?filename_only` skips the content index and matches file names only
+    let filename_only = query_param(request.url(), "filename_only")
+        .map(|v| v != "0" && v != "false")
+        .unwrap_or(false);
+
+    // `?sort=recent` lists every file matching the query newest first instead of by rank
+    let recent_first = query_param(request.url(), "sort").is_some_and(|v| v == "recent");
+
+    // `?stream` sends the best results found so far while ranking (see `stream_search`).
+    // Chunked responses need HTTP/1.1; older clients get the usual one.
+    let stream = query_param(request.url(), "stream")
+        .map(|v| v != "0" && v != "false")
+        .unwrap_or(false);
+    if stream && !filename_only && !recent_first && *request.http_version() >= HTTPVersion(1, 1) {
+        return stream_search(context, &body, request);
+    }
+
+    // `?counts` adds how many times the query words occur in each file: `[path, rank, count]`
+    let counts = query_param(request.url(), "counts")
+        .map(|v| v != "0" && v != "false")
+        .unwrap_or(false);
+
+    let (result, truncated, match_counts) = {
+        let model = context.model.lock().unwrap();
+        let (result, truncated) = if filename_only {
+            (model.search_filenames(&body.iter().collect::<String>()), false)
+        } else if recent_first {
+            (model.search_query_by_recency(&body), false)
+        } else if let Some(timeout) = context.search_timeout {
+            model.search_query_until(&body, Instant::now() + timeout)
+        } else {
+            (model.search_query(&body), false)
+        };
+        let match_counts = counts.then(|| {
+            result.iter().take(SEARCH_RESULTS_LIMIT).map(|(path, _)| model.match_count(path, &body)).collect::<Vec<_>>()
+        });
+        (result, truncated, match_counts)
+    };
+    if truncated {
+        warn!("WARN: search for {:?} took longer than --search-timeout, returning partial results", body.iter().collect::<String>());
+    }
+    record_search(context, &body, &result);
+
+    // `?lines` adds the lines holding the query words, like grep: `[path, rank, [[line, text], ...]]`,
+    // after the count if there is one. Files are read without holding the index, and parsing
+    // one gives up after `--parse-timeout` like when indexing it.
+    let show_lines = query_param(request.url(), "lines")
+        .map(|v| v != "0" && v != "false")
+        .unwrap_or(false);
+    let match_lines = show_lines.then(|| {
+        let texts: Vec<Option<String>> = result.iter().take(SEARCH_RESULTS_LIMIT)
+            .map(|(path, _)| crate::extracted_text(path, context.index_options.parse_timeout))
+            .collect();
+        let model = context.model.lock().unwrap();
+        result.iter().zip(texts).map(|((path, _), text)| {
+            text.map(|text| crate::matching_lines(&model, path, &body, &text)).unwrap_or_default()
+        }).collect::<Vec<_>>()
+    });
 
-    let json = match serde_json::to_string(&result.iter().take(20).collect::<Vec<_>>()) {
+    let results = result.iter().take(SEARCH_RESULTS_LIMIT);
+    let json = match (match_counts, match_lines) {
+        (Some(match_counts), Some(match_lines)) => {
+            let results = results.zip(match_counts).zip(match_lines)
+                .map(|(((path, rank), count), lines)| (path, rank, count, lines))
+                .collect::<Vec<_>>();
+            serde_json::to_string(&results)
+        }
+        (Some(match_counts), None) => {
+            let results = results.zip(match_counts).map(|((path, rank), count)| (path, rank, count)).collect::<Vec<_>>();
+            serde_json::to_string(&results)
+        }
+        (None, Some(match_lines)) => {
+            let results = results.zip(match_lines).map(|((path, rank), lines)| (path, rank, lines)).collect::<Vec<_>>();
+            serde_json::to_string(&results)
+        }
+        (None, None) => serde_json::to_string(&results.collect::<Vec<_>>()),
+    };
+    let json = match json {
         Ok(json) => json,
         Err(err) => {
             eprintln!("ERROR: could not convert search results to JSON: {err}");
@@ -48,7 +266,90 @@ fn serve_api_search(model: Arc<Mutex<Model>>, mut request: Request) -> io::Resul
 
     let content_type_header = Header::from_bytes("Content-Type", "application/json")
         .expect("That we didn't put any garbage in the headers");
-    request.respond(Response::from_string(&json).with_header(content_type_header))
+    // Whether `--search-timeout` cut the search short, in a header so the body stays the same list
+    let truncated_header = Header::from_bytes("X-Khoj-Truncated", if truncated { "true" } else { "false" })
+        .expect("That we didn't put any garbage in the headers");
+    request.respond(Response::from_string(&json).with_header(content_type_header).with_header(truncated_header))
+}
+
+/// Number of results `/api/search` answers with
+const SEARCH_RESULTS_LIMIT: usize = 20;
+/// How often a streamed search sends the best results found so far
+const STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Appends the search to the `--query-log`, if there is one.
+fn record_search(context: &Context, query: &[char], result: &[(PathBuf, f32)]) {
+    if let Some(query_log) = &context.query_log {
+        // Content search also returns documents that don't match at all, with rank 0
+        let matches = result.iter().filter(|(_, rank)| *rank > 0.0).count();
+        query_log.record(&query.iter().collect::<String>(), matches);
+    }
+}
+
+/// Answers a content search with newline-delimited JSON in chunked transfer encoding, so
+/// clients can show results before ranking is done. Every `STREAM_INTERVAL` a line with the
+/// best results among the documents ranked so far is sent:
+/// `{"results": [[path, rank], ...], "ranked": N, "total": M, "done": false}`, where `N` of
+/// the `M` documents were ranked. The last line has the final results, `"done": true` and
+/// `"truncated"`, which is true if `--search-timeout` cut the search short.
+fn stream_search(context: &Context, query: &[char], request: Request) -> io::Result<()> {
+    #[derive(serde::Serialize)]
+    struct Batch<'a> {
+        results: &'a [(PathBuf, f32)],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ranked: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<usize>,
+        done: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        truncated: Option<bool>,
+    }
+
+    fn send_chunk(writer: &mut dyn Write, batch: &Batch) -> io::Result<()> {
+        let mut line = serde_json::to_vec(batch).map_err(io::Error::other)?;
+        line.push(b'\n');
+        write!(writer, "{:x}\r\n", line.len())?;
+        writer.write_all(&line)?;
+        writer.write_all(b"\r\n")?;
+        // Each batch goes out right away instead of waiting for a buffer to fill up
+        writer.flush()
+    }
+
+    let mut writer = request.into_writer();
+    writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nTransfer-Encoding: chunked\r\n\r\n")?;
+    writer.flush()?;
+
+    let deadline = context.search_timeout.map(|timeout| Instant::now() + timeout);
+    // A client that went away doesn't stop the search, only the sending
+    let mut sent = Ok(());
+    // The search hands its batches over a channel, so a slow client never holds the index lock
+    let (batches, received) = mpsc::channel();
+    let (result, truncated) = thread::scope(|scope| {
+        let search = scope.spawn(move || {
+            let model = context.model.lock().unwrap();
+            let total = model.docs.len();
+            model.search_query_progressive(query, deadline, STREAM_INTERVAL, SEARCH_RESULTS_LIMIT, |results, ranked| {
+                let _ = batches.send((results, ranked, total));
+            })
+        });
+        for (results, ranked, total) in received {
+            if sent.is_ok() {
+                sent = send_chunk(&mut *writer, &Batch { results: &results, ranked: Some(ranked), total: Some(total), done: false, truncated: None });
+            }
+        }
+        search.join().expect("the search thread panicked")
+    });
+    if truncated {
+        warn!("WARN: search for {:?} took longer than --search-timeout, returning partial results", query.iter().collect::<String>());
+    }
+    record_search(context, query, &result);
+    sent?;
+
+    let results = &result[..result.len().min(SEARCH_RESULTS_LIMIT)];
+    send_chunk(&mut *writer, &Batch { results, ranked: None, total: None, done: true, truncated: Some(truncated) })?;
+    // The empty chunk ends the response
+    writer.write_all(b"0\r\n\r\n")?;
+    writer.flush()
 }
 
 fn serve_api_stats(model: Arc<Mutex<Model>>, request: Request) -> io::Result<()> {
@@ -63,7 +364,7 @@ fn serve_api_stats(model: Arc<Mutex<Model>>, request: Request) -> io::Result<()>
     let mut stats: Stats = Default::default();
     {
         let model = model.lock().unwrap();
-        stats.docs_count = model.docs.len();
+        stats.docs_count = model.file_count();
         stats.terms_count = model.df.len();
     }
 
@@ -80,31 +381,296 @@ fn serve_api_stats(model: Arc<Mutex<Model>>, request: Request) -> io::Result<()>
     request.respond(Response::from_string(&json).with_header(content_type_header))
 }
 
-fn serve_request(model: Arc<Mutex<Model>>, request: Request) -> io::Result<()> {
-    println!("INFO: received request! method: {:?}, url: {:?}", request.method(), request.url());
+/// Reports whether indexing is still running and whether it produced an empty index,
+/// so clients can explain why every search comes back empty.
+fn serve_api_status(model: Arc<Mutex<Model>>, indexing: &AtomicBool, request: Request) -> io::Result<()> {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Status {
+        indexing: bool,
+        docs_count: usize,
+        empty_index: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<&'static str>,
+    }
+
+    let indexing = indexing.load(Ordering::SeqCst);
+    let docs_count = model.lock().unwrap().file_count();
+    let empty_index = !indexing && docs_count == 0;
+    let status = Status {
+        indexing,
+        docs_count,
+        empty_index,
+        message: empty_index.then_some(crate::EMPTY_INDEX_MESSAGE),
+    };
+
+    let json = match serde_json::to_string(&status) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("ERROR: could not convert status to JSON: {err}");
+            return serve_500(request)
+        }
+    };
+
+    let content_type_header = Header::from_bytes("Content-Type", "application/json")
+        .expect("That we didn't put any garbage in the headers");
+    request.respond(Response::from_string(&json).with_header(content_type_header))
+}
+
+fn serve_api_suggest(model: Arc<Mutex<Model>>, request: Request) -> io::Result<()> {
+    let prefix = query_param(request.url(), "prefix").unwrap_or_default();
+    let prefix = prefix.trim();
+    if prefix.is_empty() {
+        return serve_400(request, "Query parameter `prefix` must not be empty");
+    }
+
+    let suggestions: Vec<String> = {
+        let model = model.lock().unwrap();
+        model.suggest(prefix, SUGGEST_LIMIT).into_iter().map(|(word, _)| word).collect()
+    };
+
+    let json = match serde_json::to_string(&suggestions) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("ERROR: could not convert suggestions to JSON: {err}");
+            return serve_500(request)
+        }
+    };
+
+    let content_type_header = Header::from_bytes("Content-Type", "application/json")
+        .expect("That we didn't put any garbage in the headers");
+    request.respond(Response::from_string(&json).with_header(content_type_header))
+}
+
+/// Lists the indexed files with their token counts and modification times, ordered by path.
+/// `?prefix=` keeps the files whose path, relative to the served folder, starts with it.
+fn serve_api_files(context: &Context, request: Request) -> io::Result<()> {
+    let prefix = query_param(request.url(), "prefix").unwrap_or_default();
+    let matches_prefix = |path: &Path| {
+        let relative = path.strip_prefix(&context.dir_path).unwrap_or(path);
+        relative.to_string_lossy().starts_with(prefix.as_str())
+    };
+
+    let json = {
+        let model = context.model.lock().unwrap();
+        serde_json::to_string(&model.indexed_files(matches_prefix))
+    };
+    let json = match json {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("ERROR: could not convert file list to JSON: {err}");
+            return serve_500(request)
+        }
+    };
+
+    let content_type_header = Header::from_bytes("Content-Type", "application/json")
+        .expect("That we didn't put any garbage in the headers");
+    request.respond(Response::from_string(&json).with_header(content_type_header))
+}
+
+/// Width and height `/api/thumb` fits thumbnails in unless `?size=` says otherwise
+const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+/// Largest `?size=` of `/api/thumb`, so a request can't make the server render posters
+const MAX_THUMBNAIL_SIZE: u32 = 1024;
+/// Default-size thumbnails kept in memory; once there are this many the cache starts over
+const MAX_CACHED_THUMBNAILS: usize = 512;
+/// Thumbnails rendered at once; requests past this get a 503 instead of another `pdftoppm`
+const MAX_THUMBNAIL_RENDERS: usize = 4;
 
-    match (request.method(), request.url()) {
+/// Answers `?path=` with a PNG of the first page of that PDF, at most `?size=` pixels wide and
+/// high. The path may be relative to the served folder but must lie inside it. Other files get
+/// a 404. Default-size thumbnails are kept until their PDF changes and other sizes are rendered
+/// on every request, so clients may cache them for an hour and revalidate with the ETag, which
+/// changes with the file.
+fn serve_api_thumb(context: &Context, request: Request) -> io::Result<()> {
+    let Some(path) = query_param(request.url(), "path") else {
+        return serve_400(request, "Missing path parameter");
+    };
+    let size = match query_param(request.url(), "size") {
+        None => DEFAULT_THUMBNAIL_SIZE,
+        Some(size) => match size.parse::<u32>() {
+            Ok(size) if (1..=MAX_THUMBNAIL_SIZE).contains(&size) => size,
+            _ => return serve_400(request, &format!("size must be a number of pixels from 1 to {MAX_THUMBNAIL_SIZE}")),
+        },
+    };
+
+    // Resolving links and `..` first keeps paths outside the folder out, however they're written
+    let root = context.dir_path.canonicalize();
+    let file_path = context.dir_path.join(&path).canonicalize();
+    let (Ok(root), Ok(file_path)) = (root, file_path) else { return serve_404(request) };
+    let is_pdf = file_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    let Ok(metadata) = file_path.metadata() else { return serve_404(request) };
+    if !file_path.starts_with(&root) || !metadata.is_file() || !is_pdf {
+        return serve_404(request);
+    }
+
+    let modified = metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}-{:x}-{size}\"", modified.as_secs(), modified.subsec_nanos(), metadata.len());
+    let etag_header = Header::from_bytes("ETag", etag.as_bytes()).expect("That we didn't put any garbage in the headers");
+    let cache_header = Header::from_bytes("Cache-Control", "private, max-age=3600")
+        .expect("That we didn't put any garbage in the headers");
+    let unchanged = request.headers().iter()
+        .any(|header| header.field.equiv("If-None-Match") && header.value.as_str() == etag);
+    if unchanged {
+        let response = Response::empty(StatusCode(304)).with_header(etag_header).with_header(cache_header);
+        return request.respond(response);
+    }
+
+    let cacheable = size == DEFAULT_THUMBNAIL_SIZE;
+    if cacheable {
+        let cached = context.thumbnails.images.lock().unwrap().get(&file_path)
+            .filter(|(cached_etag, _)| *cached_etag == etag)
+            .map(|(_, image)| image.clone());
+        if let Some(image) = cached {
+            return request.respond(thumbnail_response(image, etag_header, cache_header));
+        }
+    }
+
+    // Rendering can take up to --parse-timeout and this thread answers every request, so
+    // `pdftoppm` runs on a thread of its own that answers once it's done
+    let thumbnails = Arc::clone(&context.thumbnails);
+    if thumbnails.rendering.fetch_add(1, Ordering::SeqCst) >= MAX_THUMBNAIL_RENDERS {
+        thumbnails.rendering.fetch_sub(1, Ordering::SeqCst);
+        let response = Response::from_string("503: too many thumbnails are being rendered, try again later")
+            .with_status_code(StatusCode(503));
+        return request.respond(response);
+    }
+    let timeout = context.index_options.parse_timeout;
+    thread::spawn(move || {
+        let image = crate::render_pdf_thumbnail(&file_path, size, timeout);
+        thumbnails.rendering.fetch_sub(1, Ordering::SeqCst);
+        let result = match image {
+            Some(image) => {
+                if cacheable {
+                    let mut images = thumbnails.images.lock().unwrap();
+                    if images.len() >= MAX_CACHED_THUMBNAILS && !images.contains_key(&file_path) {
+                        images.clear();
+                    }
+                    images.insert(file_path, (etag, image.clone()));
+                }
+                request.respond(thumbnail_response(image, etag_header, cache_header))
+            }
+            None => serve_500(request),
+        };
+        if let Err(err) = result {
+            eprintln!("ERROR: could not serve the response: {err}");
+        }
+    });
+    Ok(())
+}
+
+fn thumbnail_response(image: Vec<u8>, etag_header: Header, cache_header: Header) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type_header = Header::from_bytes("Content-Type", "image/png")
+        .expect("That we didn't put any garbage in the headers");
+    Response::from_data(image)
+        .with_header(content_type_header)
+        .with_header(etag_header)
+        .with_header(cache_header)
+}
+
+/// Re-reads the ignore rules and starts a background pass that brings the index up to date,
+/// dropping files that are now ignored. Poll `/api/status` to see when it's done.
+fn serve_api_reindex(context: &Context, request: Request) -> io::Result<()> {
+    if context.indexing.swap(true, Ordering::SeqCst) {
+        return request.respond(Response::from_string("409: indexing is already in progress").with_status_code(StatusCode(409)));
+    }
+    crate::ignore_rules::reload();
+    crate::spawn_refresh_index(context);
+    request.respond(Response::from_string("reindexing").with_status_code(StatusCode(202)))
+}
+
+fn serve_request(context: &Context, request: Request) -> io::Result<()> {
+    let model = Arc::clone(&context.model);
+    info!("INFO: received request! method: {:?}, url: {:?}", request.method(), request.url());
+
+    // Route on the path alone; handlers read query parameters from the full URL
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    match (request.method(), path.as_str()) {
+        (Method::Get, "/healthz") => {
+            serve_healthz(request)
+        }
+        (Method::Get, "/version") => {
+            serve_version(request)
+        }
         (Method::Post, "/api/search") => {
-            serve_api_search(model, request)
+            serve_api_search(context, request)
         }
         (Method::Get, "/api/stats") => {
             serve_api_stats(model, request)
         }
+        (Method::Get, "/api/status") => {
+            serve_api_status(model, &context.indexing, request)
+        }
+        (Method::Get, "/api/files") => {
+            serve_api_files(context, request)
+        }
+        (Method::Get, "/api/thumb") => {
+            serve_api_thumb(context, request)
+        }
+        (Method::Post, "/api/reindex") => {
+            serve_api_reindex(context, request)
+        }
+        (Method::Get, "/api/suggest") => {
+            serve_api_suggest(model, request)
+        }
         _ => {
             serve_404(request)
         }
     }
 }
 
-pub fn start(address: &str, model: Arc<Mutex<Model>>) -> Result<(), ()> {
-    let server = Server::http(&address).map_err(|err| {
-        eprintln!("ERROR: could not start HTTP server at {address}: {err}");
-    })?;
+/// How many ports after the requested one `--auto-port` tries before giving up
+const AUTO_PORT_ATTEMPTS: u16 = 20;
+
+/// Binds `address`. If it's in use and `auto_port` is set, tries the following ports instead.
+fn bind(address: &str, auto_port: bool) -> Result<Server, ()> {
+    let mut candidate = address.to_string();
+    for _ in 0..=AUTO_PORT_ATTEMPTS {
+        match Server::http(&candidate) {
+            Ok(server) => return Ok(server),
+            Err(err) if is_addr_in_use(&*err) => {
+                if !auto_port {
+                    eprintln!("ERROR: could not start HTTP server at {address}: the address is already in use");
+                    eprintln!("       Pass another address, e.g. `serve <folder> 127.0.0.1:6970`, or --auto-port to pick the next free port");
+                    return Err(());
+                }
+                let Some(next) = next_port(&candidate) else { break };
+                info!("INFO: {candidate} is already in use, trying {next}");
+                candidate = next;
+            }
+            Err(err) => {
+                eprintln!("ERROR: could not start HTTP server at {candidate}: {err}");
+                return Err(());
+            }
+        }
+    }
+    eprintln!("ERROR: could not start HTTP server: no free port found from {address} to {candidate}");
+    Err(())
+}
+
+fn is_addr_in_use(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    err.downcast_ref::<io::Error>().is_some_and(|err| err.kind() == io::ErrorKind::AddrInUse)
+}
+
+/// `address` with its port incremented by one, or `None` if it has no port or it's the last one.
+fn next_port(address: &str) -> Option<String> {
+    let (host, port) = address.rsplit_once(':')?;
+    let port = port.parse::<u16>().ok()?.checked_add(1)?;
+    Some(format!("{host}:{port}"))
+}
+
+/// Serves the HTTP API at `address`, or the next free port after it if `auto_port` is set.
+/// `context.indexing` should be cleared once the initial indexing pass over the folder has finished.
+pub fn start(address: &str, auto_port: bool, context: Context) -> Result<(), ()> {
+    let server = bind(address, auto_port)?;
 
-    println!("INFO: listening at http://{address}/");
+    info!("INFO: listening at http://{address}/", address = server.server_addr());
 
     for request in server.incoming_requests() {
-        serve_request(Arc::clone(&model), request).map_err(|err| {
+        serve_request(&context, request).map_err(|err| {
             eprintln!("ERROR: could not serve the response: {err}");
         }).ok(); // <- don't stop on errors, keep serving
     }
@@ -1,5 +1,11 @@
-
 use ratatui::style::Color;
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Theme the TUI draws with. Set once by `init`; the default palette otherwise.
+static CURRENT: OnceLock<Theme> = OnceLock::new();
 
 pub struct Theme {
     pub background: Color,
@@ -7,6 +13,7 @@ pub struct Theme {
     pub primary: Color,
     pub secondary: Color,
     pub accent: Color,
+    pub match_highlight: Color,
     pub highlight_bg: Color,
     pub highlight_fg: Color,
     pub border: Color,
@@ -22,6 +29,7 @@ impl Default for Theme {
             primary: Color::Rgb(138, 173, 244),    // Blue
             secondary: Color::Rgb(183, 189, 248),  // Lavender
             accent: Color::Rgb(245, 194, 231),     // Pink
+            match_highlight: Color::Rgb(238, 212, 159), // Yellow
             highlight_bg: Color::Rgb(87, 91, 118), // Surface1
             highlight_fg: Color::Rgb(202, 211, 245), // Text
             border: Color::Rgb(69, 73, 94),        // Overlay0
@@ -29,3 +37,60 @@ impl Default for Theme {
         }
     }
 }
+
+/// Colors of a theme file; the ones left out keep their default.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    background: Option<String>,
+    foreground: Option<String>,
+    primary: Option<String>,
+    secondary: Option<String>,
+    accent: Option<String>,
+    match_highlight: Option<String>,
+    highlight_bg: Option<String>,
+    highlight_fg: Option<String>,
+    border: Option<String>,
+    border_highlight: Option<String>,
+}
+
+impl Theme {
+    /// The theme set by `init`, or the default one.
+    pub fn current() -> &'static Theme {
+        CURRENT.get_or_init(Theme::default)
+    }
+
+    /// Reads a TOML theme file such as `accent = "#f5c2e7"` or `border = "dark gray"`.
+    /// Colors are hex codes, color names or 256-color indexes; unknown keys and colors are errors.
+    pub fn load(path: &Path) -> Result<Theme, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read theme file {}: {err}", path.display()))?;
+        let file: ThemeFile = toml::from_str(&text)
+            .map_err(|err| format!("could not parse theme file {}: {err}", path.display()))?;
+
+        let mut theme = Theme::default();
+        let colors = [
+            ("background", file.background, &mut theme.background),
+            ("foreground", file.foreground, &mut theme.foreground),
+            ("primary", file.primary, &mut theme.primary),
+            ("secondary", file.secondary, &mut theme.secondary),
+            ("accent", file.accent, &mut theme.accent),
+            ("match_highlight", file.match_highlight, &mut theme.match_highlight),
+            ("highlight_bg", file.highlight_bg, &mut theme.highlight_bg),
+            ("highlight_fg", file.highlight_fg, &mut theme.highlight_fg),
+            ("border", file.border, &mut theme.border),
+            ("border_highlight", file.border_highlight, &mut theme.border_highlight),
+        ];
+        for (name, value, color) in colors {
+            let Some(value) = value else { continue };
+            *color = Color::from_str(&value)
+                .map_err(|_| format!("invalid color for {name} in {}: {value:?}", path.display()))?;
+        }
+        Ok(theme)
+    }
+}
+
+/// Makes `theme` the one returned by `Theme::current`. Only the first call has an effect.
+pub fn init(theme: Theme) {
+    let _ = CURRENT.set(theme);
+}
@@ -1,35 +1,83 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
-    backend::{Backend, CrosstermBackend},
+    backend::{Backend, CrosstermBackend, TestBackend},
     layout::{Constraint, Direction, Layout},
     style::{Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::{
-    collections::VecDeque,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     env,
     error::Error,
     fs::File,
     io,
-    io::{BufRead, BufReader, BufWriter},
+    io::{BufRead, BufReader, Cursor},
+    ops::Range,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 use std::process::{Command, Stdio};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use rayon::prelude::*;
 
-use crate::model::{Model};
-use crate::add_folder_to_model;
-use crate::theme::Theme;
+use crate::model::{Model, filename_match_score, is_markdown, markdown_heading, parse_query, split_chunk_key, ParsedQuery};
+use crate::{add_folder_to_model_with_progress, flag_value, IndexOptions, EMPTY_INDEX_MESSAGE};
+use crate::theme::{self, Theme};
 use crate::ignore_rules;
-
-const PREVIEW_FILL_LIMIT: usize = 100; // number of results to prefill preview for
+use crate::archive;
+use crate::logging;
+
+/// Results whose snippets are read right after a search; more are read as the selection
+/// gets within `PREVIEW_FILL_LOOKAHEAD` results of the first without one
+const PREVIEW_FILL_BATCH: usize = 40;
+const PREVIEW_FILL_LOOKAHEAD: usize = 20;
+/// Files read at once for snippets by default (`--preview-threads`), so a slow network mount
+/// doesn't make every search wait for one file after another
+const DEFAULT_PREVIEW_THREADS: usize = 8;
+const DEFAULT_RESULTS_PERCENT: u16 = 42; // share of the content area given to the results list
+const RESULTS_PERCENT_STEP: u16 = 5;
+const RESULTS_PERCENT_RANGE: (u16, u16) = (20, 80);
+/// Weight of a perfect filename match relative to the best content match. With 2.0 any
+/// filename match scores at least as high as the best content match, so filename matches
+/// keep coming first by default.
+const DEFAULT_FILENAME_WEIGHT: f32 = 2.0;
+/// Results kept per search by default (`--max-results`); a broad query over a big index
+/// would otherwise keep tens of thousands that are never looked at
+const DEFAULT_MAX_RESULTS: usize = 2000;
+/// Most a query word found in a file name only as a subsequence (`srchqry` in `search_query.rs`)
+/// adds to `filename_match_score`, which gives a substring 50 and the whole name 100
+const FUZZY_FILENAME_SCORE: f64 = 30.0;
+/// Shortest query word matched as a subsequence; shorter ones are in nearly every name
+const MIN_FUZZY_WORD_LEN: usize = 3;
+/// Fuzzy matches scoring below this share of a perfect one are too scattered to count
+const MIN_FUZZY_QUALITY: f64 = 0.7;
+const DEFAULT_SCAN_LINE_LIMIT: usize = 5000; // lines scanned per file when looking for a match to preview
+/// Most match contexts shown at once in the multi-context preview (Ctrl+E)
+const MAX_MATCH_CONTEXTS: usize = 3;
+/// Lines shown before and after each match in the multi-context preview
+const MATCH_CONTEXT_LINES: (usize, usize) = (1, 2);
+/// Preview lines are cut at this many characters when line truncation is on (Ctrl+T)
+const DEFAULT_PREVIEW_COLUMNS: usize = 160;
+/// Narrowest `--preview-columns` accepted, leaving room for the `…` markers
+const MIN_PREVIEW_COLUMNS: usize = 10;
+
+/// Snippets that aren't lines of the file
+const UNREADABLE_PREVIEW: &str = "Could not read file";
+const NO_PREVIEW: &str = "No preview available";
+/// Snippet of a PDF or the like whose text is still being extracted (see `extract_in_background`)
+const EXTRACTING_PREVIEW: &str = "Extracting text…";
+/// Start of the snippet of a file whose matches are all past the scanned lines
+const NO_MATCH_NOTE: &str = "(no match in first ";
+/// Start of the snippet of a result found by its file name
+const FILENAME_MATCH_MARKER: &str = "[FILENAME MATCH] ";
 
 /// Represents a single search result.
 #[derive(Debug, Clone)]
@@ -38,10 +86,26 @@ struct SearchResult {
     file_path: PathBuf,
     /// A snippet from the file where the match was found.
     preview_line: String,
-    /// Score from the fuzzy matcher.
-    score: i64,
+    /// Normalized score, from 0 up to the filename weight (see `Index::search`)
+    score: f64,
     /// Whether this result came from a filename match (not content)
     is_filename_match: bool,
+    /// 0-based line where the best-matching chunk of a chunked file starts; 0 otherwise
+    start_line: usize,
+    /// How many times the query words occur in the file (see `Model::match_count`)
+    matches: usize,
+    /// Heading of the markdown section `preview_line` is in, like `## Installation`
+    section: Option<String>,
+}
+
+/// `score` as a share of `best`, from 0 to 1. Scores that aren't finite, like those of
+/// boosts stacking up to infinity, count as no match rather than upsetting the order.
+fn normalize_score(score: f64, best: f64) -> f64 {
+    if score.is_finite() && best.is_finite() && best > 0.0 {
+        (score / best).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
 }
 
 /// Represents your search index.
@@ -49,6 +113,20 @@ struct Index {
     model: Model,
     /// Cached filename index for fast filename searches
     filename_cache: Vec<(PathBuf, String)>, // (path, lowercase_filename)
+    /// Maximum number of lines scanned per file for snippets and previews
+    scan_limit: usize,
+    /// How strongly filename matches are preferred over content matches (see `search`)
+    filename_weight: f32,
+    /// Characters a preview line is cut at when line truncation is on
+    preview_columns: usize,
+    /// Scores file names that contain a query word's letters in order (see `fuzzy_filename_score`)
+    matcher: SkimMatcherV2,
+    /// Results kept per search, the best ones
+    max_results: usize,
+    /// Threads reading files for result snippets
+    preview_pool: rayon::ThreadPool,
+    /// Files listed first whenever they match (toggled with Alt+S, see `favorites_path`)
+    favorites: HashSet<PathBuf>,
 }
 
 impl Index {
@@ -56,136 +134,302 @@ impl Index {
         Self {
             model: Model::default(),
             filename_cache: Vec::new(),
+            scan_limit: DEFAULT_SCAN_LINE_LIMIT,
+            filename_weight: DEFAULT_FILENAME_WEIGHT,
+            preview_columns: DEFAULT_PREVIEW_COLUMNS,
+            matcher: SkimMatcherV2::default().ignore_case(),
+            max_results: DEFAULT_MAX_RESULTS,
+            preview_pool: preview_pool(DEFAULT_PREVIEW_THREADS).expect("could not start the preview threads"),
+            favorites: HashSet::new(),
         }
     }
 
-    /// Build the filename cache once during initialization
+    /// Builds the filename cache from the files in the content index, so filename and content
+    /// search cover the same files without walking the folder a second time. Files without any
+    /// words, like `__init__.py` or `.gitkeep`, are only remembered by the index but still have names.
     fn build_filename_cache(&mut self) {
-        if let Ok(current_dir) = std::env::current_dir() {
-            self.collect_filenames(&current_dir);
-        }
-    }
-
-    fn collect_filenames(&mut self, dir: &Path) {
-        if let Ok(entries) = std::fs::read_dir(dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-
-                // Skip if matched by .khojignore
-                let is_dir_hint = path.is_dir();
-                if ignore_rules::is_ignored(&path, is_dir_hint) {
-                    continue;
-                }
-
-                if path.is_file() {
-                    if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                        self.filename_cache.push((path.clone(), filename.to_lowercase()));
-                    }
-                } else if path.is_dir() && !path.file_name().unwrap_or_default().to_str().unwrap_or("").starts_with('.') {
-                    // Recursively collect from subdirectories (skip hidden dirs)
-                    self.collect_filenames(&path);
-                }
-            }
-        }
+        let paths: BTreeSet<&Path> = self.model.docs.keys().map(|key| split_chunk_key(key).0)
+            .chain(self.model.empty_files.keys().map(PathBuf::as_path))
+            .collect();
+        self.filename_cache = paths.into_iter()
+            .filter_map(|path| {
+                let filename = path.file_name()?.to_str()?;
+                Some((path.to_path_buf(), filename.to_lowercase()))
+            })
+            .collect();
     }
 
-    fn search(&self, query: &str) -> Vec<SearchResult> {
-        if query.is_empty() || query.len() < 2 { return Vec::new(); }
+    /// Searches content and filenames, or only filenames when `filename_only` is set. Keeps the
+    /// best `max_results` results and also returns how many there were in all.
+    ///
+    /// The two sources score on unrelated scales, so both are normalized before blending:
+    /// content scores are divided by the best content score of this query (top content hit = 1.0),
+    /// filename scores by the best possible filename score (every word equal to the name = 1.0)
+    /// and then multiplied by `filename_weight`. Equal scores put filename matches first, then
+    /// follow the model's tie-break (`--tie-break`), so the list is stable across runs.
+    /// With `recent_first`, files whose name or content matches at all are listed newest first
+    /// instead, whatever their score. Either way matching favorites are moved to the top.
+    fn search(&self, query: &str, filename_only: bool, recent_first: bool) -> (Vec<SearchResult>, usize) {
+        if query.is_empty() || query.len() < 2 { return (Vec::new(), 0); }
 
-        let query_lower = query.to_lowercase();
-        let query_words: Vec<&str> = query_lower.split_whitespace().collect();
         let query_chars: Vec<char> = query.chars().collect();
+        // Field filters (ext:, path:) constrain both content and filename matches
+        let parsed = parse_query(&query_chars);
+        let query_lower = parsed.text.iter().collect::<String>().to_lowercase();
+        let query_words: Vec<&str> = query_lower.split_whitespace().collect();
 
         // Content search first (no file I/O here)
-        let content_search_results = self.model.search_query(&query_chars);
+        let content_search_results = if filename_only { Vec::new() } else { self.model.search_query_best_chunks(&query_chars) };
         let mut results = Vec::new();
         let mut processed_paths = std::collections::HashSet::new();
 
-        for (path, score) in content_search_results.iter() {
-            processed_paths.insert(path.clone());
+        let best_content_score = content_search_results.iter()
+            .map(|(_, score, _)| f64::from(*score))
+            .filter(|score| score.is_finite())
+            .fold(0.0, f64::max);
+        for (path, score, start_line) in content_search_results.iter() {
+            // Files the content search ranks 0 are listed too; a filename match replaces those
+            if *score > 0.0 {
+                processed_paths.insert(path.clone());
+            }
             results.push(SearchResult {
                 file_path: path.clone(),
                 preview_line: String::new(),
-                score: (score * 1000.0) as i64,
+                score: normalize_score(f64::from(*score), best_content_score),
                 is_filename_match: false,
+                start_line: *start_line,
+                matches: 0,
+                section: None,
             });
         }
 
         // Filename search (also no file I/O here)
-        self.add_filename_search_results_fast(&mut results, &mut processed_paths, &query_words);
+        self.add_filename_search_results_fast(&mut results, &mut processed_paths, &query_words, &parsed);
+        results.retain(|r| r.is_filename_match || r.score > 0.0 || !processed_paths.contains(&r.file_path));
 
-        // Sort by score (highest first). Do NOT truncate; keep all results.
-        results.sort_by(|a, b| b.score.cmp(&a.score));
+        if recent_first {
+            let matching = self.model.matching_files(&query_chars);
+            results.retain(|r| r.is_filename_match || matching.contains(r.file_path.as_path()));
+            results.sort_by(|a, b| self.model.recency_cmp(&a.file_path, &b.file_path));
+        } else {
+            // Sort by score (highest first), ties as documented above, and keep the best ones
+            results.sort_by(|a, b| {
+                b.score.total_cmp(&a.score)
+                    .then_with(|| b.is_filename_match.cmp(&a.is_filename_match))
+                    .then_with(|| self.model.tie_break_cmp(&a.file_path, &b.file_path))
+            });
+        }
+        // Favorites that match come first, in the order above; the sort is stable
+        results.sort_by_key(|r| !(self.favorites.contains(&r.file_path) && (recent_first || r.is_filename_match || r.score > 0.0)));
+        let total = results.len();
+        results.truncate(self.max_results);
+        for result in &mut results {
+            result.matches = self.model.match_count(&result.file_path, &query_chars);
+        }
 
-        // Fill previews only for the top results (perform file I/O now)
-        self.fill_result_previews(&mut results, query);
-        results
+        // Fill previews only for the top results (perform file I/O now); the others are filled
+        // as the selection gets near them
+        let first_batch = results.len().min(PREVIEW_FILL_BATCH);
+        self.fill_result_previews(&mut results[..first_batch], query);
+        (results, total)
     }
 
-    fn add_filename_search_results_fast(&self, results: &mut Vec<SearchResult>, processed_paths: &mut std::collections::HashSet<PathBuf>, query_words: &[&str]) {
+    fn add_filename_search_results_fast(&self, results: &mut Vec<SearchResult>, processed_paths: &mut std::collections::HashSet<PathBuf>, query_words: &[&str], parsed: &ParsedQuery) {
         for (path, filename_lower) in &self.filename_cache {
-            if processed_paths.contains(path) { continue; }
-
-            let mut filename_score = 0;
-            for word in query_words {
-                if filename_lower.contains(word) {
-                    filename_score += if filename_lower == *word { 100 } else { 50 };
-                }
-            }
+            if processed_paths.contains(path) || !parsed.matches_filters(path, self.model.tags(path), self.model.time_range(path)) { continue; }
 
-            if filename_score > 0 {
+            let filename = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            let filename_score = self.fuzzy_filename_score(&filename, filename_lower, query_words);
+            if filename_score > 0.0 {
+                let best_possible = filename_match_score(filename_lower, &[filename_lower.as_str()]) * query_words.len();
+                let normalized = normalize_score(filename_score, best_possible as f64);
                 processed_paths.insert(path.clone());
                 results.push(SearchResult {
                     file_path: path.clone(),
                     preview_line: String::new(), // filled later
-                    score: filename_score,
+                    score: normalized * f64::from(self.filename_weight),
                     is_filename_match: true,
+                    start_line: 0,
+                    matches: 0,
+                    section: None,
                 });
             }
         }
     }
 
-    /// After sorting, populate preview lines with minimal I/O for only the first PREVIEW_FILL_LIMIT results
-    fn fill_result_previews(&self, results: &mut [SearchResult], query: &str) {
-        let query_lower = query.to_lowercase();
-        let query_words: Vec<&str> = query_lower.split_whitespace().filter(|w| !w.is_empty()).collect();
-        for res in results.iter_mut().take(PREVIEW_FILL_LIMIT) {
-            let file = match std::fs::File::open(&res.file_path) {
-                Ok(f) => f,
-                Err(_) => { res.preview_line = "Could not read file".to_string(); continue; }
-            };
-            let reader = BufReader::new(file);
-
-            let mut first_non_empty: Option<String> = None;
-            let mut chosen: Option<String> = None;
-            // Scan at most N lines for performance
-            let mut scanned = 0usize;
-            for line in reader.lines() {
-                scanned += 1;
-                if scanned > 1000 { break; }
-                let Ok(line) = line else { continue };
-                if first_non_empty.is_none() && !line.trim().is_empty() {
-                    first_non_empty = Some(line.trim().to_string());
-                }
-                let ll = line.to_lowercase();
-                if query_words.iter().any(|w| ll.contains(w)) {
-                    chosen = Some(line.trim().to_string());
-                    break;
-                }
+    /// `filename_match_score`, plus the words that aren't in the name but whose letters are, in
+    /// order. Those score up to `FUZZY_FILENAME_SCORE`, more for letters close together and
+    /// at word boundaries (`_`, `.`, camelCase), as in fzf.
+    fn fuzzy_filename_score(&self, filename: &str, filename_lower: &str, query_words: &[&str]) -> f64 {
+        query_words.iter().map(|word| {
+            let score = filename_match_score(filename_lower, &[word]);
+            if score > 0 {
+                return score as f64;
+            }
+            if word.chars().count() < MIN_FUZZY_WORD_LEN {
+                return 0.0;
             }
+            let Some(fuzzy) = self.matcher.fuzzy_match(filename, word) else { return 0.0 };
+            let perfect = self.matcher.fuzzy_match(word, word).unwrap_or(fuzzy).max(1);
+            let quality = (fuzzy as f64 / perfect as f64).min(1.0);
+            if quality < MIN_FUZZY_QUALITY { 0.0 } else { FUZZY_FILENAME_SCORE * quality }
+        }).sum()
+    }
 
-            let line = chosen
-                .or(first_non_empty)
-                .unwrap_or_else(|| "No preview available".to_string());
+    /// Fills in the snippets of those of `results` that don't have one yet, reading the files
+    /// in parallel on the preview threads (`--preview-threads`).
+    fn fill_result_previews(&self, results: &mut [SearchResult], query: &str) {
+        let query_lower = snippet_query(query);
+        let distinct_words = distinct_words(&query_lower);
+        let scan_limit = self.scan_limit;
+        self.preview_pool.install(|| {
+            results.par_iter_mut()
+                .filter(|res| res.preview_line.is_empty())
+                .for_each(|res| {
+                    // An unreadable file gets a note instead of a snippet
+                    let _ = fill_result_preview(res, &distinct_words, scan_limit);
+                });
+        });
+    }
+}
+
+/// The lowercase text of `query`, without its operators, that snippets are chosen for.
+fn snippet_query(query: &str) -> String {
+    let parsed = parse_query(&query.chars().collect::<Vec<_>>());
+    parsed.text.iter().collect::<String>().to_lowercase()
+}
+
+/// The words of `text`, sorted and without duplicates.
+fn distinct_words(text: &str) -> Vec<&str> {
+    let mut words: Vec<&str> = text.split_whitespace().collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
 
-            res.preview_line = if res.is_filename_match {
-                format!("[FILENAME MATCH] {}", line)
-            } else { line };
+/// The snippet the TUI would show for `path` as a match of `query`, or `None` if the file
+/// can't be read. Used by `khoj search --format json`.
+pub(crate) fn snippet(path: &Path, query: &str) -> Option<String> {
+    let query_lower = snippet_query(query);
+    let mut res = SearchResult {
+        file_path: path.to_path_buf(),
+        preview_line: String::new(),
+        score: 0.0,
+        is_filename_match: false,
+        start_line: 0,
+        matches: 0,
+        section: None,
+    };
+    fill_result_preview(&mut res, &distinct_words(&query_lower), DEFAULT_SCAN_LINE_LIMIT).ok()?;
+    Some(res.preview_line)
+}
+
+/// Sets the snippet of `res`: the line among the first `scan_limit` (of its best chunk, if
+/// any) containing the most distinct query words. Markdown snippets also get the heading of
+/// their section. If the file can't be read, the snippet says so and the error is returned.
+fn fill_result_preview(res: &mut SearchResult, distinct_words: &[&str], scan_limit: usize) -> io::Result<()> {
+    let reader = match open_for_preview(&res.file_path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            let note = if err.kind() == io::ErrorKind::WouldBlock { EXTRACTING_PREVIEW } else { UNREADABLE_PREVIEW };
+            res.preview_line = note.to_string();
+            return Err(err);
+        }
+    };
+
+    let mut first_non_empty: Option<String> = None;
+    // The line with the most distinct query words, the first of equally good ones
+    let mut chosen: Option<String> = None;
+    let mut chosen_words = 0;
+    let mut sections = is_markdown(&res.file_path).then(MarkdownSections::default);
+    // Scan at most N lines (from the best chunk, if any) for performance
+    let mut scanned = 0usize;
+    let mut capped = false;
+    for (index, line) in reader.lines().enumerate() {
+        if index < res.start_line {
+            // Lines before the chunk may still hold the heading of its section
+            if let (Some(sections), Ok(line)) = (&mut sections, &line) {
+                sections.read(index, line);
+            }
+            continue;
+        }
+        scanned += 1;
+        if scanned > scan_limit { capped = true; break; }
+        let Ok(line) = line else { continue };
+        if first_non_empty.is_none() && !line.trim().is_empty() {
+            first_non_empty = Some(line.trim().to_string());
+        }
+        let is_heading = sections.as_mut().is_some_and(|sections| sections.read(index, &line));
+        let ll = line.to_lowercase();
+        let words = distinct_words.iter().filter(|w| ll.contains(*w)).count();
+        if words > chosen_words {
+            chosen = Some(line.trim().to_string());
+            chosen_words = words;
+            // A heading is its own section
+            res.section = sections.as_ref().filter(|_| !is_heading).and_then(|sections| sections.heading.clone());
+            // No later line can do better
+            if words == distinct_words.len() { break; }
         }
     }
+
+    // A content match whose terms weren't found before the cap gets an honest note
+    // instead of an unrelated first line
+    let line = match chosen {
+        Some(line) => line,
+        None if capped && !res.is_filename_match => format!("{NO_MATCH_NOTE}{scan_limit} lines)"),
+        None => first_non_empty.unwrap_or_else(|| NO_PREVIEW.to_string()),
+    };
+
+    res.preview_line = if res.is_filename_match {
+        format!("{FILENAME_MATCH_MARKER}{line}")
+    } else { line };
+    Ok(())
 }
 
 
+fn preview_pool(threads: usize) -> Result<rayon::ThreadPool, rayon::ThreadPoolBuildError> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("khoj-preview-{i}"))
+        .build()
+}
+
+/// Follows the headings of a markdown file as it's read line by line, leaving out `#` lines
+/// in code blocks and front matter.
+#[derive(Default)]
+struct MarkdownSections {
+    /// The last heading line read, like `## Installation`
+    heading: Option<String>,
+    in_fence: bool,
+    in_front_matter: bool,
+}
+
+impl MarkdownSections {
+    /// Reads `line`, the `index`th (0-based) of the file. Returns whether it's a heading.
+    fn read(&mut self, index: usize, line: &str) -> bool {
+        let line = line.trim();
+        if index == 0 && line == "---" {
+            self.in_front_matter = true;
+            return false;
+        }
+        if self.in_front_matter {
+            self.in_front_matter = line != "---" && line != "...";
+            return false;
+        }
+        if line.starts_with("```") || line.starts_with("~~~") {
+            self.in_fence = !self.in_fence;
+            return false;
+        }
+        match markdown_heading(line) {
+            Some(text) if !self.in_fence && !text.is_empty() => {
+                self.heading = Some(line.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 /// Application state
 struct App {
     /// The user's current search query.
@@ -205,21 +449,149 @@ struct App {
     /// Debounce control: last input time and whether a search is pending
     last_input_time: Option<Instant>,
     needs_search: bool,
+    /// Digits typed since entering jump mode (Ctrl+G or Alt+digit); `None` when not jumping
+    jump_input: Option<String>,
+    /// Restrict searches to file names, skipping the content index (toggled with Ctrl+F)
+    filename_only: bool,
+    /// List matching files newest first instead of by relevance (toggled with Ctrl+R)
+    recent_first: bool,
+    /// Percentage of the content area used by the results list; the preview gets the rest
+    results_percent: u16,
+    /// Results above the preview instead of side by side (toggled with Ctrl+L)
+    stacked_layout: bool,
+    /// Corrected query offered when nothing matched; Tab replaces the query with it
+    suggestion: Option<String>,
+    /// Files hidden from results for the rest of the session (Ctrl+D / Alt+D)
+    hidden: HashSet<PathBuf>,
+    /// One-off message for the footer, e.g. the outcome of Alt+D; cleared by the next search
+    notice: Option<String>,
+    /// Indexed folder, stripped from result paths when `relative_paths` is set
+    root: PathBuf,
+    /// Show result directories relative to `root` instead of absolute (toggled with Ctrl+P)
+    relative_paths: bool,
+    /// One line per result (file name and snippet) instead of three (toggled with Ctrl+K)
+    compact_rows: bool,
+    /// Label results with their file type, like `[MD]` (toggled with Ctrl+B, off with `--no-badges`)
+    badges: bool,
+    /// Preview several match contexts of the selected file instead of the first (toggled with Ctrl+E)
+    multi_context: bool,
+    /// Cut long preview lines with `…` instead of wrapping them (toggled with Ctrl+T)
+    truncate_lines: bool,
+    /// Search within the previewed file (Alt+F); `None` when not finding
+    find: Option<FileFind>,
+    /// Number of results the last search found, including those beyond `--max-results`
+    total_results: usize,
+    /// `EXTRACTIONS_FINISHED` when the previews were last updated for them
+    extractions_seen: usize,
+}
+
+/// A search within the file of the selected result, independent of the query.
+struct FileFind {
+    /// Lowercased text looked for
+    text: String,
+    /// Whether `text` is still being typed; once confirmed with Enter, n/N move between matches
+    editing: bool,
+    /// Lines of the file being searched
+    lines: Vec<String>,
+    /// 0-based lines containing `text`
+    matches: Vec<usize>,
+    /// Position in `matches` of the match shown
+    current: usize,
+}
+
+impl FileFind {
+    fn new(file_path: &Path) -> io::Result<Self> {
+        let lines = open_for_preview(file_path)?.lines().collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { text: String::new(), editing: true, lines, matches: Vec::new(), current: 0 })
+    }
+
+    fn update_matches(&mut self) {
+        self.matches = if self.text.is_empty() {
+            Vec::new()
+        } else {
+            self.lines.iter().enumerate()
+                .filter(|(_, line)| line.to_lowercase().contains(&self.text))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        self.current = 0;
+    }
+
+    /// Moves to the next match (`forward`) or the previous one, wrapping around.
+    fn step(&mut self, forward: bool) {
+        let count = self.matches.len();
+        if count > 0 {
+            self.current = if forward { (self.current + 1) % count } else { (self.current + count - 1) % count };
+        }
+    }
+
+    /// `n/m` for the footer, or `no matches`.
+    fn position(&self) -> String {
+        if self.matches.is_empty() {
+            "no matches".to_string()
+        } else {
+            format!("{}/{}", self.current + 1, self.matches.len())
+        }
+    }
+
+    /// The lines around the current match, with every occurrence of `text` highlighted.
+    fn preview(&self, line_limit: Option<usize>) -> StyledPreview {
+        let Some(&matched) = self.matches.get(self.current) else {
+            let message = if self.text.is_empty() { "Type to find in this file..." } else { "No matches in this file" };
+            return (message.to_string(), vec![Line::from(message)]);
+        };
+        let theme = Theme::current();
+        let words = [self.text.as_str()];
+        let mut preview_lines = Vec::new();
+        let mut styled_lines = Vec::new();
+        let first = matched.saturating_sub(3);
+        for (i, line) in self.lines.iter().enumerate().skip(first).take(matched + 11 - first) {
+            let line = clip_line(line, &words, line_limit);
+            if i == matched {
+                let prefix = format!(">>> {:3}: ", i + 1);
+                preview_lines.push(format!("{}{}", &prefix, line));
+                styled_lines.push(create_highlighted_line(&line, &words, &prefix).patch_style(Style::default().bg(theme.highlight_bg)));
+            } else {
+                let plain = format!("    {:3}: {}", i + 1, line);
+                styled_lines.push(create_highlighted_line(&plain, &words, ""));
+                preview_lines.push(plain);
+            }
+        }
+        (preview_lines.join("\n"), styled_lines)
+    }
 }
 
 impl App {
-    /// Creates a new App instance with the given index.
-    fn new(index: Index) -> Self {
+    /// Creates a new App instance with the given index of the folder `root`.
+    fn new(index: Index, root: PathBuf) -> Self {
+        let idle_text = idle_preview_text(&index);
         Self {
             query: String::new(),
             results: Vec::new(),
             index,
             results_state: ListState::default(),
-            preview_content: "Type to search files...".to_string(),
-            preview_spans: vec![Line::from("Type to search files...")],
+            preview_content: idle_text.to_string(),
+            preview_spans: vec![Line::from(idle_text)],
             last_search_query: String::new(),
             last_input_time: None,
             needs_search: false,
+            jump_input: None,
+            filename_only: false,
+            recent_first: false,
+            results_percent: DEFAULT_RESULTS_PERCENT,
+            stacked_layout: false,
+            suggestion: None,
+            hidden: HashSet::new(),
+            notice: None,
+            root,
+            relative_paths: false,
+            compact_rows: false,
+            badges: true,
+            multi_context: false,
+            truncate_lines: false,
+            find: None,
+            total_results: 0,
+            extractions_seen: 0,
         }
     }
 
@@ -269,73 +641,394 @@ impl App {
         self.update_preview();
     }
 
+    /// Enters jump mode; subsequent digits select a result by its 1-based position.
+    fn start_jump(&mut self) {
+        self.jump_input = Some(String::new());
+    }
+
+    /// Leaves jump mode, keeping whatever result the jump selected.
+    fn finish_jump(&mut self) {
+        self.jump_input = None;
+    }
+
+    /// Appends a digit to the pending jump number and moves the selection there.
+    fn on_jump_digit(&mut self, c: char) {
+        let pending = self.jump_input.get_or_insert_with(String::new);
+        pending.push(c);
+        self.apply_jump();
+    }
+
+    /// Removes the last pending jump digit; leaves jump mode once nothing is left.
+    fn on_jump_backspace(&mut self) {
+        if let Some(pending) = self.jump_input.as_mut() {
+            if pending.pop().is_none() {
+                self.jump_input = None;
+                return;
+            }
+        }
+        self.apply_jump();
+    }
+
+    /// Selects the result at the pending 1-based position, clamped to the last result.
+    fn apply_jump(&mut self) {
+        if self.results.is_empty() {
+            return;
+        }
+        let Some(n) = self.jump_input.as_deref().and_then(|s| s.parse::<usize>().ok()) else { return };
+        if n == 0 {
+            return;
+        }
+        let i = (n - 1).min(self.results.len() - 1);
+        self.results_state.select(Some(i));
+        self.update_preview();
+    }
+
+    /// Grows (positive `delta`) or shrinks the results pane, keeping both panes usable.
+    fn resize_results(&mut self, delta: i16) {
+        let (min, max) = RESULTS_PERCENT_RANGE;
+        self.results_percent = (self.results_percent as i16 + delta).clamp(min as i16, max as i16) as u16;
+    }
+
+    /// Replaces the query with the "did you mean" suggestion, if there is one.
+    fn accept_suggestion(&mut self) {
+        if let Some(suggestion) = self.suggestion.take() {
+            self.query = suggestion;
+            self.update_search_results();
+        }
+    }
+
+    /// Removes the selected result and keeps its file out of results for the rest of the session.
+    fn hide_selected(&mut self) {
+        let Some(selected) = self.results_state.selected().filter(|&i| i < self.results.len()) else { return };
+        let removed = self.results.remove(selected);
+        self.hidden.insert(removed.file_path.clone());
+        if self.results.is_empty() {
+            self.results_state.select(None);
+        } else {
+            self.results_state.select(Some(selected.min(self.results.len() - 1)));
+        }
+        self.update_preview();
+    }
+
+    /// Like `hide_selected`, but also appends the file to `.khojignore` so later runs skip it.
+    fn exclude_selected(&mut self) {
+        let Some(path) = self.results_state.selected().and_then(|i| self.results.get(i)).map(|r| r.file_path.clone()) else { return };
+        self.hide_selected();
+        self.notice = Some(match append_to_khojignore(&path) {
+            Ok(pattern) => format!("Added {} to .khojignore", pattern),
+            Err(err) => format!("Could not update .khojignore: {}", err),
+        });
+    }
+
+    /// Adds the selected result to the favorites, or removes it, saves them and searches again
+    /// so the order reflects it.
+    fn toggle_favorite_selected(&mut self) {
+        let Some(path) = self.results_state.selected().and_then(|i| self.results.get(i)).map(|r| r.file_path.clone()) else { return };
+        let added = self.index.favorites.insert(path.clone());
+        if !added {
+            self.index.favorites.remove(&path);
+        }
+        self.last_search_query.clear();
+        self.update_search_results();
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        self.notice = Some(match save_favorites(&self.index.favorites) {
+            Ok(()) if added => format!("Added {name} to favorites"),
+            Ok(()) => format!("Removed {name} from favorites"),
+            Err(err) => format!("Could not save favorites: {err}"),
+        });
+    }
+
+    /// Copies the snippet of the selected result, the line shown under its name, to the clipboard.
+    fn copy_selected_snippet(&mut self) {
+        let Some(result) = self.results_state.selected().and_then(|i| self.results.get(i)) else { return };
+        let line = result.preview_line.strip_prefix(FILENAME_MATCH_MARKER).unwrap_or(&result.preview_line).to_string();
+        if line.is_empty() || line == NO_PREVIEW || line == UNREADABLE_PREVIEW || line == EXTRACTING_PREVIEW || line.starts_with(NO_MATCH_NOTE) {
+            self.notice = Some("No snippet to copy".to_string());
+            return;
+        }
+        self.notice = Some(match copy_to_clipboard(&line) {
+            Ok(_) => format!("Copied \"{}\"", shorten(&line, 60)),
+            Err(err) => format!("Could not copy the snippet: {err}"),
+        });
+    }
+
+    /// Opens the folder of the selected result in the file manager; the TUI stays open.
+    fn open_selected_folder(&mut self) {
+        let Some(path) = self.results_state.selected().and_then(|i| self.results.get(i)).map(|r| r.file_path.clone()) else { return };
+        // Archive members are in the folder of their archive
+        let folder = match archive::file_on_disk(&path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        self.notice = Some(match open_folder_external(&folder) {
+            Ok(()) => format!("Opened {}", folder.display()),
+            Err(err) => format!("Could not open {}: {}", folder.display(), err),
+        });
+    }
+
+    /// Switches between full search and filename-only search, re-running the current query.
+    fn toggle_filename_only(&mut self) {
+        self.filename_only = !self.filename_only;
+        self.last_search_query.clear();
+        self.update_search_results();
+    }
+
+    fn toggle_recent_first(&mut self) {
+        self.recent_first = !self.recent_first;
+        self.last_search_query.clear();
+        self.update_search_results();
+    }
+
+    fn toggle_multi_context(&mut self) {
+        self.multi_context = !self.multi_context;
+        self.update_preview();
+    }
+
+    fn toggle_truncate_lines(&mut self) {
+        self.truncate_lines = !self.truncate_lines;
+        self.update_preview();
+    }
+
+    /// Starts finding within the file of the selected result.
+    fn start_find(&mut self) {
+        let Some(result) = self.results_state.selected().and_then(|i| self.results.get(i)) else { return };
+        match FileFind::new(&result.file_path) {
+            Ok(find) => self.find = Some(find),
+            Err(err) => self.notice = Some(format!("Could not read {}: {}", result.file_path.display(), err)),
+        }
+        self.update_preview();
+    }
+
+    /// Leaves find mode, going back to the preview of the query's match.
+    fn close_find(&mut self) {
+        self.find = None;
+        self.update_preview();
+    }
+
+    fn on_find_key(&mut self, c: char) {
+        if let Some(find) = self.find.as_mut() {
+            find.text.extend(c.to_lowercase());
+            find.update_matches();
+        }
+        self.update_preview();
+    }
+
+    fn on_find_backspace(&mut self) {
+        if let Some(find) = self.find.as_mut() {
+            find.text.pop();
+            find.update_matches();
+        }
+        self.update_preview();
+    }
+
+    /// Switches between typing the text to find (`editing`) and moving between its matches.
+    fn set_find_editing(&mut self, editing: bool) {
+        if let Some(find) = self.find.as_mut() {
+            find.editing = editing;
+        }
+    }
+
+    fn step_find(&mut self, forward: bool) {
+        if let Some(find) = self.find.as_mut() {
+            find.step(forward);
+        }
+        self.update_preview();
+    }
+
+    /// Directory of `path` as shown under the file name in the results list.
+    fn display_dir(&self, path: &Path) -> String {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if !self.relative_paths {
+            return dir.to_string_lossy().into_owned();
+        }
+        match dir.strip_prefix(&self.root) {
+            Ok(relative) if relative.as_os_str().is_empty() => "./".to_string(),
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            // Paths outside the root, if any, stay absolute
+            Err(_) => dir.to_string_lossy().into_owned(),
+        }
+    }
+
     /// Updates the search results based on the current query.
     fn update_search_results(&mut self) {
         if self.query == self.last_search_query {
             return;
         }
         self.last_search_query = self.query.clone();
-        self.results = self.index.search(&self.query);
+        let (results, total) = self.index.search(&self.query, self.filename_only, self.recent_first);
+        self.results = results;
+        self.total_results = total;
+        self.results.retain(|r| !self.hidden.contains(&r.file_path));
+        self.notice = None;
+        // Nothing matched: offer the query with misspelled words replaced by known terms
+        let nothing_matched = self.results.iter().all(|r| r.score == 0.0);
+        self.suggestion = if nothing_matched && !self.filename_only && !self.query.trim().is_empty() {
+            self.index.model.did_you_mean(&self.query)
+        } else {
+            None
+        };
         if !self.results.is_empty() { self.results_state.select(Some(0)); } else { self.results_state.select(None); }
         self.update_preview();
     }
 
+    /// Reads the snippets of the results around the selection once one within
+    /// `PREVIEW_FILL_LOOKAHEAD` of it has none, so scrolling or jumping far down reads only
+    /// what's about to be shown.
+    fn fill_previews_near_selection(&mut self) {
+        let Some(selected) = self.results_state.selected() else { return };
+        let start = selected.saturating_sub(PREVIEW_FILL_LOOKAHEAD);
+        let needed = (selected + PREVIEW_FILL_LOOKAHEAD + 1).min(self.results.len());
+        if self.results[start.min(needed)..needed].iter().all(|r| !r.preview_line.is_empty()) {
+            return;
+        }
+        let end = (selected + PREVIEW_FILL_BATCH).max(needed).min(self.results.len());
+        self.index.fill_result_previews(&mut self.results[start..end], &self.last_search_query);
+    }
+
+    /// Fills in the snippets and preview that were waiting for text extracted in the
+    /// background, once some extraction has finished.
+    fn show_finished_extractions(&mut self) {
+        let finished = EXTRACTIONS_FINISHED.load(Ordering::Relaxed);
+        if finished == self.extractions_seen {
+            return;
+        }
+        self.extractions_seen = finished;
+        for result in self.results.iter_mut().filter(|r| r.preview_line == EXTRACTING_PREVIEW) {
+            result.preview_line.clear();
+        }
+        self.update_preview();
+    }
+
     /// Updates the preview pane with the content of the selected file.
     fn update_preview(&mut self) {
+        self.fill_previews_near_selection();
+        let line_limit = self.truncate_lines.then_some(self.index.preview_columns);
+        if let Some(find) = &self.find {
+            (self.preview_content, self.preview_spans) = find.preview(line_limit);
+            return;
+        }
         if let Some(selected_index) = self.results_state.selected() {
             if let Some(selected_result) = self.results.get(selected_index) {
                 // Enhanced file preview with highlighting
-                let (content, spans) = get_enhanced_preview_with_styling(&selected_result.file_path, &self.query)
-                    .unwrap_or_else(|e| (format!("Error reading file: {}", e), vec![Line::from("Error reading file")]));
+                let multi_context = if self.multi_context {
+                    get_multi_context_preview(&selected_result.file_path, &self.query, selected_result.start_line, self.index.scan_limit, line_limit)
+                } else {
+                    Ok(None)
+                };
+                let preview = match multi_context {
+                    Ok(Some(preview)) => Ok(preview),
+                    // With a single match the usual preview shows more of its context
+                    Ok(None) => get_enhanced_preview_with_styling(&selected_result.file_path, &self.query, selected_result.start_line, self.index.scan_limit, line_limit),
+                    Err(err) => Err(err),
+                };
+                let extracting = |e: &(dyn Error + 'static)| e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::WouldBlock);
+                let (content, spans) = preview.unwrap_or_else(|e| if extracting(&*e) {
+                    (EXTRACTING_PREVIEW.to_string(), vec![Line::from(EXTRACTING_PREVIEW)])
+                } else {
+                    (format!("Error reading file: {}", e), vec![Line::from("Error reading file")])
+                });
                 self.preview_content = content;
                 self.preview_spans = spans;
             }
         } else {
-            self.preview_content = "Type to search files...".to_string();
-            self.preview_spans = vec![Line::from("Type to search files...")];
+            let idle_text = idle_preview_text(&self.index);
+            self.preview_content = idle_text.to_string();
+            self.preview_spans = vec![Line::from(idle_text)];
         }
     }
 }
 
+/// Longest file type shown as a badge; longer extensions are probably not a type
+const MAX_BADGE_LEN: usize = 4;
+
+/// The file type label of `path`, its extension in capitals like `MD` or `PDF`. Logs compressed
+/// with gzip are `LOG` and archive members get the type of the member. `None` for files
+/// without a short extension, like dot files.
+fn file_badge(path: &Path) -> Option<String> {
+    if crate::is_log_file(path) {
+        return Some("LOG".to_string());
+    }
+    let extension = path.extension()?.to_str()?;
+    (extension.len() <= MAX_BADGE_LEN && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then(|| extension.to_ascii_uppercase())
+}
+
+/// Text for the preview pane when no result is selected. An empty index would make every
+/// search come back empty, so say so up front instead of letting the user guess.
+fn idle_preview_text(index: &Index) -> &'static str {
+    if index.model.docs.is_empty() {
+        EMPTY_INDEX_MESSAGE
+    } else {
+        "Type to search files..."
+    }
+}
+
 pub fn main() -> Result<(), Box<dyn Error>> {
     // Parse CLI args for --refresh
     let args: Vec<String> = env::args().collect();
     if args.iter().any(|a| a == "-h" || a == "--help") {
-        eprintln!("Usage: khoj [--refresh|-r]\n  --refresh  Rebuild index even if .finder.json exists");
+        eprintln!("Usage: khoj [--refresh|-r] [--gitignore] [--scan-lines N] [--filename-weight W] [--preview-columns N] [--max-results N] [--preview-threads N] [--theme FILE] [--no-badges]\n  --refresh       Rebuild index even if .finder.json exists\n  --gitignore     Also skip paths matched by .gitignore files\n  --scan-lines N  Lines scanned per file when looking for a preview match (default {DEFAULT_SCAN_LINE_LIMIT})\n  --filename-weight W  Weight of filename matches relative to content matches (default {DEFAULT_FILENAME_WEIGHT})\n  --preview-columns N  Characters preview lines are cut at when Ctrl+T truncates them (default {DEFAULT_PREVIEW_COLUMNS})\n  --max-results N  Results kept per search, the best ones (default {DEFAULT_MAX_RESULTS})\n  --preview-threads N  Files read at once for result snippets (default {DEFAULT_PREVIEW_THREADS})\n  --theme FILE    Colors to draw with, see `khoj theme preview FILE`\n  --no-badges     Start without the file type badges, like [MD], before result names (Ctrl+B toggles them)");
+        crate::usage(&args[0]);
         return Ok(());
     }
+    if crate::wants_version(&args) {
+        println!("khoj {}", crate::VERSION);
+        return Ok(());
+    }
+    let scan_limit = match flag_value(&args, "--scan-lines") {
+        Some(v) => v.parse::<usize>().map_err(|_| format!("invalid value for --scan-lines: {v}"))?,
+        None => DEFAULT_SCAN_LINE_LIMIT,
+    };
+    let filename_weight = match flag_value(&args, "--filename-weight") {
+        Some(v) => v.parse::<f32>().ok().filter(|w| w.is_finite() && *w >= 0.0)
+            .ok_or_else(|| format!("invalid value for --filename-weight: {v}"))?,
+        None => DEFAULT_FILENAME_WEIGHT,
+    };
+    let preview_columns = match flag_value(&args, "--preview-columns") {
+        Some(v) => v.parse::<usize>().ok().filter(|&n| n >= MIN_PREVIEW_COLUMNS)
+            .ok_or_else(|| format!("invalid value for --preview-columns: {v} (expected at least {MIN_PREVIEW_COLUMNS})"))?,
+        None => DEFAULT_PREVIEW_COLUMNS,
+    };
+    let max_results = match flag_value(&args, "--max-results") {
+        Some(v) => v.parse::<usize>().ok().filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid value for --max-results: {v} (expected a positive number)"))?,
+        None => DEFAULT_MAX_RESULTS,
+    };
+    let preview_threads = match flag_value(&args, "--preview-threads") {
+        Some(v) => v.parse::<usize>().ok().filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid value for --preview-threads: {v} (expected a positive number)"))?,
+        None => DEFAULT_PREVIEW_THREADS,
+    };
+    if let Some(theme_path) = flag_value(&args, "--theme") {
+        theme::init(Theme::load(Path::new(theme_path))?);
+    }
+    let index_options = IndexOptions::from_args(&args).map_err(|()| "invalid command line options")?;
+    let ranking = crate::ranking_params_from_args(&args).map_err(|()| "invalid command line options")?;
+    let synonyms = crate::synonyms_from_args(&args).map_err(|()| "invalid command line options")?;
     let refresh = args.iter().any(|a| a == "--refresh" || a == "-r");
+    let use_gitignore = args.iter().any(|a| a == "--gitignore");
 
     // Determine working directory and index path
     let current_dir = env::current_dir()?;
 
-    // Initialize ignore rules from .khojignore
-    ignore_rules::init(&current_dir);
+    // Initialize ignore rules from .khojignore (and .gitignore if requested)
+    ignore_rules::init(&current_dir, use_gitignore);
 
-    let index_path = current_dir.join(".finder.json");
+    let index_path = crate::index_path(&current_dir, &args);
 
     // Prepare model, either by loading existing index or indexing afresh
     let wrapped_model: Arc<Mutex<Model>> = if !refresh && index_path.try_exists().unwrap_or(false) {
         // Load existing index
-        match File::open(&index_path) {
-            Ok(file) => {
-                let reader = BufReader::new(file);
-                let model: Model = serde_json::from_reader(reader)?;
-                Arc::new(Mutex::new(model))
-            }
-            Err(_) => Arc::new(Mutex::new(Model::default())),
-        }
+        let model = crate::load_model(&index_path).map_err(|()| "could not load the index")?;
+        Arc::new(Mutex::new(model))
     } else {
-        // Build a new index and save it
+        // Build a new index and save it. The terminal is still in normal mode here,
+        // so progress is shown on stderr instead of a blank screen
         let wrapped = Arc::new(Mutex::new(Model::default()));
-        let mut processed = 0;
-        add_folder_to_model(&current_dir, Arc::clone(&wrapped), &mut processed).map_err(|_| "Failed to index folder")?;
+        let (processed, _) = add_folder_to_model_with_progress(&current_dir, Arc::clone(&wrapped), &index_options)
+            .map_err(|_| "Failed to index folder")?;
         if processed > 0 {
-            if let Ok(file) = File::create(&index_path) {
-                let writer = BufWriter::new(file);
-                let model = wrapped.lock().unwrap();
-                serde_json::to_writer(writer, &*model)?;
-            }
+            crate::save_model(&wrapped.lock().unwrap(), &index_path).map_err(|()| "could not save the index")?;
         }
         wrapped
     };
@@ -352,11 +1045,24 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     // Create index with the populated model
     let mut index = Index::new();
     index.model = final_model;
+    index.model.ranking = ranking;
+    index.model.synonyms = synonyms;
+    // A loaded index keeps its tokenizer; say so if --no-stem asked for another one
+    crate::apply_requested_tokenizer(&mut index.model, &index_options);
+    STEMMED_HIGHLIGHTS.store(index.model.tokenizer.stem, Ordering::Relaxed);
+    index.scan_limit = scan_limit;
+    index.filename_weight = filename_weight;
+    index.preview_columns = preview_columns;
+    index.max_results = max_results;
+    index.preview_pool = preview_pool(preview_threads)?;
+    index.favorites = load_favorites().map_err(|err| format!("could not read the favorites: {err}"))?;
 
     // Build filename cache for fast filename searches
     index.build_filename_cache();
 
-    // Setup terminal
+    // Setup terminal; from here on nothing may print over it, and previews don't wait for parsers
+    logging::set_silent(true);
+    EXTRACT_IN_BACKGROUND.store(true, Ordering::Relaxed);
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -364,7 +1070,8 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app and run it
-    let app = App::new(index);
+    let mut app = App::new(index, current_dir);
+    app.badges = !args.iter().any(|a| a == "--no-badges");
     let res = run_app(&mut terminal, app);
 
     // Restore terminal
@@ -375,6 +1082,7 @@ pub fn main() -> Result<(), Box<dyn Error>> {
         DisableMouseCapture
     )?;
     terminal.show_cursor()?;
+    logging::set_silent(false);
 
     match res {
         Ok(RunOutcome::Quit) => {}
@@ -389,6 +1097,75 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 }
 
 
+/// Size of the sample drawn by `preview_theme`; the width follows the terminal when known
+const THEME_PREVIEW_HEIGHT: u16 = 22;
+const THEME_PREVIEW_WIDTH: u16 = 100;
+
+/// Prints a sample of the TUI drawn with the theme in `theme_path`, so a theme can be tried
+/// out without an index. The results and preview are made up. The sample is drawn off-screen
+/// and printed as colored text, so it also works when the output is piped to `less -R`.
+pub fn preview_theme(theme_path: &Path) -> Result<(), Box<dyn Error>> {
+    theme::init(Theme::load(theme_path)?);
+
+    let root = PathBuf::from("/home/you/notes");
+    let mut app = App::new(Index::new(), root.clone());
+    app.query = "penalty act".to_string();
+    let sample = |path: &str, preview_line: &str, is_filename_match: bool| SearchResult {
+        file_path: root.join(path),
+        preview_line: preview_line.to_string(),
+        score: 0.0,
+        is_filename_match,
+        start_line: 0,
+        matches: if is_filename_match { 0 } else { 2 },
+        section: None,
+    };
+    app.results = vec![
+        sample("law/penalty-act.md", "# Penalty Act", true),
+        sample("law/offences.txt", "Every offence under this act carries a penalty", false),
+        sample("drafts/fines.md", "penalties are reviewed each year", false),
+    ];
+    app.results_state.select(Some(0));
+    app.notice = Some(format!("Theme preview of {}", theme_path.display()));
+
+    let query_words = ["penalty", "act"];
+    let theme = Theme::current();
+    app.preview_spans = vec![
+        Line::from("      1: # Penalty Act"),
+        Line::from("      2: "),
+        create_highlighted_line("The penalty act applies to every offence listed below.", &query_words, ">>>   3: ")
+            .patch_style(Style::default().bg(theme.highlight_bg)),
+        Line::from("      4: Fines are set by the court."),
+        Line::from("      5: See also: offences.txt"),
+    ];
+
+    let width = crossterm::terminal::size().map(|(width, _)| width).unwrap_or(THEME_PREVIEW_WIDTH);
+    let mut terminal = Terminal::new(TestBackend::new(width, THEME_PREVIEW_HEIGHT))?;
+    terminal.draw(|f| ui(f, &mut app))?;
+    print_buffer(terminal.backend().buffer())?;
+    Ok(())
+}
+
+/// Writes the cells of `buffer` to stdout with their colors and boldness.
+fn print_buffer(buffer: &ratatui::buffer::Buffer) -> io::Result<()> {
+    use crossterm::style::{Attribute, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+    use io::Write;
+
+    let mut stdout = io::stdout().lock();
+    let width = buffer.area.width as usize;
+    for row in buffer.content().chunks(width) {
+        for cell in row {
+            let bold = if cell.modifier.contains(Modifier::BOLD) { Attribute::Bold } else { Attribute::NormalIntensity };
+            crossterm::queue!(stdout,
+                SetForegroundColor(cell.fg.into()),
+                SetBackgroundColor(cell.bg.into()),
+                SetAttribute(bold),
+                Print(cell.symbol()))?;
+        }
+        crossterm::queue!(stdout, SetAttribute(Attribute::Reset), ResetColor, Print("\n"))?;
+    }
+    stdout.flush()
+}
+
 /// The main application loop.
 enum RunOutcome { Quit, Open(PathBuf) }
 
@@ -406,8 +1183,50 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<R
         if crossterm::event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
                 if key.kind == KeyEventKind::Press {
+                    // Jump mode captures digits so they don't end up in the query
+                    if app.jump_input.is_some() {
+                        match key.code {
+                            KeyCode::Char(c) if c.is_ascii_digit() => { app.on_jump_digit(c); continue; }
+                            KeyCode::Backspace => { app.on_jump_backspace(); continue; }
+                            KeyCode::Enter | KeyCode::Esc => { app.finish_jump(); continue; }
+                            _ => app.finish_jump(),
+                        }
+                    }
+                    // Find mode captures typing, then n/N; any other key leaves it and acts as usual
+                    if let Some(editing) = app.find.as_ref().map(|find| find.editing) {
+                        let plain = !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+                        match key.code {
+                            KeyCode::Esc => { app.close_find(); continue; }
+                            KeyCode::Char(c) if editing && plain => { app.on_find_key(c); continue; }
+                            KeyCode::Backspace if editing => { app.on_find_backspace(); continue; }
+                            KeyCode::Enter if editing => { app.set_find_editing(false); continue; }
+                            KeyCode::Char('n') if !editing && plain => { app.step_find(true); continue; }
+                            KeyCode::Char('N') if !editing && plain => { app.step_find(false); continue; }
+                            KeyCode::Char('/') if !editing && plain => { app.set_find_editing(true); continue; }
+                            _ => app.close_find(),
+                        }
+                    }
                     match key.code {
                         KeyCode::Esc => return Ok(RunOutcome::Quit),
+                        KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => app.start_jump(),
+                        KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => app.toggle_filename_only(),
+                        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => app.toggle_recent_first(),
+                        KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => app.stacked_layout = !app.stacked_layout,
+                        KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => app.relative_paths = !app.relative_paths,
+                        KeyCode::Char('k') if key.modifiers.contains(KeyModifiers::CONTROL) => app.compact_rows = !app.compact_rows,
+                        KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => app.badges = !app.badges,
+                        KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => app.toggle_multi_context(),
+                        KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => app.toggle_truncate_lines(),
+                        KeyCode::Left if key.modifiers.contains(KeyModifiers::CONTROL) => app.resize_results(-(RESULTS_PERCENT_STEP as i16)),
+                        KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => app.resize_results(RESULTS_PERCENT_STEP as i16),
+                        KeyCode::Char(c) if c.is_ascii_digit() && key.modifiers.contains(KeyModifiers::ALT) => app.on_jump_digit(c),
+                        KeyCode::Tab => app.accept_suggestion(),
+                        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => app.hide_selected(),
+                        KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::ALT) => app.exclude_selected(),
+                        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::ALT) => app.toggle_favorite_selected(),
+                        KeyCode::Char('y') if key.modifiers.contains(KeyModifiers::ALT) => app.copy_selected_snippet(),
+                        KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::ALT) => app.start_find(),
+                        KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::ALT) => app.open_selected_folder(),
                         KeyCode::Char(c) => app.on_key(c),
                         KeyCode::Backspace => app.on_backspace(),
                         KeyCode::Down => app.next_result(),
@@ -425,6 +1244,8 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<R
             }
         }
 
+        app.show_finished_extractions();
+
         // Debounced search trigger
         if app.needs_search {
             if let Some(t) = app.last_input_time {
@@ -441,7 +1262,7 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<R
 
 /// Renders the user interface.
 fn ui(f: &mut Frame, app: &mut App) {
-    let theme = Theme::default();
+    let theme = Theme::current();
     let size = f.size();
     // Paint background
     let bg_block = Block::default().style(Style::default().bg(theme.background));
@@ -458,7 +1279,14 @@ fn ui(f: &mut Frame, app: &mut App) {
         .split(size);
 
     // Header
-    let header = Paragraph::new("  Khoj • ↑↓ navigate • Enter open • Esc quit")
+    let mut header_text = "  Khoj • ↑↓ navigate • Ctrl+G jump • Ctrl+F filenames • Enter open • Esc quit".to_string();
+    if app.filename_only {
+        header_text.push_str("  [filename-only]");
+    }
+    if app.recent_first {
+        header_text.push_str("  [newest first]");
+    }
+    let header = Paragraph::new(header_text)
         .style(Style::default().fg(theme.foreground).bg(theme.highlight_bg).add_modifier(Modifier::BOLD));
     f.render_widget(header, layout[0]);
 
@@ -473,9 +1301,10 @@ fn ui(f: &mut Frame, app: &mut App) {
     f.render_widget(input, layout[1]);
     f.set_cursor(layout[1].x + app.query.len() as u16 + 1, layout[1].y + 1);
 
+    let content_direction = if app.stacked_layout { Direction::Vertical } else { Direction::Horizontal };
     let content_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(42), Constraint::Percentage(58)].as_ref())
+        .direction(content_direction)
+        .constraints([Constraint::Percentage(app.results_percent), Constraint::Percentage(100 - app.results_percent)].as_ref())
         .split(layout[2]);
 
     // Prepare query words
@@ -485,15 +1314,36 @@ fn ui(f: &mut Frame, app: &mut App) {
     // Results items with theme
     let results_items: Vec<ListItem> = app.results.iter().map(|res| {
         let file_name = res.file_path.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown");
-        let dir_path = res.file_path.parent().and_then(|p| p.to_str()).unwrap_or("");
-        let trimmed_preview = if res.preview_line.is_empty() {"(preview on select)".to_string()} else if res.preview_line.len()>80 {format!("{}…", &res.preview_line[..77])} else {res.preview_line.clone()};
-        let filename_line = create_highlighted_line(file_name, &q_words, "");
+        let dir_path = app.display_dir(&res.file_path);
+        let trimmed_preview = if res.preview_line.is_empty() {"(preview on select)".to_string()} else {shorten(&res.preview_line, 80)};
+        let marker = if app.index.favorites.contains(&res.file_path) { "★ " } else { "" };
+        let mut filename_line = create_highlighted_line(file_name, &q_words, marker);
+        if let Some(badge) = file_badge(&res.file_path).filter(|_| app.badges) {
+            // Source code stands out from documents and data
+            let color = if crate::lexer::is_code_file(&res.file_path) { theme.accent } else { theme.primary };
+            filename_line.spans.insert(1, Span::styled(format!("[{badge}] "), Style::default().fg(color).add_modifier(Modifier::BOLD)));
+        }
+        if res.matches > 0 {
+            filename_line.spans.push(Span::styled(format!("  ×{}", res.matches), Style::default().fg(theme.secondary)));
+        }
+        if app.compact_rows {
+            let mut line = filename_line;
+            line.spans.extend(create_highlighted_line_with_base(&trimmed_preview, &q_words, "  ", Style::default().fg(theme.secondary)).spans);
+            return ListItem::new(line).style(Style::default().fg(theme.foreground));
+        }
         let preview_line = create_highlighted_line(&trimmed_preview, &q_words, "  → ");
-        let path_line = Line::from(vec![Span::styled("  ", Style::default()), Span::styled(dir_path.to_string(), Style::default().fg(theme.secondary))]);
+        let mut path_line = create_highlighted_line_with_base(&dir_path, &q_words, "  ", Style::default().fg(theme.secondary));
+        if let Some(section) = &res.section {
+            path_line.spans.push(Span::styled(format!("  under {section}"), Style::default().fg(theme.secondary)));
+        }
         ListItem::new(vec![filename_line, path_line, preview_line]).style(Style::default().fg(theme.foreground))
     }).collect();
 
-    let results_title = format!("Results ({})", app.results.len());
+    let results_title = if app.total_results > app.index.max_results {
+        format!("Results ({} of {}, capped)", app.results.len(), app.total_results)
+    } else {
+        format!("Results ({})", app.results.len())
+    };
     let results_list = List::new(results_items)
         .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(theme.border)).title(Span::styled(results_title, Style::default().fg(theme.secondary).add_modifier(Modifier::BOLD))))
         .highlight_style(Style::default().bg(theme.highlight_bg).fg(theme.highlight_fg).add_modifier(Modifier::BOLD))
@@ -501,10 +1351,30 @@ fn ui(f: &mut Frame, app: &mut App) {
     f.render_stateful_widget(results_list, content_chunks[0], &mut app.results_state);
 
     let preview_block = Block::default().borders(Borders::ALL).border_style(Style::default().fg(theme.border)).title(Span::styled("Preview", Style::default().fg(theme.secondary).add_modifier(Modifier::BOLD)));
-    let preview = Paragraph::new(app.preview_spans.clone()).wrap(Wrap { trim: true }).block(preview_block).style(Style::default().fg(theme.foreground));
+    let mut preview = Paragraph::new(app.preview_spans.clone()).block(preview_block).style(Style::default().fg(theme.foreground));
+    // Truncated lines were already cut to length; wrapping them would defeat the purpose
+    if !app.truncate_lines {
+        preview = preview.wrap(Wrap { trim: true });
+    }
     f.render_widget(preview, content_chunks[1]);
 
-    let footer_text = format!("  Query len: {}  •  Results: {}  ", app.query.chars().count(), app.results.len());
+    let mut footer_text = format!("  Query len: {}  •  Results: {}  ", app.query.chars().count(), app.results.len());
+    if let Some(pending) = &app.jump_input {
+        footer_text.push_str(&format!("•  Jump to: {}_  ", pending));
+    } else if let Some(find) = &app.find {
+        if find.editing {
+            footer_text.push_str(&format!("•  Find in file: {}_ ({}) • Enter done • Esc close  ", find.text, find.position()));
+        } else {
+            footer_text.push_str(&format!("•  Find in file: {} ({}) • n/N next/previous • / edit • Esc close  ", find.text, find.position()));
+        }
+    } else if let Some(notice) = &app.notice {
+        footer_text.push_str(&format!("•  {}  ", notice));
+    } else if let Some(suggestion) = &app.suggestion {
+        footer_text.push_str(&format!("•  Did you mean: {}? (Tab to accept)  ", suggestion));
+    }
+    if !app.hidden.is_empty() {
+        footer_text.push_str(&format!("•  Hidden: {}  ", app.hidden.len()));
+    }
     let footer = Paragraph::new(footer_text).style(Style::default().fg(theme.foreground).bg(theme.highlight_bg));
     f.render_widget(footer, layout[3]);
 }
@@ -513,9 +1383,11 @@ fn ui(f: &mut Frame, app: &mut App) {
 // --- Helper Functions ---
 
 /// Enhanced preview function that returns both plain text and styled spans for highlighting
-fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(String, Vec<Line<'static>>), Box<dyn Error>> {
-    let file = std::fs::File::open(file_path)?;
-    let mut reader = BufReader::new(file);
+/// Previews the first match of `query` at or after `start_line` (0-based), scanning at most
+/// `scan_limit` lines from there. With a `line_limit`, lines are cut at that many characters
+/// (see `clip_line`).
+fn get_enhanced_preview_with_styling(file_path: &Path, query: &str, start_line: usize, scan_limit: usize, line_limit: Option<usize>) -> Result<(String, Vec<Line<'static>>), Box<dyn Error>> {
+    let mut reader = open_for_preview(file_path)?;
 
     let query_lower = query.to_lowercase();
     let query_words: Vec<&str> = query_lower.split_whitespace().filter(|w| !w.is_empty()).collect();
@@ -531,6 +1403,7 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
     let mut prev_lines: VecDeque<(usize, String)> = VecDeque::with_capacity(3);
     let mut line_num = 0usize;
     let mut match_found = false;
+    let mut capped = false;
 
     // Also collect first 15 lines for fallback
     let mut first_lines: Vec<String> = Vec::new();
@@ -546,11 +1419,13 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
         }
     } {
         line_num += 1;
-        let line = buf.trim_end_matches(['\n', '\r']).to_string();
+        let full_line = buf.trim_end_matches(['\n', '\r']);
+        // Matching looks at the whole line; only what is shown gets cut
+        let ll = full_line.to_lowercase();
+        let line = clip_line(full_line, &query_words, line_limit);
         if first_lines.len() < 15 { first_lines.push(format!("    {:3}: {}", line_num, &line)); }
 
-        let ll = line.to_lowercase();
-        if !match_found && query_words.iter().any(|w| ll.contains(w)) {
+        if !match_found && line_num > start_line && query_words.iter().any(|w| ll.contains(w)) {
             // Emit previous context lines
             for (n, pline) in prev_lines.iter() {
                 let plain = format!("    {:3}: {}", n, pline);
@@ -560,7 +1435,9 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
             // Emit the matching line with highlight
             let prefix = format!(">>> {:3}: ", line_num);
             preview_lines.push(format!("{}{}", &prefix, &line));
-            styled_lines.push(create_highlighted_line(&line, &query_words, &prefix));
+            // Give the matched line a background so it stands out from the context around it
+            let theme = Theme::current();
+            styled_lines.push(create_highlighted_line(&line, &query_words, &prefix).patch_style(Style::default().bg(theme.highlight_bg)));
 
             // Emit up to 10 lines after match
             for i in 0..10 {
@@ -568,7 +1445,7 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
                 match reader.read_line(&mut buf) {
                     Ok(0) | Err(_) => break,
                     Ok(_) => {
-                        let next_line = buf.trim_end_matches(['\n','\r']).to_string();
+                        let next_line = clip_line(buf.trim_end_matches(['\n','\r']), &query_words, line_limit);
                         let ln = line_num + i + 1;
                         let plain = format!("    {:3}: {}", ln, &next_line);
                         preview_lines.push(plain.clone());
@@ -586,7 +1463,7 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
         prev_lines.push_back((line_num, line));
 
         // Safety: hard limit on lines scanned
-        if line_num >= 5000 { break; }
+        if line_num >= start_line + scan_limit { capped = true; break; }
     }
 
     if !match_found {
@@ -594,6 +1471,9 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
         if first_lines.is_empty() {
             first_lines.push("(empty file)".to_string());
         }
+        if capped {
+            first_lines.insert(0, format!("(no match in first {} lines)", scan_limit));
+        }
         let styled: Vec<Line<'static>> = first_lines.iter().map(|l| Line::from(l.clone())).collect();
         return Ok((first_lines.join("\n"), styled));
     }
@@ -601,27 +1481,296 @@ fn get_enhanced_preview_with_styling(file_path: &Path, query: &str) -> Result<(S
     Ok((preview_lines.join("\n"), styled_lines))
 }
 
+/// `text` if it has at most `max_chars` characters, otherwise its start ending in `…`. Counts
+/// characters rather than bytes, so a cut never lands inside an accented letter.
+fn shorten(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some(_) => format!("{}…", text.chars().take(max_chars.saturating_sub(3)).collect::<String>()),
+        None => text.to_string(),
+    }
+}
+
+/// Cuts `line` to `limit` characters, marking the cut with `…`, so minified code and
+/// single-line JSON don't fill the preview. If the first match of `query_words` would be cut
+/// off, the shown part starts shortly before it instead. `None` keeps the line whole.
+fn clip_line(line: &str, query_words: &[&str], limit: Option<usize>) -> String {
+    let len = line.chars().count();
+    let limit = match limit {
+        Some(limit) if len > limit => limit,
+        _ => return line.to_string(),
+    };
+    let lower = line.to_lowercase();
+    let first_match = query_words.iter()
+        .filter_map(|word| lower.find(word).map(|at| (lower[..at].chars().count(), word.chars().count())))
+        .min();
+    let start = match first_match {
+        Some((at, word_len)) if at + word_len >= limit => at.saturating_sub(limit / 4).min(len - limit),
+        _ => 0,
+    };
+
+    let mut end = start + limit - usize::from(start > 0);
+    if end < len {
+        // Make room for the trailing marker
+        end -= 1;
+    }
+    let mut clipped = String::new();
+    if start > 0 {
+        clipped.push('…');
+    }
+    clipped.extend(line.chars().skip(start).take(end - start));
+    if end < len {
+        clipped.push('…');
+    }
+    clipped
+}
+
+/// Whether the index stems words, so highlighting should also mark the other forms of a query
+/// word (see `highlight_ranges`). Set once the index is loaded.
+static STEMMED_HIGHLIGHTS: AtomicBool = AtomicBool::new(true);
+
+/// Byte ranges of `line` to highlight for `query_words`, sorted and not overlapping: wherever a
+/// query word occurs and, if the index stems words, every word of the line with the same stem
+/// as a query word, so "running" also highlights "runs" like the search matched it.
+fn highlight_ranges(line: &str, query_words: &[&str]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let lower = line.to_lowercase();
+    // Lowercasing a few characters changes their length, which would shift the positions
+    if lower.len() == line.len() {
+        for word in query_words.iter().filter(|word| word.len() > 1) {
+            let word = word.to_lowercase();
+            ranges.extend(lower.match_indices(&word)
+                .map(|(start, _)| start..start + word.len())
+                .filter(|range| line.is_char_boundary(range.start) && line.is_char_boundary(range.end)));
+        }
+    }
+    if STEMMED_HIGHLIGHTS.load(Ordering::Relaxed) {
+        let stems: HashSet<String> = query_words.iter()
+            .filter(|word| word.chars().all(char::is_alphabetic))
+            .map(|word| crate::lexer::stem(word.to_lowercase()))
+            .collect();
+        if !stems.is_empty() {
+            let mut start = None;
+            for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+                match start {
+                    None if c.is_alphabetic() => start = Some(i),
+                    Some(word_start) if !c.is_alphanumeric() => {
+                        if stems.contains(&crate::lexer::stem(line[word_start..i].to_lowercase())) {
+                            ranges.push(word_start..i);
+                        }
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
 /// Create a highlighted line with colored spans
 fn create_highlighted_line(line: &str, query_words: &[&str], prefix: &str) -> Line<'static> {
-    let theme = Theme::default();
+    create_highlighted_line_with_base(line, query_words, prefix, Style::default())
+}
+
+/// Like `create_highlighted_line`, but non-matching text is drawn with `base` (e.g. dimmed for paths).
+fn create_highlighted_line_with_base(line: &str, query_words: &[&str], prefix: &str, base: Style) -> Line<'static> {
+    let theme = Theme::current();
     let mut spans = vec![Span::styled(prefix.to_string(), Style::default().fg(theme.secondary))];
-    let mut remaining = line.to_string();
-    while !remaining.is_empty() {
-        let mut found_match = false; let mut earliest_pos = remaining.len(); let mut match_len = 0;
-        for word in query_words { if !word.is_empty() && word.len()>1 { let rem_lower = remaining.to_lowercase(); let w_lower = word.to_lowercase(); if let Some(pos)=rem_lower.find(&w_lower) { if pos < earliest_pos { earliest_pos = pos; match_len = word.len(); found_match=true; } } } }
-        if found_match { if earliest_pos>0 { spans.push(Span::raw(remaining[..earliest_pos].to_string())); }
-            let matched_text = &remaining[earliest_pos..earliest_pos+match_len];
-            spans.push(Span::styled(matched_text.to_string(), Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)));
-            remaining = remaining[earliest_pos+match_len..].to_string();
-        } else { spans.push(Span::raw(remaining.clone())); break; }
+    let mut end = 0;
+    for range in highlight_ranges(line, query_words) {
+        if range.start > end {
+            spans.push(Span::styled(line[end..range.start].to_string(), base));
+        }
+        spans.push(Span::styled(line[range.clone()].to_string(), Style::default().fg(theme.match_highlight).add_modifier(Modifier::BOLD)));
+        end = range.end;
+    }
+    if end < line.len() {
+        spans.push(Span::styled(line[end..].to_string(), base));
     }
     Line::from(spans)
 }
 
+/// Most extracted texts kept by `extracted_text`; the cache starts over once it fills up
+const EXTRACTED_TEXT_CACHE_CAPACITY: usize = 64;
+/// Threads extracting text for previews while the TUI runs (see `extract_in_background`)
+const EXTRACTION_THREADS: usize = 2;
+
+/// Path -> (modification time, extracted text or `None` if extraction failed)
+type ExtractedTexts = HashMap<PathBuf, (SystemTime, Option<String>)>;
+
+/// Shared by the preview threads so moving through results doesn't re-parse the same PDF
+/// over and over.
+static EXTRACTED_TEXT: OnceLock<Mutex<ExtractedTexts>> = OnceLock::new();
+/// Set while the TUI runs: previews then never wait for a parser, see `extract_in_background`
+static EXTRACT_IN_BACKGROUND: AtomicBool = AtomicBool::new(false);
+/// Files being extracted in the background
+static EXTRACTING: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+static EXTRACTION_POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
+/// Number of background extractions finished, so the TUI knows when to show their text
+static EXTRACTIONS_FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+fn extracted_text_cache() -> MutexGuard<'static, ExtractedTexts> {
+    EXTRACTED_TEXT.get_or_init(Mutex::default).lock().unwrap()
+}
+
+/// Whether files like `path` aren't plain text, so previews must show the text the indexer
+/// extracts from them rather than their raw bytes.
+fn needs_extraction(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    matches!(extension.as_deref(), Some("pdf" | "xlsx")) || (extension.as_deref() == Some("gz") && crate::is_log_file(path))
+        || extension.is_some_and(|e| crate::external_parser(&e).is_some())
+}
+
+/// Opens `path` for reading line by line in previews and snippets. In the TUI, text that
+/// has to be extracted and isn't cached yet fails with `WouldBlock` until it is.
+fn open_for_preview(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    // Archive members are re-read from the archive, which has to be parsed either way
+    if needs_extraction(path) || archive::split_member_path(path).is_some() {
+        let text = if EXTRACT_IN_BACKGROUND.load(Ordering::Relaxed) { extract_in_background(path)? } else { extracted_text(path)? };
+        Ok(Box::new(Cursor::new(text)))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// The text of `path` as extracted by the indexer's parser, cached until the file (or the
+/// archive it's a member of) changes. A file that can't be extracted isn't tried again
+/// until then either.
+fn extracted_text(path: &Path) -> io::Result<String> {
+    let modified = archive::file_on_disk(path).metadata()?.modified()?;
+    if let Some(text) = cached_extracted_text(path, modified) {
+        return text;
+    }
+
+    let text = if archive::split_member_path(path).is_some() {
+        archive::parse_member_path(path)
+    } else {
+        crate::parse_entire_file_by_extension(path)
+            .map_err(|()| io::Error::new(io::ErrorKind::InvalidData, "could not extract text"))
+    };
+    let mut cache = extracted_text_cache();
+    if cache.len() >= EXTRACTED_TEXT_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(path.to_path_buf(), (modified, text.as_ref().ok().cloned()));
+    text
+}
+
+/// The cached outcome of extracting `path` as it was at `modified`, if any.
+fn cached_extracted_text(path: &Path, modified: SystemTime) -> Option<io::Result<String>> {
+    let cache = extracted_text_cache();
+    let (_, text) = cache.get(path).filter(|(time, _)| *time == modified)?;
+    Some(text.clone().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "could not extract text")))
+}
+
+/// Like `extracted_text`, but text that isn't cached yet is extracted on the extraction
+/// threads, so a slow parser or OCR never holds up typing or moving through results.
+/// Fails with `WouldBlock` until the text is ready; see `App::show_finished_extractions`.
+fn extract_in_background(path: &Path) -> io::Result<String> {
+    let modified = archive::file_on_disk(path).metadata()?.modified()?;
+    if let Some(text) = cached_extracted_text(path, modified) {
+        return text;
+    }
+    if EXTRACTING.get_or_init(Mutex::default).lock().unwrap().insert(path.to_path_buf()) {
+        let path = path.to_path_buf();
+        let pool = EXTRACTION_POOL.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(EXTRACTION_THREADS)
+                .thread_name(|i| format!("khoj-extract-{i}"))
+                .build()
+                .expect("could not start the extraction threads")
+        });
+        pool.spawn(move || {
+            let _ = extracted_text(&path);
+            EXTRACTING.get_or_init(Mutex::default).lock().unwrap().remove(&path);
+            EXTRACTIONS_FINISHED.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    Err(io::Error::new(io::ErrorKind::WouldBlock, "still extracting its text"))
+}
+
+/// Plain preview text and the same lines styled for display
+type StyledPreview = (String, Vec<Line<'static>>);
+
+/// Previews up to `MAX_MATCH_CONTEXTS` separate places in the file that match `query`,
+/// divided by `...`. Places matching query words not seen in an earlier one are preferred,
+/// so a multi-word query shows why each word matched. Returns `None` if there are fewer than
+/// two matches within `scan_limit` lines after `start_line`. Lines are cut at `line_limit`
+/// characters, if given (see `clip_line`).
+fn get_multi_context_preview(file_path: &Path, query: &str, start_line: usize, scan_limit: usize, line_limit: Option<usize>) -> Result<Option<StyledPreview>, Box<dyn Error>> {
+    let query_lower = query.to_lowercase();
+    let query_words: Vec<&str> = query_lower.split_whitespace().filter(|w| !w.is_empty()).collect();
+    if query_words.is_empty() {
+        return Ok(None);
+    }
+
+    let reader = open_for_preview(file_path)?;
+    let lines: Vec<String> = reader.lines().take(start_line + scan_limit).map(|line| line.unwrap_or_default()).collect();
+    let matches: Vec<(usize, Vec<&str>)> = lines.iter().enumerate().skip(start_line)
+        .filter_map(|(i, line)| {
+            let lower = line.to_lowercase();
+            let words: Vec<&str> = query_words.iter().copied().filter(|w| lower.contains(w)).collect();
+            (!words.is_empty()).then_some((i, words))
+        })
+        .collect();
+
+    let (before, after) = MATCH_CONTEXT_LINES;
+    let overlaps = |chosen: &[usize], i: usize| chosen.iter().any(|&c| i.abs_diff(c) <= before + after);
+    let mut chosen: Vec<usize> = Vec::new();
+    let mut seen_words: HashSet<&str> = HashSet::new();
+    // First the places that add a word, then any other ones while there's room
+    for (i, words) in &matches {
+        if chosen.len() < MAX_MATCH_CONTEXTS && !overlaps(&chosen, *i) && words.iter().any(|w| !seen_words.contains(w)) {
+            seen_words.extend(words);
+            chosen.push(*i);
+        }
+    }
+    for (i, _) in &matches {
+        if chosen.len() < MAX_MATCH_CONTEXTS && !overlaps(&chosen, *i) {
+            chosen.push(*i);
+        }
+    }
+    if chosen.len() < 2 {
+        return Ok(None);
+    }
+    chosen.sort_unstable();
+
+    let theme = Theme::current();
+    let mut preview_lines: Vec<String> = Vec::new();
+    let mut styled_lines: Vec<Line<'static>> = Vec::new();
+    for (n, &matched) in chosen.iter().enumerate() {
+        if n > 0 {
+            preview_lines.push("    ...".to_string());
+            styled_lines.push(Line::styled("    ...", Style::default().fg(theme.secondary)));
+        }
+        let first = matched.saturating_sub(before);
+        for (i, line) in lines.iter().enumerate().skip(first).take(matched + after + 1 - first) {
+            let line = clip_line(line, &query_words, line_limit);
+            if i == matched {
+                let prefix = format!(">>> {:3}: ", i + 1);
+                preview_lines.push(format!("{}{}", &prefix, line));
+                styled_lines.push(create_highlighted_line(&line, &query_words, &prefix).patch_style(Style::default().bg(theme.highlight_bg)));
+            } else {
+                let plain = format!("    {:3}: {}", i + 1, line);
+                styled_lines.push(create_highlighted_line(&plain, &query_words, ""));
+                preview_lines.push(plain);
+            }
+        }
+    }
+    Ok(Some((preview_lines.join("\n"), styled_lines)))
+}
+
 /// Simple preview function with styling that reads the first few lines of a file
 fn get_simple_preview_with_styling(file_path: &Path) -> Result<(String, Vec<Line<'static>>), Box<dyn Error>> {
-    let file = std::fs::File::open(file_path)?;
-    let reader = BufReader::new(file);
+    let reader = open_for_preview(file_path)?;
     let mut lines: Vec<String> = Vec::new();
     for (i, line) in reader.lines().enumerate() {
         if i >= 20 { break; }
@@ -639,12 +1788,28 @@ fn open_file_external(path: &Path) {
     let _ = disable_raw_mode();
     let mut stdout = io::stdout();
     let _ = execute!(stdout, DisableMouseCapture);
+    // Archive members only exist inside the archive, so the editor gets a temporary copy
+    let extracted;
+    let path = if archive::split_member_path(path).is_some() {
+        match archive::extract_to_temp(path) {
+            Ok(copy) => {
+                extracted = copy;
+                extracted.as_path()
+            }
+            Err(err) => {
+                eprintln!("ERROR: could not extract {}: {err}", path.display());
+                return;
+            }
+        }
+    } else {
+        path
+    };
     // Launch editor
     let (program, mut args) = select_editor();
     args.push(path.to_string_lossy().to_string());
-    // For GUI editors (code/code-insiders) launch detached (non-blocking). For terminal editors, block.
-    if program == "code" || program == "code-insiders" {
-    if let Ok(child) = Command::new(&program)
+    // For GUI editors (code, notepad, ...) launch detached (non-blocking). For terminal editors, block.
+    if is_gui_editor(&program) {
+        if let Ok(child) = Command::new(&program)
             .args(&args)
             .stdin(Stdio::null())
             .stdout(Stdio::null())
@@ -664,24 +1829,173 @@ fn open_file_external(path: &Path) {
     println!("");
 }
 
+/// Opens `folder` with the platform's file manager: `open` on macOS, `explorer` on Windows and
+/// `xdg-open` elsewhere. Unlike a terminal editor it doesn't need the terminal, so it's launched
+/// detached with its output discarded while the TUI keeps running.
+fn open_folder_external(folder: &Path) -> io::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut child = Command::new(opener)
+        .arg(folder)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap it once it exits so it doesn't linger as a zombie until khoj quits
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Puts `text` on the system clipboard with the platform's tool: `pbcopy` on macOS, `clip` on
+/// Windows and `wl-copy`, `xclip` or `xsel` elsewhere, whichever is installed. Returns the
+/// name of the tool that took it.
+fn copy_to_clipboard(text: &str) -> io::Result<&'static str> {
+    use std::io::Write;
+
+    let tools: &[(&'static str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip", &[])]
+    } else {
+        &[("wl-copy", &[]), ("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+    };
+    for (tool, args) in tools {
+        let mut child = match Command::new(tool).args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        // Closing stdin tells the tool the text is complete
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        // Without a display, wl-copy and xclip fail; try the next tool
+        if child.wait()?.success() {
+            return Ok(tool);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no clipboard available; install wl-clipboard, xclip or xsel"))
+}
+
+/// Appends a pattern matching exactly `path` to `.khojignore` in the current directory.
+/// Returns the pattern that was written.
+fn append_to_khojignore(path: &Path) -> io::Result<String> {
+    use std::io::Write;
+
+    let root = env::current_dir()?;
+    let relative = path.strip_prefix(&root).unwrap_or(path);
+    // Anchor to the root and escape characters gitignore patterns treat specially
+    let mut pattern = String::from("/");
+    let components: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    for c in components.join("/").chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '!' | '#' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+
+    let khojignore = root.join(".khojignore");
+    let needs_newline = std::fs::read(&khojignore).map(|c| !c.is_empty() && !c.ends_with(b"\n")).unwrap_or(false);
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&khojignore)?;
+    if needs_newline {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", pattern)?;
+    Ok(pattern)
+}
+
+/// Where the favorites are kept: `khoj/favorites.txt` in `$XDG_CONFIG_HOME`, `~/.config` or,
+/// on Windows, `%APPDATA%`. One absolute path per line.
+fn favorites_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("khoj").join("favorites.txt"))
+}
+
+/// The saved favorites; none if there aren't any yet.
+fn load_favorites() -> io::Result<HashSet<PathBuf>> {
+    let Some(path) = favorites_path() else { return Ok(HashSet::new()) };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(content.lines().filter(|line| !line.trim().is_empty()).map(PathBuf::from).collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn save_favorites(favorites: &HashSet<PathBuf>) -> io::Result<()> {
+    let path = favorites_path().ok_or_else(|| io::Error::other("no config directory; set XDG_CONFIG_HOME or HOME"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut lines: Vec<String> = favorites.iter().map(|path| path.to_string_lossy().into_owned()).collect();
+    lines.sort();
+    std::fs::write(&path, lines.iter().map(|line| format!("{line}\n")).collect::<String>())
+}
+
+/// Whether `program` is a GUI editor that should be launched detached instead of
+/// taking over the terminal. Ignores any directory and `.exe`/`.cmd` suffix.
+fn is_gui_editor(program: &str) -> bool {
+    let name = Path::new(program)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(program)
+        .to_ascii_lowercase();
+    matches!(name.as_str(), "code" | "code-insiders" | "notepad" | "notepad++" | "subl")
+}
+
+/// Looks `bin` up in PATH and returns the name to launch it by. On Windows the
+/// executable suffixes from PATHEXT (`.exe`, `.cmd`, ...) are tried as well, and the
+/// suffixed name is returned since `Command` only appends `.exe` by itself.
+fn find_in_path(bin: &str) -> Option<String> {
+    let path_var = env::var_os("PATH")?;
+    let suffixes: Vec<String> = if cfg!(windows) {
+        let pathext = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+        std::iter::once(String::new())
+            .chain(pathext.split(';').filter(|e| !e.is_empty()).map(|e| e.to_ascii_lowercase()))
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    env::split_paths(&path_var).find_map(|dir| {
+        suffixes.iter()
+            .map(|suffix| format!("{bin}{suffix}"))
+            .find(|name| dir.join(name).is_file())
+    })
+}
+
+/// Splits an editor command from the environment (e.g. `code --wait`) into program and arguments.
+fn split_editor_command(command: &str) -> Option<(String, Vec<String>)> {
+    let mut parts = command.split_whitespace().map(str::to_string);
+    let program = parts.next()?;
+    Some((program, parts.collect()))
+}
+
 fn select_editor() -> (String, Vec<String>) {
-    // Helper to find a binary in PATH
-    fn in_path(bin: &str) -> bool {
-        if let Ok(path_var) = env::var("PATH") {
-            for p in env::split_paths(&path_var) {
-                let candidate = p.join(bin);
-                if candidate.is_file() { return true; }
-            }
+    // An explicitly configured editor always wins
+    for var in ["KHOJ_EDITOR", "EDITOR"] {
+        if let Some(editor) = env::var(var).ok().as_deref().and_then(split_editor_command) {
+            return editor;
         }
-        false
     }
 
-    for candidate in ["code", "code-insiders"].iter() {
-        if in_path(candidate) { return ((**candidate).to_string(), vec![]); }
+    for candidate in ["code", "code-insiders"] {
+        if let Some(program) = find_in_path(candidate) { return (program, vec![]); }
     }
 
-    if let Ok(ed) = env::var("KHOJ_EDITOR") { return (ed, vec![]); }
-    if let Ok(ed) = env::var("EDITOR") { return (ed, vec![]); }
-    if in_path("nano") { return ("nano".to_string(), vec![]); }
+    if cfg!(windows) {
+        return ("notepad".to_string(), vec![]);
+    }
+    if find_in_path("nano").is_some() { return ("nano".to_string(), vec![]); }
     ("vi".to_string(), vec![])
 }
@@ -0,0 +1,10 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// Creates a fresh scratch directory for the fixtures of a single test.
+pub fn fixture_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("khoj-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create fixture directory");
+    dir
+}
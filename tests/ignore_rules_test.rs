@@ -0,0 +1,67 @@
+mod common;
+
+use common::fixture_dir;
+use khoj::ignore_rules::{build_ignorer, matches};
+use std::fs;
+use std::path::PathBuf;
+
+/// Creates a fresh scratch directory with `.khojignore` holding `rules`, if any.
+fn root_with_rules(name: &str, rules: Option<&str>) -> PathBuf {
+    let dir = fixture_dir(name);
+    if let Some(rules) = rules {
+        fs::write(dir.join(".khojignore"), rules).expect("write .khojignore");
+    }
+    dir
+}
+
+#[test]
+fn patterns_match_files_and_everything_below_matched_directories() {
+    let root = root_with_rules("patterns", Some("*.log\nbuild\n"));
+    let ig = build_ignorer(&root);
+
+    assert!(matches(&ig, &root.join("server.log"), false));
+    assert!(matches(&ig, &root.join("nested/deep/server.log"), false));
+    assert!(matches(&ig, &root.join("build"), true));
+    assert!(matches(&ig, &root.join("build/out/main.rs"), false));
+    assert!(!matches(&ig, &root.join("server.txt"), false));
+    assert!(!matches(&ig, &root.join("rebuild.rs"), false));
+}
+
+#[test]
+fn trailing_slash_patterns_only_match_directories() {
+    let root = root_with_rules("directories", Some("cache/\n"));
+    let ig = build_ignorer(&root);
+
+    assert!(matches(&ig, &root.join("cache"), true));
+    assert!(matches(&ig, &root.join("cache/entry.txt"), false));
+    assert!(!matches(&ig, &root.join("cache"), false));
+    assert!(!matches(&ig, &root.join("src/cache"), false));
+}
+
+#[test]
+fn negated_patterns_keep_files_that_would_be_ignored() {
+    let root = root_with_rules("negation", Some("*.me\n!keep.me\n"));
+    let ig = build_ignorer(&root);
+
+    assert!(matches(&ig, &root.join("drop.me"), false));
+    assert!(!matches(&ig, &root.join("keep.me"), false));
+    assert!(!matches(&ig, &root.join("docs/keep.me"), false));
+}
+
+#[test]
+fn paths_outside_the_root_are_never_ignored() {
+    let root = root_with_rules("outside", Some("*.log\n"));
+    let ig = build_ignorer(&root);
+
+    assert!(!matches(&ig, &std::env::temp_dir().join("elsewhere/server.log"), false));
+}
+
+#[test]
+fn missing_khojignore_ignores_nothing() {
+    let root = root_with_rules("missing", None);
+    let ig = build_ignorer(&root);
+
+    assert!(ig.is_empty());
+    assert!(!matches(&ig, &root.join("server.log"), false));
+    assert!(!matches(&ig, &root.join("target"), true));
+}
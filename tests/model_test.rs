@@ -0,0 +1,820 @@
+mod common;
+
+use common::fixture_dir;
+use khoj::{add_folder_to_model, load_model, matching_lines, save_model, IndexOptions};
+use khoj::model::{search_merged, Chunk, IdfFormula, IndexProblem, Model, Synonyms, TieBreak, TokenizerConfig};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+fn add(model: &mut Model, path: &str, content: &str) {
+    let content = content.chars().collect::<Vec<_>>();
+    model.add_document(PathBuf::from(path), SystemTime::now(), &content);
+}
+
+fn search(model: &Model, query: &str) -> Vec<(PathBuf, f32)> {
+    model.search_query(&query.chars().collect::<Vec<_>>())
+}
+
+/// Writes `content` to `file_name` under `dir`, creating the directories in between.
+fn write_fixture(dir: &Path, file_name: &str, content: &str) -> PathBuf {
+    let path = dir.join(file_name);
+    fs::create_dir_all(path.parent().unwrap()).expect("create fixture directory");
+    fs::write(&path, content).expect("write fixture file");
+    path
+}
+
+#[test]
+fn single_document_corpus_scores_are_finite() {
+    // With one document every term has n/m = 1, so idf is log10(1) = 0
+    let mut model = Model::default();
+    add(&mut model, "only.md", "the quick brown fox jumps over the lazy dog");
+
+    for query in ["fox", "quick brown", "missing", "fox missing", "quick brown fox"] {
+        let results = search(&model, query);
+        assert_eq!(results.len(), 1, "query {query:?}");
+        for (path, rank) in results {
+            assert!(rank.is_finite() && rank >= 0.0, "{} scored {rank} for {query:?}", path.display());
+        }
+    }
+}
+
+#[test]
+fn empty_documents_are_not_indexed() {
+    let mut model = Model::default();
+    add(&mut model, "empty.md", "");
+    add(&mut model, "blank.md", "  \n\t\n");
+    add(&mut model, "fox.md", "the quick brown fox");
+    assert!(!model.docs.contains_key(Path::new("empty.md")));
+    assert!(!model.docs.contains_key(Path::new("blank.md")));
+
+    let results = search(&model, "fox");
+    assert_eq!(results.len(), 1);
+    assert!(results.iter().all(|(_, rank)| rank.is_finite()));
+    assert_eq!(results[0].0, Path::new("fox.md"));
+
+    // A file that was emptied drops out of the index
+    add(&mut model, "fox.md", "");
+    assert!(model.docs.is_empty() && model.df.is_empty());
+
+    // ...but is remembered, so it isn't parsed again until it changes
+    let emptied = SystemTime::now();
+    model.add_document(PathBuf::from("fox.md"), emptied, &[]);
+    assert!(!model.requires_reindexing(Path::new("fox.md"), emptied));
+    assert!(model.requires_reindexing(Path::new("fox.md"), emptied + Duration::from_secs(1)));
+}
+
+#[test]
+fn removed_documents_leave_no_degenerate_frequencies() {
+    let mut model = Model::default();
+    add(&mut model, "a.md", "alpha beta");
+    add(&mut model, "b.md", "gamma delta");
+    add(&mut model, "c.md", "gamma epsilon");
+
+    // Dropping the only document containing "alpha" must not leave a zero df behind
+    assert_eq!(model.retain_documents(|path| path != Path::new("a.md")), 1);
+    assert!(!model.df.contains_key("alpha"));
+
+    for query in ["alpha", "gamma", "alpha gamma"] {
+        let results = search(&model, query);
+        assert_eq!(results.len(), 2, "query {query:?}");
+        assert!(results.iter().all(|(_, rank)| rank.is_finite() && *rank >= 0.0), "query {query:?}");
+    }
+}
+
+#[test]
+fn did_you_mean_suggests_close_vocabulary_terms() {
+    let mut model = Model::default();
+    add(&mut model, "a.md", "penalty for every offence");
+    add(&mut model, "b.md", "the court decides the penalty");
+
+    assert_eq!(model.did_you_mean("court").as_deref(), None);
+    assert_eq!(model.did_you_mean("cuort").as_deref(), Some("court"));
+    // Known words and field filters are kept, unknown ones without a close term too
+    assert_eq!(model.did_you_mean("ext:md cort decides").as_deref(), Some("ext:md court decides"));
+    assert_eq!(model.did_you_mean("zzzzzz").as_deref(), None);
+    // Corrections are words from the documents, not their stems
+    assert_eq!(model.did_you_mean("penalyt").as_deref(), Some("penalty"));
+    assert_eq!(model.did_you_mean("offences cuort").as_deref(), Some("offences court"));
+}
+
+#[test]
+fn suggestions_are_words_as_written() {
+    let mut model = Model::default();
+    add(&mut model, "a.md", "penalties for every offence");
+    add(&mut model, "b.md", "the penalty and the penalties");
+    add(&mut model, "c.md", "pending cases");
+
+    let words = |prefix: &str| model.suggest(prefix, 10).into_iter().map(|(word, _)| word).collect::<Vec<_>>();
+    // "penalti" is the stem; the shortest word it came from is suggested instead
+    assert_eq!(words("pen"), ["penalty", "pending"]);
+    assert_eq!(words("penalty"), ["penalty"]);
+    assert_eq!(words("penalti"), ["penalty"]);
+
+    // Surface forms go with the last document using the term
+    model.retain_documents(|path| path == Path::new("c.md"));
+    let mut forms: Vec<&String> = model.surface_forms.values().collect();
+    forms.sort();
+    assert_eq!(forms, ["cases", "pending"]);
+}
+
+fn chunk(first_line: usize, content: &str) -> Chunk {
+    let (count, tf, positions) = Model::compute_search_data(&content.chars().collect::<Vec<_>>(), TokenizerConfig::default());
+    Chunk { first_line, count, tf, positions, heading_tf: Default::default() }
+}
+
+#[test]
+fn chunked_documents_collapse_to_their_best_chunk() {
+    let mut model = Model::default();
+    model.add_chunked_document(PathBuf::from("long.md"), SystemTime::now(), vec![
+        chunk(0, "alpha beta gamma delta"),
+        chunk(150, "delta epsilon zebra zebra"),
+        chunk(300, "epsilon zebra alpha beta gamma delta"),
+    ]);
+    add(&mut model, "short.md", "alpha beta");
+
+    assert_eq!(model.file_count(), 2);
+    let results = model.search_query_best_chunks(&"zebra".chars().collect::<Vec<_>>());
+    let long: Vec<_> = results.iter().filter(|(path, _, _)| path == Path::new("long.md")).collect();
+    assert_eq!(long.len(), 1);
+    assert_eq!(long[0].2, 150);
+    assert_eq!(results[0].0, Path::new("long.md"));
+    // A term in several chunks of a file counts as found in one file, not in several
+    assert_eq!((model.df["zebra"], model.df["alpha"]), (1, 2));
+    assert!(model.check().is_empty());
+
+    // Reindexing the file as a whole replaces all of its chunks
+    add(&mut model, "long.md", "alpha");
+    assert_eq!(model.docs.len(), 2);
+    assert!(!model.df.contains_key("zebra"));
+}
+
+#[test]
+fn indexed_files_count_the_tokens_of_all_chunks() {
+    let mut model = Model::default();
+    model.add_chunked_document(PathBuf::from("notes/long.md"), UNIX_EPOCH + Duration::from_secs(42), vec![
+        chunk(0, "alpha beta gamma"),
+        chunk(150, "delta epsilon"),
+    ]);
+    add(&mut model, "notes/short.md", "alpha beta");
+    add(&mut model, "code/main.rs", "fn main");
+
+    let files = model.indexed_files(|path| path.starts_with("notes"));
+    let listed: Vec<_> = files.iter().map(|file| (file.path, file.tokens)).collect();
+    assert_eq!(listed, [(Path::new("notes/long.md"), 5), (Path::new("notes/short.md"), 2)]);
+    assert_eq!(files[0].last_modified, 42);
+    assert_eq!(model.indexed_files(|_| true).len(), 3);
+}
+
+#[test]
+fn equal_scores_are_ordered_by_tie_break() {
+    let mut model = Model::default();
+    // Identical content gives identical ranks; b.md is the newest, c.md the oldest
+    for (path, age) in [("c.md", 30), ("a.md", 20), ("b.md", 10)] {
+        let content = "shared words here".chars().collect::<Vec<_>>();
+        model.add_document(PathBuf::from(path), UNIX_EPOCH + Duration::from_secs(1000 - age), &content);
+    }
+    add(&mut model, "other.md", "unrelated");
+
+    let order = |model: &Model| -> Vec<PathBuf> {
+        search(model, "shared").into_iter().take(3).map(|(path, _)| path).collect()
+    };
+    assert_eq!(order(&model), ["a.md", "b.md", "c.md"].map(PathBuf::from));
+
+    model.ranking.tie_break = TieBreak::Recency;
+    assert_eq!(order(&model), ["b.md", "a.md", "c.md"].map(PathBuf::from));
+}
+
+#[test]
+fn smoothed_idf_orders_ubiquitous_terms_by_frequency() {
+    let mut model = Model::default();
+    add(&mut model, "once.md", "act one two three");
+    add(&mut model, "twice.md", "act act two three");
+    add(&mut model, "thrice.md", "act act act three");
+
+    // "act" is in every document, so the classic idf gives it no weight at all
+    assert!(search(&model, "act").iter().all(|(_, rank)| *rank == 0.0));
+
+    model.ranking.idf = IdfFormula::Smoothed;
+    let results = search(&model, "act");
+    assert!(results.iter().all(|(_, rank)| *rank > 0.0));
+    let order: Vec<PathBuf> = results.into_iter().map(|(path, _)| path).collect();
+    assert_eq!(order, ["thrice.md", "twice.md", "once.md"].map(PathBuf::from));
+}
+
+#[test]
+fn tokenizer_config_is_saved_with_the_index() {
+    let unstemmed = TokenizerConfig { stem: false, ..TokenizerConfig::default() };
+    let mut model = Model::default();
+    assert!(model.request_tokenizer(unstemmed));
+    add(&mut model, "a.md", "penalties apply");
+    add(&mut model, "b.md", "nothing here");
+
+    let json = serde_json::to_string(&model).expect("model serializes");
+    let mut loaded: Model = serde_json::from_str(&json).expect("model deserializes");
+    assert_eq!(loaded.tokenizer, unstemmed);
+    // Queries are tokenized like the documents, so only the exact word matches
+    assert!(search(&loaded, "penalties")[0].1 > 0.0);
+    assert!(search(&loaded, "penalty").iter().all(|(_, rank)| *rank == 0.0));
+
+    // A populated index refuses a different tokenizer instead of silently mixing them
+    assert!(!loaded.request_tokenizer(TokenizerConfig::default()));
+    assert_eq!(loaded.tokenizer, unstemmed);
+
+    // Indexes saved before the settings existed were stemmed and kept tokens of any length
+    let old: Model = serde_json::from_str(r#"{"docs":{},"df":{}}"#).expect("old index deserializes");
+    assert_eq!(old.tokenizer, TokenizerConfig::legacy());
+    assert!(old.tokenizer.stem);
+    let old: Model = serde_json::from_str(r#"{"docs":{},"df":{},"tokenizer":{"stem":false}}"#).expect("old index deserializes");
+    assert_eq!(old.tokenizer, TokenizerConfig { stem: false, ..TokenizerConfig::legacy() });
+}
+
+#[test]
+fn tokens_outside_the_length_limits_are_dropped() {
+    let blob = "QmFzZTY0IGVuY29kZWQgZGF0YSB0aGF0IGdvZXMgb24gYW5kIG9u";
+    let content = format!("a x = minified({blob}); the penalty applies");
+    let (count, tf, positions) = Model::compute_search_data(&content.chars().collect::<Vec<_>>(), TokenizerConfig::default());
+    assert!(!tf.contains_key(&blob.to_lowercase()));
+    // Single letters and punctuation are gone too, from every statistic
+    for dropped in ["a", "x", "=", "(", ";"] {
+        assert!(!tf.contains_key(dropped) && !positions.contains_key(dropped), "{dropped:?} was kept");
+    }
+    assert_eq!(count, 4);
+    assert!(tf.contains_key("penalti"));
+
+    let (legacy_count, legacy_tf, _) = Model::compute_search_data(&content.chars().collect::<Vec<_>>(), TokenizerConfig::legacy());
+    assert!(legacy_tf.contains_key(&blob.to_lowercase()));
+    assert!(legacy_count > count);
+}
+
+#[test]
+fn split_identifiers_match_their_words() {
+    let split = TokenizerConfig { split_identifiers: true, ..TokenizerConfig::default() };
+    let mut model = Model::default();
+    assert!(model.request_tokenizer(split));
+    add(&mut model, "camel.rs", "fn getUserName() {}");
+    add(&mut model, "snake.py", "def get_user_name(): pass");
+    add(&mut model, "acronym.rs", "struct HTTPServerConfig;");
+    add(&mut model, "other.md", "nothing to see");
+
+    let matches = |query: &str| -> Vec<PathBuf> {
+        search(&model, query).into_iter().filter(|(_, rank)| *rank > 0.0).map(|(path, _)| path).collect()
+    };
+    let mut found = matches("user name");
+    found.sort();
+    assert_eq!(found, ["camel.rs", "snake.py"].map(PathBuf::from));
+    // The whole identifier is indexed too, and a query identifier is split the same way
+    assert_eq!(matches("getusername")[0], Path::new("camel.rs"));
+    assert_eq!(matches("get_user_name")[0], Path::new("snake.py"));
+    assert_eq!(matches("server config"), [PathBuf::from("acronym.rs")]);
+
+    let (_, tf, _) = Model::compute_search_data(&"getUserName".chars().collect::<Vec<_>>(), TokenizerConfig::default());
+    assert!(!tf.contains_key("user"));
+}
+
+#[test]
+fn markdown_headings_rank_higher_than_body_text() {
+    let mut model = Model::default();
+    add(&mut model, "heading.md", "# Penalty\nrules for every offence in the act");
+    add(&mut model, "body.md", "rules for every penalty in the act");
+    add(&mut model, "code.md", "```sh\n# penalty\n```\nrules for every offence in the act");
+    add(&mut model, "other.md", "nothing relevant");
+
+    let order = |model: &Model| -> Vec<PathBuf> {
+        search(model, "penalty").into_iter().take(3).map(|(path, _)| path).collect()
+    };
+    // A comment in a code block is body text, so it ranks like any other mention
+    assert_eq!(order(&model), ["heading.md", "body.md", "code.md"].map(PathBuf::from));
+
+    model.ranking.heading_boost = 0.0;
+    assert_eq!(order(&model)[0], Path::new("body.md"));
+}
+
+#[test]
+fn file_and_directory_names_match_queries() {
+    let mut model = Model::default();
+    add(&mut model, "project/src/main.rs", "fn run() { start(); }");
+    add(&mut model, "project/src/lib.rs", "pub fn start() { main(); }");
+    add(&mut model, "project/main/util.rs", "pub fn helper() {}");
+    add(&mut model, "project/docs/readme.md", "nothing relevant");
+
+    let paths = |model: &Model| -> Vec<PathBuf> {
+        search(model, "main").into_iter().filter(|(_, rank)| *rank > 0.0).map(|(path, _)| path).collect()
+    };
+    // The file name weighs more than a directory, which weighs more than a mention;
+    // `project` is shared by every file, so it doesn't count as a directory match
+    assert_eq!(paths(&model), ["project/src/main.rs", "project/main/util.rs", "project/src/lib.rs"].map(PathBuf::from));
+    assert!(search(&model, "project").iter().all(|(_, rank)| *rank == 0.0));
+
+    model.ranking.name_weight = 0.0;
+    model.ranking.directory_weight = 0.0;
+    assert_eq!(paths(&model), [PathBuf::from("project/src/lib.rs")]);
+}
+
+#[test]
+fn tag_filters_match_front_matter_tags() {
+    let mut model = Model::default();
+    add(&mut model, "soup.md", "lentil soup recipe");
+    add(&mut model, "stew.md", "lentil stew recipe");
+    model.set_tags(Path::new("soup.md"), vec!["recipes".to_string(), "winter".to_string()]);
+
+    let paths = |query: &str| -> Vec<PathBuf> { search(&model, query).into_iter().map(|(path, _)| path).collect() };
+    assert_eq!(paths("tag:Recipes lentil"), [PathBuf::from("soup.md")]);
+    assert_eq!(paths("tag:recipes tag:summer lentil"), [PathBuf::from("soup.md")]);
+    assert!(paths("tag:summer lentil").is_empty());
+}
+
+#[test]
+fn merged_search_normalizes_each_index() {
+    let mut notes = Model::default();
+    add(&mut notes, "notes/penalty.md", "penalty penalty");
+    add(&mut notes, "notes/mixed.md", "penalty and other words here");
+    add(&mut notes, "notes/other.md", "nothing relevant");
+    let mut code = Model::default();
+    add(&mut code, "code/penalty.rs", "fn penalty() {}");
+    add(&mut code, "code/lib.rs", "fn main() {}");
+
+    let results = search_merged(&[notes, code], &"penalty".chars().collect::<Vec<_>>());
+    let paths: Vec<&Path> = results.iter().map(|(path, _)| path.as_path()).collect();
+    // The best match of every index scores 1.0, whatever its raw tf-idf was
+    assert_eq!(paths, [Path::new("code/penalty.rs"), Path::new("notes/penalty.md"), Path::new("notes/mixed.md")]);
+    assert_eq!(results[0].1, 1.0);
+    assert_eq!(results[1].1, 1.0);
+    assert!(results[2].1 > 0.0 && results[2].1 < 1.0);
+}
+
+#[test]
+fn synonyms_expand_queries_with_less_weight() {
+    let mut model = Model::default();
+    add(&mut model, "literal.md", "notes on ml pipelines");
+    add(&mut model, "spelled.md", "notes on machine learning pipelines");
+    add(&mut model, "other.md", "notes on gardening");
+    add(&mut model, "filler.md", "nothing relevant");
+
+    assert_eq!(search(&model, "ml")[1].1, 0.0);
+
+    model.synonyms = Synonyms::parse("# comment\nml = machine learning\ncar, automobile\n");
+    let results = search(&model, "ml");
+    assert_eq!(results[0].0, Path::new("literal.md"));
+    assert_eq!(results[1].0, Path::new("spelled.md"));
+    assert!(results[1].1 > 0.0 && results[1].1 < results[0].1);
+    assert_eq!(results[2].1, 0.0);
+
+    // Groups work both ways, including from a phrase to a single word
+    let results = search(&model, "machine learning");
+    assert_eq!(results[0].0, Path::new("spelled.md"));
+    assert!(results[1].0 == Path::new("literal.md") && results[1].1 > 0.0);
+}
+
+#[test]
+fn renamed_documents_keep_their_statistics() {
+    let mut model = Model::default();
+    add(&mut model, "old.md", "quarterly penalty report");
+    add(&mut model, "other.md", "penalty");
+    model.set_content_hash(Path::new("old.md"), 42);
+    let df = model.df.clone();
+
+    let later = SystemTime::now() + Duration::from_secs(60);
+    assert!(model.rename_document(Path::new("old.md"), Path::new("new.md"), later));
+    assert_eq!(model.df, df);
+    assert_eq!(model.file_count(), 2);
+    assert_eq!(model.content_hash(Path::new("new.md")), Some(42));
+    assert!(!model.requires_reindexing(Path::new("new.md"), later));
+    assert_eq!(search(&model, "quarterly")[0].0, Path::new("new.md"));
+
+    assert!(!model.rename_document(Path::new("old.md"), Path::new("again.md"), later));
+}
+
+#[test]
+fn index_files_are_never_indexed() {
+    let dir = fixture_dir("index-files");
+    write_fixture(&dir, "notes.md", "penalty act");
+    write_fixture(&dir, ".finder.json", r#"{"docs": {"penalty": "act"}}"#);
+    write_fixture(&dir, ".finder.json.tmp", r#"{"docs": {}}"#);
+    write_fixture(&dir, ".finder.bin", "binary index");
+
+    // An index built before index files were skipped may contain one
+    let mut model = Model::default();
+    add(&mut model, &dir.join(".finder.json").to_string_lossy(), "docs penalty act");
+    let model = Arc::new(Mutex::new(model));
+    let options = IndexOptions { hidden: true, ..IndexOptions::default() };
+    add_folder_to_model(&dir, Arc::clone(&model), &options, &AtomicUsize::new(0)).expect("index folder");
+
+    let model = model.lock().unwrap();
+    let paths: Vec<&PathBuf> = model.docs.keys().collect();
+    assert_eq!(paths, [&dir.join("notes.md")]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn files_older_than_since_are_left_alone() {
+    let dir = fixture_dir("since");
+    write_fixture(&dir, "old.md", "ancient penalty");
+    write_fixture(&dir, "new.md", "recent penalty");
+    let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    fs::File::options().write(true).open(dir.join("old.md")).and_then(|file| file.set_modified(two_days_ago))
+        .expect("backdate old note");
+
+    let model = Arc::new(Mutex::new(Model::default()));
+    let options = IndexOptions::from_args(&["--since".to_string(), "1d".to_string()]).expect("valid --since");
+    add_folder_to_model(&dir, Arc::clone(&model), &options, &AtomicUsize::new(0)).expect("index folder");
+    let paths: Vec<PathBuf> = model.lock().unwrap().docs.keys().cloned().collect();
+    assert_eq!(paths, [dir.join("new.md")]);
+
+    // Files indexed before stay in the index even though they are skipped
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+    add_folder_to_model(&dir, Arc::clone(&model), &options, &AtomicUsize::new(0)).expect("index folder");
+    assert_eq!(model.lock().unwrap().file_count(), 2);
+
+    for valid in ["45s", "2w", "1700000000", "2024-05-01", "2024-05-01T12:30"] {
+        assert!(IndexOptions::from_args(&["--since".to_string(), valid.to_string()]).is_ok(), "{valid}");
+    }
+    let date = IndexOptions::from_args(&["--since".to_string(), "2024-02-29T12:00:30".to_string()]).unwrap();
+    assert_eq!(date.since, Some(UNIX_EPOCH + Duration::from_secs(1_709_208_030)));
+    for invalid in ["1y", "yesterday", "2024-13-01", "-5d"] {
+        assert!(IndexOptions::from_args(&["--since".to_string(), invalid.to_string()]).is_err(), "{invalid}");
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn searches_past_their_deadline_are_marked_truncated() {
+    let mut model = Model::default();
+    add(&mut model, "fox.md", "the quick brown fox");
+    add(&mut model, "dog.md", "the lazy dog");
+    let query = "fox".chars().collect::<Vec<_>>();
+
+    let (results, truncated) = model.search_query_until(&query, Instant::now() + Duration::from_secs(60));
+    assert!(!truncated);
+    assert_eq!(results, search(&model, "fox"));
+
+    let (results, truncated) = model.search_query_until(&query, Instant::now());
+    assert!(truncated);
+    assert!(results.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn files_changed_while_being_indexed_are_reindexed_next_run() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Prints the file it's given, then modifies it, like an editor saving while khoj reads
+    let script = write_fixture(&fixture_dir("touching-parser"), "touching-parser.sh", "#!/bin/sh\ncat \"$1\"\ntouch \"$1\"\n");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("make the parser executable");
+    std::env::set_var("KHOJ_PARSER_KHOJTOUCH", &script);
+    let dir = fixture_dir("changed-while-indexing");
+    let path = write_fixture(&dir, "notes.khojtouch", "penalty notes");
+    let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    fs::File::options().write(true).open(&path).and_then(|file| file.set_modified(two_days_ago))
+        .expect("backdate notes");
+
+    let model = Arc::new(Mutex::new(Model::default()));
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+    let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).expect("mtime of notes");
+    assert!(modified > two_days_ago);
+    assert!(model.lock().unwrap().docs.is_empty());
+    assert!(model.lock().unwrap().requires_reindexing(&path, modified));
+
+    std::env::set_var("KHOJ_PARSER_KHOJTOUCH", "cat");
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+    assert_eq!(model.lock().unwrap().docs.keys().collect::<Vec<_>>(), [&path]);
+    assert!(!model.lock().unwrap().requires_reindexing(&path, modified));
+}
+
+#[test]
+fn lockfiles_are_skipped_unless_default_excludes_are_off() {
+    let dir = fixture_dir("lockfiles");
+    write_fixture(&dir, "app/index.json", r#"{"name": "left-pad"}"#);
+    write_fixture(&dir, "app/package-lock.json", r#"{"left-pad": "1.3.0"}"#);
+    write_fixture(&dir, "app/generated.json", r#"{"left-pad": "1.3.0"}"#);
+
+    let model = Arc::new(Mutex::new(Model::default()));
+    let args = ["--exclude-file".to_string(), "generated.json".to_string()];
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::from_args(&args).unwrap(), &AtomicUsize::new(0))
+        .expect("index folder");
+    let paths: Vec<PathBuf> = model.lock().unwrap().docs.keys().cloned().collect();
+    assert_eq!(paths, [dir.join("app/index.json")]);
+
+    let options = IndexOptions::from_args(&["--no-default-excludes".to_string()]).unwrap();
+    add_folder_to_model(&dir, Arc::clone(&model), &options, &AtomicUsize::new(0)).expect("index folder");
+    assert_eq!(model.lock().unwrap().file_count(), 3);
+
+    // Reindexing with the defaults drops the lockfile again
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+    assert!(!model.lock().unwrap().docs.contains_key(&dir.join("app/package-lock.json")));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn near_duplicates_rank_lower_with_the_duplicate_penalty() {
+    let boilerplate: Vec<String> = (0..200).map(|i| format!("clause{i} licensed")).collect();
+    let boilerplate = boilerplate.join(" ");
+    let mut model = Model::default();
+    for i in 0..9 {
+        add(&mut model, &format!("src/module{i}.rs"), &format!("{boilerplate} module{i}"));
+    }
+    let substantive: Vec<String> = (0..200).map(|i| format!("detail{i} step{i} licensed")).collect();
+    add(&mut model, "notes.md", &substantive.join(" "));
+    add(&mut model, "unrelated.md", "nothing to see here");
+    model.update_uniqueness();
+
+    assert_ne!(search(&model, "licensed")[0].0, PathBuf::from("notes.md"));
+    model.ranking.penalize_duplicates = true;
+    let results = search(&model, "licensed");
+    assert_eq!(results[0].0, PathBuf::from("notes.md"));
+    // The duplicates still match, only lower
+    assert!(results[1..10].iter().all(|(path, rank)| path.starts_with("src") && *rank > 0.0));
+}
+
+#[test]
+fn dotted_numbers_are_searchable_as_written() {
+    let dotted = TokenizerConfig { dotted_numbers: true, ..TokenizerConfig::default() };
+    let mut model = Model::default();
+    assert!(model.request_tokenizer(dotted));
+    add(&mut model, "CHANGELOG.md", "## v1.2.3 - 2023-01-15\nFixed the crash on startup.");
+    add(&mut model, "older.md", "## 1.2.4\nReleased 3.2.1 on 2023-02-01.");
+    add(&mut model, "hosts.txt", "gateway 192.168.1.1 at the end of the sentence 10.0.0.1.");
+    add(&mut model, "other.md", "nothing to see");
+
+    let matches = |query: &str| -> Vec<PathBuf> {
+        search(&model, query).into_iter().filter(|(_, rank)| *rank > 0.0).map(|(path, _)| path).collect()
+    };
+    assert_eq!(matches("1.2.3"), [PathBuf::from("CHANGELOG.md")]);
+    assert_eq!(matches("v1.2.3"), [PathBuf::from("CHANGELOG.md")]);
+    assert_eq!(matches("2023-01-15")[0], Path::new("CHANGELOG.md"));
+    assert_eq!(matches("192.168.1.1"), [PathBuf::from("hosts.txt")]);
+    assert_eq!(matches("10.0.0.1"), [PathBuf::from("hosts.txt")]);
+    // The parts are still indexed on their own
+    let mut found = matches("2023");
+    found.sort();
+    assert_eq!(found, ["CHANGELOG.md", "older.md"].map(PathBuf::from));
+
+    let (_, tf, _) = Model::compute_search_data(&"v1.2.3".chars().collect::<Vec<_>>(), TokenizerConfig::default());
+    assert!(!tf.contains_key("1.2.3"));
+}
+
+#[test]
+fn check_finds_and_repairs_drifted_statistics() {
+    let mut model = Model::default();
+    add(&mut model, "fox.md", "the quick brown fox");
+    add(&mut model, "dog.md", "the lazy dog");
+    assert!(model.check().is_empty());
+
+    // Tamper with the stored statistics the way a buggy version could have
+    let mut json = serde_json::to_value(&model).expect("model serializes");
+    json["docs"]["fox.md"]["count"] = 7.into();
+    json["docs"]["dog.md"]["positions"]["lazy"] = serde_json::json!([1, 9]);
+    json["docs"]["empty.md"] = json["docs"]["dog.md"].clone();
+    json["docs"]["empty.md"]["tf"] = serde_json::json!({});
+    json["df"]["ghost"] = 2.into();
+    json["df"]["the"] = 1.into();
+    let mut model: Model = serde_json::from_value(json).expect("model deserializes");
+
+    assert_eq!(model.check(), [
+        IndexProblem::Positions { key: PathBuf::from("dog.md") },
+        IndexProblem::Empty { key: PathBuf::from("empty.md") },
+        IndexProblem::Count { key: PathBuf::from("fox.md"), stored: 7, actual: 4 },
+        IndexProblem::DocFreq { term: "ghost".to_string(), stored: 2, actual: 0 },
+        IndexProblem::DocFreq { term: "the".to_string(), stored: 1, actual: 2 },
+    ]);
+
+    model.repair();
+    assert!(model.check().is_empty());
+    assert_eq!(model.file_count(), 2);
+    assert_eq!(search(&model, "lazy dog")[0].0, PathBuf::from("dog.md"));
+}
+
+#[test]
+fn progressive_search_reports_the_best_results_so_far() {
+    let mut model = Model::default();
+    for i in 0..600 {
+        add(&mut model, &format!("doc{i:03}.md"), &format!("penalty {} filler text", "act ".repeat(i % 7)));
+    }
+    let query = "penalty act".chars().collect::<Vec<_>>();
+
+    let mut reports = Vec::new();
+    let (results, truncated) = model.search_query_progressive(&query, None, Duration::ZERO, 5, |top, ranked| {
+        reports.push((top, ranked));
+    });
+    assert!(!truncated);
+    assert_eq!(results, search(&model, "penalty act"));
+    // Without an interval there is a report every few hundred documents
+    assert!(reports.len() >= 2);
+    for (top, ranked) in &reports {
+        assert!(top.len() <= 5.min(*ranked));
+        assert!(top.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+}
+
+#[test]
+fn match_count_sums_query_word_occurrences() {
+    let mut model = Model::default();
+    add(&mut model, "fines.md", "penalty act: the penalty is a fine, and a second penalty doubles it");
+    add(&mut model, "other.md", "nothing to see");
+
+    let query = |text: &str| text.chars().collect::<Vec<_>>();
+    assert_eq!(model.match_count(Path::new("fines.md"), &query("penalty act")), 4);
+    // Repeating a word in the query doesn't count its occurrences again
+    assert_eq!(model.match_count(Path::new("fines.md"), &query("penalty penalty ext:md")), 3);
+    assert_eq!(model.match_count(Path::new("other.md"), &query("penalty")), 0);
+    assert_eq!(model.match_count(Path::new("missing.md"), &query("penalty")), 0);
+}
+
+#[test]
+fn recency_search_lists_every_matching_file_newest_first() {
+    let mut model = Model::default();
+    let now = SystemTime::now();
+    let add_at = |model: &mut Model, path: &str, age_secs: u64, content: &str| {
+        let content = content.chars().collect::<Vec<_>>();
+        model.add_document(PathBuf::from(path), now - Duration::from_secs(age_secs), &content);
+    };
+    add_at(&mut model, "old.md", 300, "penalty penalty penalty clause");
+    add_at(&mut model, "new.md", 100, "penalty once");
+    add_at(&mut model, "middle.txt", 200, "penalty report");
+
+    // The word is in every file, so it ranks 0 everywhere but still matches
+    let query = "penalty".chars().collect::<Vec<_>>();
+    let paths = |results: Vec<(PathBuf, f32)>| results.into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+    assert_eq!(paths(model.search_query_by_recency(&query)), ["new.md", "middle.txt", "old.md"].map(PathBuf::from));
+
+    let query = "clause ext:md".chars().collect::<Vec<_>>();
+    assert_eq!(paths(model.search_query_by_recency(&query)), [PathBuf::from("old.md")]);
+    let query = "report ext:md".chars().collect::<Vec<_>>();
+    assert!(model.search_query_by_recency(&query).is_empty());
+}
+
+#[test]
+fn unchanged_directories_are_not_read_again() {
+    let dir = fixture_dir("dir-listings");
+    write_fixture(&dir, "notes/a.md", "penalty act");
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+    let backdate = |path: &Path| {
+        fs::File::open(path).and_then(|file| file.set_modified(an_hour_ago)).expect("backdate directory");
+    };
+    backdate(&dir.join("notes"));
+    backdate(&dir);
+
+    let model = Arc::new(Mutex::new(Model::default()));
+    let index = || add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+    index();
+    assert_eq!(model.lock().unwrap().dirs.len(), 2);
+
+    // Writing to a file doesn't change its directory, but the file itself is still checked
+    write_fixture(&dir, "notes/a.md", "quarterly report");
+    fs::File::options().write(true).open(dir.join("notes/a.md"))
+        .and_then(|file| file.set_modified(SystemTime::now() + Duration::from_secs(60)))
+        .expect("bump modification time");
+    index();
+    assert_eq!(search(&model.lock().unwrap(), "quarterly")[0].0, dir.join("notes/a.md"));
+
+    // A file added without the directory's time changing is missed, since the directory isn't read
+    write_fixture(&dir, "notes/b.md", "penalty clause");
+    backdate(&dir.join("notes"));
+    index();
+    assert_eq!(model.lock().unwrap().file_count(), 1);
+
+    // Once the directory changes it is read again
+    write_fixture(&dir, "notes/c.md", "penalty clause");
+    index();
+    assert_eq!(model.lock().unwrap().file_count(), 3);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn boosts_can_be_turned_off_for_plain_tf_idf() {
+    let mut model = Model::default();
+    add(&mut model, "phrase.md", "penalty act filler filler");
+    add(&mut model, "repeated.md", "penalty penalty penalty penalty");
+    add(&mut model, "other.md", "act other other other");
+    add(&mut model, "unrelated.md", "other");
+
+    let order = |model: &Model| search(model, "penalty act").into_iter().take(2).map(|(path, _)| path).collect::<Vec<_>>();
+    assert_eq!(order(&model), ["phrase.md", "repeated.md"].map(PathBuf::from));
+
+    // Without the coverage and phrase boosts the higher term frequency wins
+    model.ranking.boosts = false;
+    assert_eq!(order(&model), ["repeated.md", "phrase.md"].map(PathBuf::from));
+}
+
+#[test]
+fn position_decay_ranks_early_mentions_higher() {
+    let mut model = Model::default();
+    add(&mut model, "intro.md", "kernel notes about other things here");
+    add(&mut model, "appendix.md", "notes about other things here kernel");
+    add(&mut model, "unrelated.md", "notes");
+
+    // Without decay both mention the word once among as many words
+    let ranks = search(&model, "kernel");
+    assert_eq!(ranks[0].1, ranks[1].1);
+
+    model.ranking.position_decay = 1.0;
+    let ranks = search(&model, "kernel");
+    assert_eq!(ranks[0].0, PathBuf::from("intro.md"));
+    assert!(ranks[0].1 > ranks[1].1);
+}
+
+#[test]
+fn matching_lines_are_found_from_word_positions() {
+    let text = "Running the tests\nnothing here\nwe ran a test run\nretest\n";
+    let mut model = Model::default();
+    add(&mut model, "notes.txt", text);
+    let query = "test".chars().collect::<Vec<_>>();
+
+    // Stemmed whole words only: `retest` is another word
+    assert_eq!(model.matching_lines(Path::new("notes.txt"), &query, text), [0, 2]);
+
+    // Positions that no longer hold the word after an edit are skipped
+    let edited = "Running the checks\nnothing here\nwe ran a test run\nretest\n";
+    assert_eq!(model.matching_lines(Path::new("notes.txt"), &query, edited), [2]);
+    assert!(model.matching_lines(Path::new("missing.txt"), &query, text).is_empty());
+}
+
+#[test]
+fn matching_lines_in_front_matter_keep_their_line_numbers() {
+    let dir = fixture_dir("front-matter-lines");
+    let text = "---\ntitle: Penalty notes\ntags: [law]\n---\nintro line\nthe penalties apply here\n";
+    let path = write_fixture(&dir, "notes.md", text);
+    let model = Arc::new(Mutex::new(Model::default()));
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::default(), &AtomicUsize::new(0)).expect("index folder");
+
+    // The front matter is indexed as its bare values, yet lines are numbered as in the file
+    let query = "penalty".chars().collect::<Vec<_>>();
+    assert_eq!(matching_lines(&model.lock().unwrap(), &path, &query, text), [
+        (2, "title: Penalty notes".to_string()),
+        (6, "the penalties apply here".to_string()),
+    ]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn source_code_can_be_left_unstemmed_while_prose_is_stemmed() {
+    let mut model = Model::default();
+    assert!(model.request_tokenizer(TokenizerConfig { stem_code: false, ..TokenizerConfig::default() }));
+    add(&mut model, "runner.rs", "fn running() -> bool");
+    add(&mut model, "notes.md", "the tests are running");
+    add(&mut model, "other.md", "nothing here");
+
+    let matching = |query: &str| {
+        let mut paths: Vec<PathBuf> = search(&model, query).into_iter().filter(|(_, rank)| *rank > 0.0).map(|(path, _)| path).collect();
+        paths.sort();
+        paths
+    };
+    // Prose still matches other forms of a word; code only the identifier as written
+    assert_eq!(matching("running"), ["notes.md", "runner.rs"].map(PathBuf::from));
+    assert_eq!(matching("runs"), [PathBuf::from("notes.md")]);
+    assert_eq!(model.did_you_mean("running"), None);
+}
+
+#[test]
+fn logs_are_filtered_by_the_times_of_their_lines() {
+    // 2024-01-10 08:00, 2024-01-10 09:00 and 2024-02-03 10:00 UTC
+    let (jan_8, jan_9, feb_3) = (1_704_873_600, 1_704_877_200, 1_706_954_400);
+    let mut model = Model::default();
+    add(&mut model, "jan.log", "start\nerror disk full");
+    add(&mut model, "feb.log", "error timeout\nstack\nok");
+    add(&mut model, "notes.md", "error notes");
+    model.set_time_ranges(Path::new("jan.log"), &[Some(jan_8), Some(jan_9)], usize::MAX);
+    model.set_time_ranges(Path::new("feb.log"), &[Some(feb_3), None, None], usize::MAX);
+    assert_eq!(model.time_range(Path::new("jan.log")), Some((jan_8, jan_9)));
+
+    let matching = |query: &str| {
+        let mut paths: Vec<PathBuf> = search(&model, query).into_iter().map(|(path, _)| path).collect();
+        paths.sort();
+        paths
+    };
+    assert_eq!(matching("error since:2024-02-01"), [PathBuf::from("feb.log")]);
+    // A date alone stands for its whole day
+    assert_eq!(matching("error until:2024-01-10"), [PathBuf::from("jan.log")]);
+    assert_eq!(matching("error until:2024-01-09"), Vec::<PathBuf>::new());
+    assert_eq!(matching("error since:2024-01-10T08:30 until:2024-02-03"), ["feb.log", "jan.log"].map(PathBuf::from));
+}
+
+#[test]
+fn binary_index_loads_as_it_was_saved() {
+    let dir = fixture_dir("binary-index");
+    let long: String = (0..40).map(|i| format!("line {i} mentions penalty{i}\n")).collect();
+    write_fixture(&dir, "notes/long.md", &long);
+    write_fixture(&dir, "notes/short.md", "---\ntags: [law]\n---\nthe penalties apply to getUserName");
+    write_fixture(&dir, "app.log", "2024-01-10T08:00:00Z error disk full\n2024-01-10T09:00:00Z recovered\n");
+
+    let mut model = Model::default();
+    assert!(model.request_tokenizer(TokenizerConfig { split_identifiers: true, ..TokenizerConfig::default() }));
+    let model = Arc::new(Mutex::new(model));
+    let args = ["--chunk-lines", "10", "--logs", "--penalize-duplicates"].map(String::from);
+    add_folder_to_model(&dir, Arc::clone(&model), &IndexOptions::from_args(&args).unwrap(), &AtomicUsize::new(0))
+        .expect("index folder");
+    let model = Arc::try_unwrap(model).ok().unwrap().into_inner().unwrap();
+
+    let index_path = dir.join(".finder.bin");
+    save_model(&model, &index_path).expect("save binary index");
+    let loaded = load_model(&index_path).expect("load binary index");
+    // Everything saved comes back, chunks, tags and log times included
+    assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&model).unwrap());
+    assert!(loaded.docs.len() > loaded.file_count());
+    assert_eq!(loaded.tags(&dir.join("notes/short.md")), ["law"]);
+    assert!(loaded.time_range(&dir.join("app.log")).is_some());
+    assert_eq!(search(&loaded, "user penalties"), search(&model, "user penalties"));
+    let _ = fs::remove_dir_all(&dir);
+}
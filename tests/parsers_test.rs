@@ -0,0 +1,304 @@
+mod common;
+
+use common::fixture_dir;
+use khoj::archive::{member_path, parse_member_path, split_member_path};
+use khoj::front_matter;
+use khoj::{is_log_file, log_line_time, parse_entire_file_by_extension, parse_entire_file_with_timeout};
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+fn write_fixture(name: &str, file_name: &str, content: &[u8]) -> PathBuf {
+    let path = fixture_dir(name).join(file_name);
+    fs::write(&path, content).expect("write fixture file");
+    path
+}
+
+/// Builds a single-page PDF that draws `text` with a standard font, with a correct xref table.
+fn minimal_pdf(text: &str) -> Vec<u8> {
+    let stream = format!("BT /F1 24 Tf 72 720 Td ({text}) Tj ET");
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>".to_string(),
+        format!("<< /Length {} >>\nstream\n{stream}\nendstream", stream.len()),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{object}\nendobj\n", i + 1).as_bytes());
+    }
+
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n", objects.len() + 1).as_bytes());
+    pdf
+}
+
+/// Builds a zip archive, such as an `.xlsx` workbook, from `(entry name, content)` pairs.
+fn workbook(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, xml) in entries {
+        zip.start_file(*name, zip::write::FileOptions::default()).expect("start workbook entry");
+        zip.write_all(xml.as_bytes()).expect("write workbook entry");
+    }
+    zip.finish().expect("finish workbook").into_inner()
+}
+
+#[test]
+fn parses_plain_text() {
+    let path = write_fixture("txt", "notes.txt", b"The quick brown fox\njumps over the lazy dog\n");
+    let content = parse_entire_file_by_extension(&path).expect("text file parses");
+    assert!(content.contains("quick brown fox"));
+    assert!(content.contains("lazy dog"));
+}
+
+#[test]
+fn parses_source_files_as_text() {
+    let path = write_fixture("rs", "main.rs", b"fn main() { println!(\"hello\"); }\n");
+    let content = parse_entire_file_by_extension(&path).expect("source file parses");
+    assert!(content.contains("fn main()"));
+}
+
+#[test]
+fn extension_dispatch_is_case_insensitive() {
+    let path = write_fixture("upper", "README.MD", b"# Title\nbody text\n");
+    let content = parse_entire_file_by_extension(&path).expect("upper-case extension parses");
+    assert!(content.contains("body text"));
+}
+
+#[test]
+fn parses_xml_character_data() {
+    let xml = br#"<?xml version="1.0"?>
+<doc><title>Penalty Act</title><section id="1">Every offence is punishable</section></doc>"#;
+    let path = write_fixture("xml", "act.xml", xml);
+    let content = parse_entire_file_by_extension(&path).expect("xml file parses");
+    assert!(content.contains("Penalty Act"));
+    assert!(content.contains("Every offence is punishable"));
+    // Markup and attribute values are not part of the extracted text
+    assert!(!content.contains("section"));
+    assert!(!content.contains("id"));
+}
+
+#[test]
+fn rejects_malformed_xml() {
+    let path = write_fixture("bad-xml", "broken.xml", b"<doc><title>unclosed</doc>");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn parses_pdf_text() {
+    let path = write_fixture("pdf", "hello.pdf", &minimal_pdf("Hello PDF world"));
+    let content = parse_entire_file_by_extension(&path).expect("pdf file parses");
+    assert!(content.contains("Hello"));
+    assert!(content.contains("world"));
+}
+
+#[test]
+fn rejects_malformed_pdf() {
+    let path = write_fixture("bad-pdf", "broken.pdf", b"this is not a pdf");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn parses_xlsx_cells_from_all_sheets() {
+    let shared_strings = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" count="3" uniqueCount="3">
+<si><t>Quarterly revenue</t></si><si><t>Penalty</t></si><si><r><t>rich </t></r><r><t>text</t></r></si>
+</sst>"#;
+    let sheet1 = r#"<?xml version="1.0" encoding="UTF-8"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>
+<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1"><v>1234.5</v></c></row>
+<row r="2"><c r="A2" t="inlineStr"><is><t>inline note</t></is></c><c r="B2"><f>B1*2</f><v>2469</v></c></row>
+</sheetData></worksheet>"#;
+    let sheet2 = r#"<?xml version="1.0" encoding="UTF-8"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>
+<row r="1"><c r="A1" t="s"><v>1</v></c><c r="B1" t="s"><v>2</v></c></row>
+</sheetData></worksheet>"#;
+    let xlsx = workbook(&[
+        ("xl/sharedStrings.xml", shared_strings),
+        ("xl/worksheets/sheet1.xml", sheet1),
+        ("xl/worksheets/sheet2.xml", sheet2),
+    ]);
+    let path = write_fixture("xlsx", "report.xlsx", &xlsx);
+    let content = parse_entire_file_by_extension(&path).expect("xlsx file parses");
+    // First sheet: shared string, number, inline string and a formula's cached result
+    assert!(content.contains("Quarterly revenue"));
+    assert!(content.contains("1234.5"));
+    assert!(content.contains("inline note"));
+    assert!(content.contains("2469"));
+    assert!(!content.contains("B1*2"));
+    // Second sheet, including a string made of rich text runs
+    assert!(content.contains("Penalty"));
+    assert!(content.contains("rich text"));
+}
+
+#[test]
+fn rejects_malformed_xlsx() {
+    let path = write_fixture("bad-xlsx", "broken.xlsx", b"PK this is not a zip archive");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+
+    let no_sheets = workbook(&[("docProps/app.xml", "<Properties/>")]);
+    let path = write_fixture("empty-xlsx", "empty.xlsx", &no_sheets);
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn parses_zip_archive_members() {
+    let zip = workbook(&[
+        ("docs/readme.md", "# Readme\nthe aardvark sleeps here\n"),
+        ("docs/act.xml", "<doc><title>Penalty Act</title></doc>"),
+    ]);
+    let archive = write_fixture("zip", "notes.zip", &zip);
+
+    let readme = member_path(&archive, "docs/readme.md");
+    assert_eq!(split_member_path(&readme), Some((archive.as_path(), "docs/readme.md")));
+    assert!(parse_member_path(&readme).expect("markdown member parses").contains("aardvark"));
+    // Formats with their own parser go through the extension dispatch too
+    let act = parse_member_path(&member_path(&archive, "docs/act.xml")).expect("xml member parses");
+    assert!(act.contains("Penalty Act"));
+    assert!(!act.contains("title"));
+
+    assert!(parse_member_path(&member_path(&archive, "docs/absent.md")).is_err());
+    assert_eq!(split_member_path(&archive), None);
+}
+
+#[test]
+fn rejects_unsupported_extension() {
+    let path = write_fixture("unsupported", "image.png", b"\x89PNG\r\n");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn rejects_file_without_extension() {
+    let path = write_fixture("no-ext", "Makefile", b"all:\n\techo hi\n");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn rejects_missing_file() {
+    let path = fixture_dir("missing").join("absent.txt");
+    assert_eq!(parse_entire_file_by_extension(&path), Err(()));
+}
+
+#[test]
+fn parses_latex_prose_without_commands_or_math() {
+    let tex = br"\documentclass{article}
+\usepackage{amsmath}
+\begin{document}
+\section{Gradient Descent}
+We \textbf{minimise} the loss % TODO: cite the survey
+as shown by \cite{ruder2016overview}, at rate $\alpha = 0.01$.
+\begin{equation}
+  \theta_{t+1} = \theta_t - \nabla J
+\end{equation}
+It costs 5\% per step.
+\end{document}
+";
+    let path = write_fixture("tex", "paper.tex", tex);
+    let content = parse_entire_file_by_extension(&path).expect("tex file parses");
+    assert!(content.contains("Gradient Descent"));
+    assert!(content.contains("We minimise the loss"));
+    assert!(content.contains("5% per step"));
+    for markup in ["textbf", "section", "documentclass", "amsmath", "ruder2016overview", "TODO", "alpha", "theta", "nabla", "equation"] {
+        assert!(!content.contains(markup), "{markup} should not be extracted");
+    }
+    // Line numbers still match the source
+    assert_eq!(content.lines().count(), 11);
+    assert!(content.lines().nth(9).unwrap().contains("per step"));
+}
+
+#[test]
+fn parses_markdown_front_matter() {
+    let note = "---\ntitle: \"Lentil Soup\"\ntags: [Recipes, winter]\naliases:\n  - soup\n---\n# Ingredients\nred lentils\n";
+    let parsed = front_matter::parse(note).expect("front matter parses");
+    assert_eq!(parsed.tags(), ["recipes", "winter"]);
+    assert_eq!(parsed.weighted_values().collect::<Vec<_>>(), ["Lentil Soup", "Recipes", "winter"]);
+    // Keys and delimiters are gone, values stay on their lines
+    assert_eq!(parsed.text, "\nLentil Soup\nRecipes winter\n\nsoup\n\n# Ingredients\nred lentils\n");
+
+    assert!(front_matter::parse("# No front matter\n---\n").is_none());
+    assert!(front_matter::parse("---\ntitle: never closed\n").is_none());
+}
+
+#[test]
+fn reads_the_time_at_the_start_of_log_lines() {
+    // 2024-01-15 10:23:45 UTC
+    let time = 1_705_314_225;
+    assert_eq!(log_line_time("2024-01-15T10:23:45Z GET /index.html"), Some(time));
+    assert_eq!(log_line_time("2024-01-15 10:23:45,123 INFO started"), Some(time));
+    assert_eq!(log_line_time("[2024-01-15 10:23:45] worker ready"), Some(time));
+    assert_eq!(log_line_time("  2024-01-15T10:23 shorter"), Some(time - 45));
+    assert_eq!(log_line_time("2024-01-15 no time of day"), Some(time - (10 * 60 + 23) * 60 - 45));
+    for line in ["", "at 2024-01-15 10:23:45", "15/01/2024 10:23:45", "2024-1-15 10:23:45", "2024-13-45 10:23:45"] {
+        assert_eq!(log_line_time(line), None, "{line:?}");
+    }
+}
+
+#[test]
+fn recognizes_plain_and_gzipped_logs() {
+    for log in ["app.log", "APP.LOG", "app.log.gz", "app.LOG.GZ"] {
+        assert!(is_log_file(Path::new(log)), "{log}");
+    }
+    for other in ["app.gz", "app.tar.gz", "log", "app.log.txt", "app.logs"] {
+        assert!(!is_log_file(Path::new(other)), "{other}");
+    }
+}
+
+#[test]
+fn parses_plain_and_gzipped_logs() {
+    let text = "2024-01-15T10:23:45Z error disk full\n2024-01-15T10:24:00Z recovered\n";
+    let plain = write_fixture("plain-log", "app.log", text.as_bytes());
+    assert_eq!(parse_entire_file_by_extension(&plain).unwrap(), text);
+
+    let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzipped.write_all(text.as_bytes()).unwrap();
+    let gzipped = write_fixture("gzipped-log", "app.log.gz", &gzipped.finish().unwrap());
+    assert_eq!(parse_entire_file_by_extension(&gzipped).unwrap(), text);
+
+    // Bytes that aren't UTF-8 don't fail the whole log, and other `.gz` files aren't logs
+    let mangled = write_fixture("mangled-log", "app.log", b"ok\xff line\n");
+    assert_eq!(parse_entire_file_by_extension(&mangled).unwrap(), "ok\u{fffd} line\n");
+    let archive = write_fixture("not-a-log", "data.gz", b"whatever");
+    assert!(parse_entire_file_by_extension(&archive).is_err());
+}
+
+#[test]
+fn external_parsers_extract_what_their_command_prints() {
+    std::env::set_var("KHOJ_PARSER_KHOJCAT", "cat");
+    let path = write_fixture("external-parser", "notes.khojcat", b"penalty notes\n");
+    assert_eq!(parse_entire_file_by_extension(&path).unwrap(), "penalty notes\n");
+    assert_eq!(parse_entire_file_with_timeout(&path, Some(Duration::from_secs(10))).unwrap(), "penalty notes\n");
+
+    // Extensions khoj parses itself never go to an external parser
+    std::env::set_var("KHOJ_PARSER_MD", "false");
+    let markdown = write_fixture("external-parser-md", "notes.md", b"# Notes\n");
+    assert_eq!(parse_entire_file_by_extension(&markdown).unwrap(), "# Notes\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn external_parsers_that_take_too_long_are_killed() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    // Records its process id next to the file it's given, then hangs
+    let script = write_fixture("slow-parser", "slow-parser.sh", b"#!/bin/sh\necho $$ > \"$1.pid\"\nexec sleep 30\n");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("make the parser executable");
+    std::env::set_var("KHOJ_PARSER_KHOJSLOW", &script);
+    let path = write_fixture("slow-parser-input", "notes.khojslow", b"penalty notes\n");
+
+    let start = Instant::now();
+    assert!(parse_entire_file_with_timeout(&path, Some(Duration::from_millis(500))).is_err());
+    assert!(start.elapsed() < Duration::from_secs(10));
+    let pid = fs::read_to_string(path.with_extension("khojslow.pid")).expect("the parser started");
+    let alive = Command::new("kill").args(["-0", pid.trim()]).status().expect("run kill");
+    assert!(!alive.success(), "the parser is still running");
+}
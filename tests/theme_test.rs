@@ -0,0 +1,39 @@
+mod common;
+
+use common::fixture_dir;
+use khoj::theme::Theme;
+use ratatui::style::Color;
+use std::fs;
+use std::path::PathBuf;
+
+fn write_theme(name: &str, content: &str) -> PathBuf {
+    let path = fixture_dir(name).join("theme.toml");
+    fs::write(&path, content).expect("write theme file");
+    path
+}
+
+#[test]
+fn themes_override_only_the_colors_they_set() {
+    let path = write_theme("partial", "accent = \"#f5c2e7\"\nborder = \"dark gray\"\nbackground = \"236\"\n");
+    let theme = Theme::load(&path).expect("valid theme");
+    let default = Theme::default();
+
+    assert_eq!(theme.accent, Color::Rgb(0xf5, 0xc2, 0xe7));
+    assert_eq!(theme.border, Color::DarkGray);
+    assert_eq!(theme.background, Color::Indexed(236));
+    assert_eq!(theme.foreground, default.foreground);
+    assert_eq!(theme.match_highlight, default.match_highlight);
+    assert_eq!(theme.border_highlight, default.border_highlight);
+}
+
+#[test]
+fn bad_colors_and_unknown_keys_are_errors() {
+    let err = Theme::load(&write_theme("bad-color", "accent = \"not a color\"\n")).err().expect("invalid color");
+    assert!(err.contains("accent") && err.contains("not a color"), "{err}");
+
+    let err = Theme::load(&write_theme("unknown-key", "accent_color = \"red\"\n")).err().expect("unknown key");
+    assert!(err.contains("accent_color"), "{err}");
+
+    let missing = fixture_dir("missing").join("theme.toml");
+    assert!(Theme::load(&missing).err().expect("missing file").contains("could not read"));
+}
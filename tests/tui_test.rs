@@ -1,3 +1,6 @@
+mod common;
+
+use common::fixture_dir;
 use rexpect::session::PtySession;
 use rexpect::spawn;
 use std::error::Error;
@@ -38,3 +41,26 @@ fn test_tui() -> Result<(), Box<dyn Error>> {
     p.send_control('c')?;
     Ok(())
 }
+
+#[test]
+fn accented_previews_are_truncated_without_panicking() -> Result<(), Box<dyn Error>> {
+    let dir = fixture_dir("accented");
+    // Byte 77 of the line, where long previews used to be cut, falls inside an `é`
+    let line = format!("penalty {}{}", "a".repeat(68), "é".repeat(20));
+    std::fs::write(dir.join("accents.txt"), &line)?;
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_khoj"));
+    command.current_dir(&dir);
+    let mut p = rexpect::session::spawn_command(command, Some(10000))?;
+    p.exp_string("Search")?;
+    p.send("penalty")?;
+    p.flush()?;
+    // The row is drawn only if building its preview didn't panic
+    p.exp_string("accents.txt")?;
+    // Raw mode turns Ctrl+C into an ordinary key; Esc is what quits
+    p.send("\x1b")?;
+    p.flush()?;
+    assert!(!clean_output(&p.exp_eof()?).contains("panicked"));
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(())
+}